@@ -0,0 +1,486 @@
+use std::path::Path;
+
+use async_zip::tokio::read::seek::ZipFileReader;
+use serde_json::Value;
+use tokio::fs::File;
+use tokio::io::BufReader;
+
+use crate::types::error::Error;
+
+use super::encryption::{decrypt_stream, get_snapshot_key, ENCRYPTED_SUFFIX};
+
+pub const METADATA_FILE: &str = "metadata.json";
+pub const FILE_INDEX: &str = "files.json";
+pub const WORLD_DATA_SUFFIX: &str = "_data.zip";
+
+/// Layout version written into the metadata of every snapshot. Snapshots
+/// without one were written before entries were looked up by name and are
+/// treated as version 1. Version 3 snapshots may hold encrypted entries.
+pub const FORMAT_VERSION: u32 = 3;
+
+pub type SnapshotReader = ZipFileReader<BufReader<File>>;
+
+/// How much of the end of an archive holds its end of central directory
+/// record, which may be followed by a comment of up to 64 KiB.
+pub const ZIP_TAIL_SIZE: u64 = END_OF_CENTRAL_DIRECTORY_SIZE as u64 + u16::MAX as u64;
+pub const LOCAL_HEADER_SIZE: u64 = 30;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const END_OF_CENTRAL_DIRECTORY_SIZE: usize = 22;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const CENTRAL_DIRECTORY_HEADER_SIZE: usize = 46;
+
+pub async fn open_snapshot(backup_path: &Path) -> Result<SnapshotReader, Error> {
+    let file = File::open(backup_path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => {
+            Error::SnapshotNotFound(format!("Backup {} does not exist", backup_path.display()))
+        }
+        _ => Error::io(
+            format!("Failed to open backup file {}", backup_path.display()),
+            e,
+        ),
+    })?;
+
+    ZipFileReader::with_tokio(BufReader::new(file))
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!(
+                "Failed to open backup file {}: {:?}",
+                backup_path.display(),
+                e
+            ))
+        })
+}
+
+/// Returns the index of the first entry whose name matches, regardless of
+/// where in the archive it was written.
+pub fn find_entry<F: Fn(&str) -> bool>(zip: &SnapshotReader, matches: F) -> Option<usize> {
+    zip.file()
+        .entries()
+        .iter()
+        .position(|entry| entry.filename().as_str().is_ok_and(&matches))
+}
+
+pub fn find_named_entry(zip: &SnapshotReader, name: &str) -> Option<usize> {
+    find_entry(zip, |entry| entry == name)
+}
+
+/// The nested world data zip is named after the world id, so it is found by
+/// its suffix. Also returns whether the entry is encrypted.
+pub fn find_world_data_entry(zip: &SnapshotReader) -> Option<(usize, bool)> {
+    let encrypted_suffix = format!("{}{}", WORLD_DATA_SUFFIX, ENCRYPTED_SUFFIX);
+
+    if let Some(index) = find_entry(zip, |entry| entry.ends_with(WORLD_DATA_SUFFIX)) {
+        return Some((index, false));
+    }
+
+    find_entry(zip, |entry| entry.ends_with(&encrypted_suffix)).map(|index| (index, true))
+}
+
+pub async fn read_entry_to_string(
+    zip: &mut SnapshotReader,
+    index: usize,
+    backup_path: &Path,
+) -> Result<String, Error> {
+    let mut reader = zip.reader_with_entry(index).await.map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to open entry {} in backup {}: {:?}",
+            index,
+            backup_path.display(),
+            e
+        ))
+    })?;
+
+    let mut contents = String::new();
+
+    reader
+        .read_to_string_checked(&mut contents)
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!(
+                "Failed to read entry {} in backup {}: {:?}",
+                index,
+                backup_path.display(),
+                e
+            ))
+        })?;
+
+    Ok(contents)
+}
+
+/// Reads the format version out of raw snapshot metadata, rejecting
+/// snapshots written by a newer version of teller before their metadata is
+/// parsed any further.
+pub fn check_format_version(metadata: &Value, backup_path: &Path) -> Result<u32, Error> {
+    let version = match metadata.get("format_version") {
+        Some(version) => match version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+        {
+            Some(version) => version,
+            None => {
+                return Err(Error::SnapshotFormat(format!(
+                    "Backup {} has an invalid format version: {}",
+                    backup_path.display(),
+                    version
+                )))
+            }
+        },
+        None => 1,
+    };
+
+    if version > FORMAT_VERSION {
+        return Err(Error::SnapshotFormat(format!(
+            "Backup {} uses format version {}, but this version of ChunkVault only supports up to version {}. Please update ChunkVault to open it.",
+            backup_path.display(),
+            version,
+            FORMAT_VERSION
+        )));
+    }
+
+    Ok(version)
+}
+
+/// Reads a small named entry, falling back to its encrypted copy when the
+/// snapshot only holds that one.
+pub async fn read_named_entry(
+    zip: &mut SnapshotReader,
+    name: &str,
+    backup_path: &Path,
+) -> Result<Option<String>, Error> {
+    if let Some(index) = find_named_entry(zip, name) {
+        return read_entry_to_string(zip, index, backup_path)
+            .await
+            .map(Some);
+    }
+
+    let encrypted_name = format!("{}{}", name, ENCRYPTED_SUFFIX);
+
+    let index = match find_named_entry(zip, &encrypted_name) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    let key = get_snapshot_key(backup_path)?;
+
+    let mut reader = zip.reader_with_entry(index).await.map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to open {} in backup {}: {:?}",
+            encrypted_name,
+            backup_path.display(),
+            e
+        ))
+    })?;
+
+    let mut contents = Vec::new();
+
+    decrypt_stream(&key, &mut reader, &mut contents)
+        .await
+        .map_err(|e| {
+            Error::Encryption(format!(
+                "Failed to decrypt {} in backup {}: {}",
+                encrypted_name,
+                backup_path.display(),
+                e
+            ))
+        })?;
+
+    String::from_utf8(contents).map(Some).map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to read {} in backup {}: {:?}",
+            encrypted_name,
+            backup_path.display(),
+            e
+        ))
+    })
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    bytes
+        .get(at..at + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Finds the offset and size of the central directory in the last
+/// [`ZIP_TAIL_SIZE`] bytes of an archive. Zip64 archives are not supported.
+pub(crate) fn find_central_directory(tail: &[u8]) -> Option<(u64, u64)> {
+    let last = tail.len().checked_sub(END_OF_CENTRAL_DIRECTORY_SIZE)?;
+
+    let record = (0..=last)
+        .rev()
+        .find(|&at| read_u32(tail, at) == Some(END_OF_CENTRAL_DIRECTORY))?;
+
+    let size = read_u32(tail, record + 12)?;
+    let offset = read_u32(tail, record + 16)?;
+
+    if size == u32::MAX || offset == u32::MAX {
+        return None;
+    }
+
+    Some((offset as u64, size as u64))
+}
+
+/// Looks up an uncompressed entry in a central directory, and returns the
+/// offset of its local header and its size.
+pub(crate) fn find_stored_entry(central_directory: &[u8], name: &str) -> Option<(u64, u64)> {
+    let mut at = 0;
+
+    while read_u32(central_directory, at) == Some(CENTRAL_DIRECTORY_HEADER) {
+        let compression = read_u16(central_directory, at + 10)?;
+        let size = read_u32(central_directory, at + 20)?;
+        let name_length = read_u16(central_directory, at + 28)? as usize;
+        let extra_length = read_u16(central_directory, at + 30)? as usize;
+        let comment_length = read_u16(central_directory, at + 32)? as usize;
+        let header_offset = read_u32(central_directory, at + 42)?;
+
+        let name_start = at + CENTRAL_DIRECTORY_HEADER_SIZE;
+        let entry_name = central_directory.get(name_start..name_start + name_length)?;
+
+        if entry_name == name.as_bytes() {
+            if compression != 0 || size == u32::MAX || header_offset == u32::MAX {
+                return None;
+            }
+
+            return Some((header_offset as u64, size as u64));
+        }
+
+        at = name_start + name_length + extra_length + comment_length;
+    }
+
+    None
+}
+
+/// Length of a local file header including its name and extra field, which
+/// is where the entry's data starts.
+pub(crate) fn local_header_length(header: &[u8]) -> Option<u64> {
+    let name_length = read_u16(header, 26)? as u64;
+    let extra_length = read_u16(header, 28)? as u64;
+
+    Some(LOCAL_HEADER_SIZE + name_length + extra_length)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use serde_json::json;
+    use zip::write::FileOptions;
+    use zip::CompressionMethod;
+
+    use super::*;
+
+    fn build_archive(entries: &[(&str, &[u8], CompressionMethod)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, data, method) in entries {
+            let options = FileOptions::default().compression_method(*method);
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn sample_archive() -> Vec<u8> {
+        build_archive(&[
+            ("world_data.zip", b"region data", CompressionMethod::Stored),
+            (
+                METADATA_FILE,
+                br#"{"format_version":3}"#,
+                CompressionMethod::Stored,
+            ),
+            ("files.json", &[b'a'; 512], CompressionMethod::Deflated),
+        ])
+    }
+
+    fn central_directory(archive: &[u8]) -> &[u8] {
+        let (offset, size) = find_central_directory(archive).unwrap();
+        &archive[offset as usize..(offset + size) as usize]
+    }
+
+    #[test]
+    fn finds_central_directory_of_archive() {
+        let archive = sample_archive();
+        let (offset, size) = find_central_directory(&archive).unwrap();
+
+        assert!(offset > 0);
+        assert_eq!(
+            read_u32(&archive, offset as usize),
+            Some(CENTRAL_DIRECTORY_HEADER)
+        );
+        assert!(offset + size <= archive.len() as u64);
+    }
+
+    #[test]
+    fn finds_central_directory_before_comment() {
+        let mut archive = sample_archive();
+        let expected = find_central_directory(&archive);
+
+        // Set the comment length and append the comment.
+        let record = archive.len() - END_OF_CENTRAL_DIRECTORY_SIZE;
+        archive[record + 20..record + 22].copy_from_slice(&5u16.to_le_bytes());
+        archive.extend_from_slice(b"hello");
+
+        assert_eq!(find_central_directory(&archive), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_tails() {
+        let mut zip64 = vec![0; END_OF_CENTRAL_DIRECTORY_SIZE];
+        zip64[..4].copy_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        zip64[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut cut_record = END_OF_CENTRAL_DIRECTORY.to_le_bytes().to_vec();
+        cut_record.extend_from_slice(&[0; 10]);
+
+        let cases: Vec<(&str, Vec<u8>)> = vec![
+            ("empty", Vec::new()),
+            ("too short", vec![0; 4]),
+            ("no record", vec![0xAB; 1024]),
+            ("cut off record", cut_record),
+            ("zip64", zip64),
+        ];
+
+        for (name, tail) in cases {
+            assert_eq!(find_central_directory(&tail), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn truncated_archives_do_not_panic() {
+        let archive = sample_archive();
+
+        for end in 0..archive.len() {
+            find_central_directory(&archive[..end]);
+            find_central_directory(&archive[end..]);
+        }
+    }
+
+    #[test]
+    fn finds_stored_entries() {
+        let archive = sample_archive();
+        let central_directory = central_directory(&archive);
+
+        let (header_offset, size) = find_stored_entry(central_directory, METADATA_FILE).unwrap();
+
+        let header = &archive[header_offset as usize..];
+        let data_start = header_offset + local_header_length(header).unwrap();
+        let data = &archive[data_start as usize..(data_start + size) as usize];
+
+        assert_eq!(data, br#"{"format_version":3}"#);
+    }
+
+    #[test]
+    fn skips_unusable_entries() {
+        let archive = sample_archive();
+        let central_directory = central_directory(&archive);
+
+        let cases = [
+            ("compressed", "files.json"),
+            ("missing", "level.dat"),
+            ("prefix of a name", "world"),
+            ("empty name", ""),
+        ];
+
+        for (case, name) in cases {
+            assert_eq!(find_stored_entry(central_directory, name), None, "{}", case);
+        }
+    }
+
+    #[test]
+    fn truncated_central_directories_do_not_panic() {
+        let archive = sample_archive();
+        let central_directory = central_directory(&archive).to_vec();
+
+        for end in 0..central_directory.len() {
+            find_stored_entry(&central_directory[..end], METADATA_FILE);
+        }
+
+        // A name length that points past the end of the directory.
+        let mut corrupted = central_directory.clone();
+        corrupted[28..30].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(find_stored_entry(&corrupted, METADATA_FILE), None);
+
+        assert_eq!(find_stored_entry(&[0xFF; 64], METADATA_FILE), None);
+    }
+
+    #[test]
+    fn reads_local_header_length() {
+        let mut header = vec![0; LOCAL_HEADER_SIZE as usize];
+        header[26..28].copy_from_slice(&13u16.to_le_bytes());
+        header[28..30].copy_from_slice(&4u16.to_le_bytes());
+
+        assert_eq!(local_header_length(&header), Some(LOCAL_HEADER_SIZE + 17));
+        assert_eq!(local_header_length(&header[..27]), None);
+        assert_eq!(local_header_length(&[]), None);
+    }
+
+    #[test]
+    fn checks_format_version() {
+        let path = Path::new("backup.chunkvault-snapshot");
+
+        let cases = [
+            (json!({}), Some(1)),
+            (json!({ "format_version": 1 }), Some(1)),
+            (
+                json!({ "format_version": FORMAT_VERSION }),
+                Some(FORMAT_VERSION),
+            ),
+            (json!({ "format_version": FORMAT_VERSION + 1 }), None),
+            (json!({ "format_version": "3" }), None),
+            (json!({ "format_version": -1 }), None),
+            (json!({ "format_version": u64::from(u32::MAX) + 1 }), None),
+        ];
+
+        for (metadata, expected) in cases {
+            let version = check_format_version(&metadata, path).ok();
+            assert_eq!(version, expected, "{}", metadata);
+        }
+    }
+
+    async fn write_temp_archive(data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("teller-test-{}.zip", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, data).await.unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn reads_named_entries() {
+        let path = write_temp_archive(&sample_archive()).await;
+
+        let mut zip = open_snapshot(&path).await.unwrap();
+
+        let metadata = read_named_entry(&mut zip, METADATA_FILE, &path).await;
+        let missing = read_named_entry(&mut zip, "level.dat", &path).await;
+        let world_data = find_world_data_entry(&zip);
+
+        let _ = tokio::fs::remove_file(&path).await;
+
+        assert_eq!(
+            metadata.unwrap().as_deref(),
+            Some(r#"{"format_version":3}"#)
+        );
+        assert_eq!(missing.unwrap(), None);
+        assert_eq!(world_data, Some((0, false)));
+    }
+
+    #[tokio::test]
+    async fn rejects_files_that_are_not_archives() {
+        let path = write_temp_archive(b"not a zip archive").await;
+
+        let opened = open_snapshot(&path).await;
+
+        let _ = tokio::fs::remove_file(&path).await;
+
+        assert!(matches!(opened, Err(Error::SnapshotFormat(_))));
+
+        let missing = open_snapshot(Path::new("/nonexistent/backup.zip")).await;
+        assert!(matches!(missing, Err(Error::SnapshotNotFound(_))));
+    }
+}
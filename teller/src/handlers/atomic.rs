@@ -0,0 +1,90 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::error;
+use tokio::fs::{self, File, OpenOptions};
+
+/// Files in a vault are written under this suffix and only renamed to their
+/// final name once complete, so a crash never leaves a truncated snapshot or
+/// object under a name that is read back.
+pub const PARTIAL_SUFFIX: &str = ".partial";
+
+/// Where a file is written before it is committed to `destination`. It lives
+/// in the same folder, as a rename is only atomic within one file system.
+pub(crate) fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_default();
+    name.push(PARTIAL_SUFFIX);
+
+    destination.with_file_name(name)
+}
+
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir).await?.sync_all().await
+}
+
+/// Windows has no way to flush a directory, renames are journaled by NTFS.
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Flushes a finished file to disk and moves it to `destination`, then
+/// flushes the folder so the rename itself survives a crash.
+pub(crate) async fn commit_file(path: &Path, destination: &Path) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await?
+        .sync_all()
+        .await?;
+
+    fs::rename(path, destination).await?;
+
+    match destination.parent() {
+        Some(parent) => sync_dir(parent).await,
+        None => Ok(()),
+    }
+}
+
+/// Removes what is left of a partial file after a failed write.
+pub(crate) async fn discard_partial(path: &Path) {
+    if let Err(e) = fs::remove_file(path).await {
+        if e.kind() != io::ErrorKind::NotFound {
+            error!("Failed to remove partial file {}: {}", path.display(), e);
+        }
+    }
+}
+
+pub(crate) async fn write_atomic(destination: &Path, data: &[u8]) -> io::Result<()> {
+    let partial = partial_path(destination);
+
+    let written = match fs::write(&partial, data).await {
+        Ok(_) => commit_file(&partial, destination).await,
+        Err(e) => Err(e),
+    };
+
+    if written.is_err() {
+        discard_partial(&partial).await;
+    }
+
+    written
+}
+
+pub(crate) async fn copy_atomic(source: &Path, destination: &Path) -> io::Result<()> {
+    let partial = partial_path(destination);
+
+    let copied = match fs::copy(source, &partial).await {
+        Ok(_) => commit_file(&partial, destination).await,
+        Err(e) => Err(e),
+    };
+
+    if copied.is_err() {
+        discard_partial(&partial).await;
+    }
+
+    copied
+}
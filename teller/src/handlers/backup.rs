@@ -1,30 +1,61 @@
 use log::{error, info};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::path::Path;
 use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 
 use async_recursion::async_recursion;
+use async_zip::base::read::{WithEntry, ZipEntryReader};
 use async_zip::error::ZipError;
 use async_zip::tokio::read::seek::ZipFileReader;
 use async_zip::tokio::write::ZipFileWriter;
 use async_zip::{Compression, ZipEntryBuilder};
+use futures_util::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use sha2::{Digest, Sha256};
 use tokio::fs::File;
+use tokio_util::compat::TokioAsyncWriteCompatExt;
 
 use serde_json::json;
-use tokio::io::AsyncReadExt;
 use tokio::io::AsyncSeek;
 use tokio::io::AsyncWrite;
 
 use crate::handlers::config::backup::get_backup_config;
 use crate::handlers::search::worlds::get_world_path_by_id;
-use crate::types::backup::BackupMetadata;
-
+use crate::types::backup::{
+    BackupMetadata, FileIndex, FileIndexEntry, InUsePolicy, VaultStorage, VaultVerifyReport,
+    VerifyReport,
+};
+use crate::types::error::Error;
+use crate::types::progress::ProgressStage;
+use crate::utils::calculate_dir_size;
+
+use super::archive::{
+    check_format_version, find_world_data_entry, open_snapshot, read_named_entry, FILE_INDEX,
+    FORMAT_VERSION, METADATA_FILE,
+};
+use super::atomic::{commit_file, copy_atomic};
+use super::catalog::{forget_snapshots, forget_world, record_snapshot};
 use super::config::get_config_folder;
+use super::encryption::{
+    decrypt_stream, encrypt_stream, get_snapshot_key, get_vault_key, VaultKey, ENCRYPTED_SUFFIX,
+};
+use super::lock::check_world_in_use;
+use super::progress::Progress;
+use super::queue::{enqueue_upload, staged_snapshot_path};
 use super::search::worlds::is_minecraft_world;
+use super::store::{
+    collect_garbage, extract_store_snapshot, find_unreferenced_objects, is_safe_relative_path,
+    read_snapshot_manifest, store_world_snapshot, verify_store_snapshot, BUFFER_SIZE, OBJECTS_DIR,
+};
+use super::vault::{
+    fetch_snapshot, find_newest_snapshot, open_vault, read_snapshot_metadata, snapshot_file_name,
+    vault_backend, LocalSnapshot, VaultBackend, SNAPSHOT_SUFFIX,
+};
 use super::world::{parse_world_entry_data, process_world_data};
 
-async fn get_default_vault() -> PathBuf {
+pub(crate) async fn get_default_vault() -> PathBuf {
     let config_dir = get_config_folder();
 
     let vault_dir = config_dir.join("vault");
@@ -38,16 +69,31 @@ async fn get_default_vault() -> PathBuf {
     }
 }
 
-pub async fn create_world_backup(world_path: PathBuf) -> Result<PathBuf, String> {
-    let default_vault = get_default_vault().await;
+pub(crate) async fn get_temp_dir() -> PathBuf {
+    let temp_dir = get_default_vault().await.join("temp");
 
-    let temp_dir = match default_vault.join("temp").exists() {
-        true => default_vault.join("temp"),
-        false => {
-            let _ = tokio::fs::create_dir_all(default_vault.join("temp")).await;
-            default_vault.join("temp")
-        }
-    };
+    if !temp_dir.exists() {
+        let _ = tokio::fs::create_dir_all(&temp_dir).await;
+    }
+
+    temp_dir
+}
+
+/// Archives a world into the temp folder of the default vault. When `parent`
+/// is given, files whose size and modification time match the parent's file
+/// index are left out and the snapshot records the parent's id instead.
+/// `best_effort` marks snapshots taken while the game had the world open.
+/// Returns the file name the snapshot goes by in vaults and where it was
+/// written, under a name of its own so concurrent backups never share files.
+pub async fn create_world_backup(
+    world_path: PathBuf,
+    parent: Option<(String, FileIndex)>,
+    best_effort: bool,
+    progress: &Progress,
+) -> Result<(String, PathBuf), Error> {
+    let temp_dir = get_temp_dir().await;
+
+    let world_size = calculate_dir_size(world_path.clone(), progress).await?;
 
     let mut world_entry_data = parse_world_entry_data(world_path.clone()).await?;
 
@@ -58,53 +104,127 @@ pub async fn create_world_backup(world_path: PathBuf) -> Result<PathBuf, String>
     let world_data = match process_world_data(&world_path, game_type).await {
         Ok(data) => data,
         Err(e) => {
-            return Err(format!("Could not process world data: {:?}", e));
+            return Err(Error::Nbt(format!("Could not process world data: {:?}", e)));
         }
     };
 
+    let (parent_id, previous_files) = match parent {
+        Some((parent_id, parent_index)) => {
+            let previous_files: HashMap<String, FileIndexEntry> = parent_index
+                .files
+                .into_iter()
+                .map(|file| (file.path.clone(), file))
+                .collect();
+
+            (Some(parent_id), previous_files)
+        }
+        None => (None, HashMap::new()),
+    };
+
     let metadata = json!({
         "entry": world_entry_data,
         "data": world_data,
+        "parent": parent_id,
+        "format_version": FORMAT_VERSION,
+        "best_effort": best_effort,
     });
 
-    info!("Creating backup for world {}", world_entry_data.id);
+    match &parent_id {
+        Some(parent_id) => info!(
+            "Creating incremental backup for world {} against {}",
+            world_entry_data.id, parent_id
+        ),
+        None => info!("Creating backup for world {}", world_entry_data.id),
+    }
+
+    let backup_name = snapshot_file_name(&chrono::Utc::now().timestamp().to_string());
+
+    let run_id = uuid::Uuid::new_v4();
+    let backup_path = temp_dir.join(format!("{}-{}", run_id, backup_name));
+    let world_zip_path = temp_dir.join(format!("{}_data.zip", run_id));
+
+    progress.start(ProgressStage::Archiving, world_size);
+
+    let written = write_world_snapshot(
+        &world_entry_data.id,
+        &backup_path,
+        &world_zip_path,
+        &metadata,
+        &world_path,
+        &previous_files,
+        progress,
+    )
+    .await;
+
+    // The snapshot is only kept when every part of it was written, so a failed
+    // or cancelled backup never leaves a partial snapshot to be copied.
+    if let Err(e) = tokio::fs::remove_file(&world_zip_path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!("Failed to remove {}: {}", world_zip_path.display(), e);
+        }
+    }
+
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&backup_path).await;
+        return Err(e);
+    }
 
-    let backup_id = format!("{}.chunkvault-snapshot", chrono::Utc::now().timestamp());
-    let backup_path = temp_dir.join(backup_id);
+    Ok((backup_name, backup_path))
+}
 
-    let mut zip = ZipFileWriter::with_tokio(File::create(backup_path.clone()).await.unwrap());
+async fn write_world_snapshot(
+    world_id: &str,
+    backup_path: &Path,
+    world_zip_path: &Path,
+    metadata: &serde_json::Value,
+    world_path: &Path,
+    previous_files: &HashMap<String, FileIndexEntry>,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let backup_file = File::create(backup_path)
+        .await
+        .map_err(|e| Error::io(format!("Failed to create {}", backup_path.display()), e))?;
+    let mut zip = ZipFileWriter::with_tokio(backup_file);
 
-    let meta_builder = ZipEntryBuilder::new("metadata.json".into(), Compression::Stored);
+    let meta_builder = ZipEntryBuilder::new(METADATA_FILE.into(), Compression::Stored);
 
     zip.write_entry_whole(meta_builder, metadata.to_string().as_bytes())
-        .await
-        .unwrap();
+        .await?;
 
-    let world_zip_path = temp_dir.join(format!("{}_data.zip", world_entry_data.id));
-    let mut world_zip = ZipFileWriter::with_tokio(File::create(&world_zip_path).await.unwrap());
-    add_directory_to_zip(&mut world_zip, &world_path, &world_path)
+    let world_zip_file = File::create(world_zip_path)
         .await
-        .unwrap();
-    world_zip.close().await.unwrap();
+        .map_err(|e| Error::io(format!("Failed to create {}", world_zip_path.display()), e))?;
+    let mut world_zip = ZipFileWriter::with_tokio(world_zip_file);
+    let mut file_index = FileIndex::default();
 
-    let mut world_zip_file = File::open(&world_zip_path).await.unwrap();
-    let mut buffer = Vec::new();
-    world_zip_file.read_to_end(&mut buffer).await.unwrap();
+    add_directory_to_zip(
+        &mut world_zip,
+        world_path,
+        world_path,
+        previous_files,
+        &mut file_index,
+        progress,
+    )
+    .await?;
 
-    let world_zip_builder = ZipEntryBuilder::new(
-        format!("{}_data.zip", world_entry_data.id).into(),
-        Compression::Stored,
-    );
+    world_zip.close().await?;
 
-    zip.write_entry_whole(world_zip_builder, &buffer)
-        .await
-        .unwrap();
+    let world_zip_builder =
+        ZipEntryBuilder::new(format!("{}_data.zip", world_id).into(), Compression::Stored);
+
+    write_file_to_zip(&mut zip, world_zip_builder, world_zip_path).await?;
+
+    let index_builder = ZipEntryBuilder::new(FILE_INDEX.into(), Compression::Deflate);
+
+    let file_index = serde_json::to_string(&file_index)
+        .map_err(|e| Error::SnapshotFormat(format!("Failed to serialize file index: {:?}", e)))?;
 
-    zip.close().await.unwrap();
+    zip.write_entry_whole(index_builder, file_index.as_bytes())
+        .await?;
 
-    tokio::fs::remove_file(&world_zip_path).await.unwrap();
+    zip.close().await?;
 
-    Ok(backup_path)
+    Ok(())
 }
 
 #[async_recursion]
@@ -112,113 +232,304 @@ async fn add_directory_to_zip<W: AsyncWrite + AsyncSeek + Unpin + Send>(
     zip_writer: &mut ZipFileWriter<W>,
     directory: &Path,
     prefix: &Path,
-) -> Result<(), ZipError> {
+    previous_files: &HashMap<String, FileIndexEntry>,
+    file_index: &mut FileIndex,
+    progress: &Progress,
+) -> Result<(), Error> {
     let mut entries = tokio::fs::read_dir(directory).await?;
 
-    while let Ok(entry) = entries.next_entry().await {
-        let entry = match entry {
-            Some(entry) => entry,
-            None => {
-                break;
-            }
-        };
+    while let Some(entry) = entries.next_entry().await? {
         let path = entry.path();
-        let name = path.strip_prefix(Path::new(prefix)).unwrap();
+        progress.check_cancelled()?;
+
         if path.is_file() {
-            let builder = ZipEntryBuilder::new(name.to_str().unwrap().into(), Compression::Zstd)
-                .unix_permissions(0o755);
+            let name = match path
+                .strip_prefix(prefix)
+                .ok()
+                .and_then(|name| name.to_str())
+            {
+                Some(name) => name,
+                None => {
+                    return Err(Error::World(format!(
+                        "Cannot back up {}, its name is not valid UTF-8",
+                        path.display()
+                    )))
+                }
+            };
 
-            let mut f = File::open(&path).await?;
-            let mut buffer = Vec::new();
-            f.read_to_end(&mut buffer).await?;
+            let metadata = entry.metadata().await?;
+            let size = metadata.len();
+
+            progress.advance(size);
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|duration| duration.as_millis() as i64)
+                .unwrap_or_default();
+
+            if let Some(previous) = previous_files.get(name) {
+                if modified != 0 && previous.size == size && previous.modified == modified {
+                    file_index.files.push(previous.clone());
+                    continue;
+                }
+            }
 
-            zip_writer
-                .write_entry_whole(builder, &buffer)
-                .await
-                .unwrap();
+            let builder =
+                ZipEntryBuilder::new(name.into(), Compression::Zstd).unix_permissions(0o755);
+
+            let (hash, size) = write_file_to_zip(zip_writer, builder, &path).await?;
+
+            file_index.files.push(FileIndexEntry {
+                path: name.to_string(),
+                size,
+                modified,
+                hash,
+            });
         } else if path.is_dir() {
-            add_directory_to_zip(zip_writer, &path, prefix).await?;
+            add_directory_to_zip(
+                zip_writer,
+                &path,
+                prefix,
+                previous_files,
+                file_index,
+                progress,
+            )
+            .await?;
         }
     }
     Ok(())
 }
 
+/// Streams a file from disk into a new zip entry in fixed-size chunks so that
+/// memory use does not grow with the size of the file. Returns the SHA-256 and
+/// size of what was written.
+async fn write_file_to_zip<W: AsyncWrite + AsyncSeek + Unpin + Send>(
+    zip_writer: &mut ZipFileWriter<W>,
+    builder: ZipEntryBuilder,
+    path: &Path,
+) -> Result<(String, u64), ZipError> {
+    let mut file = File::open(path).await?;
+
+    let mut entry_writer = zip_writer.write_entry_stream(builder).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        entry_writer.write_all(&buffer[..read]).await?;
+        size += read as u64;
+    }
+
+    entry_writer.close().await?;
+
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Copies a snapshot into an encrypted vault, encrypting every entry. The
+/// metadata is also kept readable when the vault allows it, so the vault can
+/// still be listed while locked.
+async fn write_encrypted_snapshot(
+    backup_path: &Path,
+    destination: &Path,
+    key: &VaultKey,
+    plaintext_metadata: bool,
+) -> Result<(), Error> {
+    let mut source = open_snapshot(backup_path).await?;
+
+    let file = File::create(destination)
+        .await
+        .map_err(|e| Error::io(format!("Failed to create {}", destination.display()), e))?;
+
+    let mut zip = ZipFileWriter::with_tokio(file);
+
+    let mut index = 0;
+    while let Ok(mut reader) = source.reader_with_entry(index).await {
+        index += 1;
+
+        let name = reader
+            .entry()
+            .filename()
+            .as_str()
+            .map_err(|e| {
+                Error::SnapshotFormat(format!(
+                    "Invalid entry name in {}: {:?}",
+                    backup_path.display(),
+                    e
+                ))
+            })?
+            .to_string();
+
+        let encrypted_builder = ZipEntryBuilder::new(
+            format!("{}{}", name, ENCRYPTED_SUFFIX).into(),
+            Compression::Stored,
+        );
+
+        let mut entry_writer = zip
+            .write_entry_stream(encrypted_builder)
+            .await
+            .map_err(|e| Error::SnapshotFormat(format!("Failed to write {}: {:?}", name, e)))?;
+
+        if name == METADATA_FILE && plaintext_metadata {
+            let mut metadata = Vec::new();
+            reader
+                .read_to_end_checked(&mut metadata)
+                .await
+                .map_err(|e| Error::SnapshotFormat(format!("Failed to read {}: {:?}", name, e)))?;
+
+            encrypt_stream(key, &mut metadata.as_slice(), &mut entry_writer).await?;
+            entry_writer
+                .close()
+                .await
+                .map_err(|e| Error::SnapshotFormat(format!("Failed to write {}: {:?}", name, e)))?;
+
+            let meta_builder = ZipEntryBuilder::new(METADATA_FILE.into(), Compression::Stored);
+            zip.write_entry_whole(meta_builder, &metadata)
+                .await
+                .map_err(|e| Error::SnapshotFormat(format!("Failed to write {}: {:?}", name, e)))?;
+
+            continue;
+        }
+
+        encrypt_stream(key, &mut reader, &mut entry_writer).await?;
+        entry_writer
+            .close()
+            .await
+            .map_err(|e| Error::SnapshotFormat(format!("Failed to write {}: {:?}", name, e)))?;
+    }
+
+    zip.close().await.map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to finish {}: {:?}",
+            destination.display(),
+            e
+        ))
+    })?;
+
+    Ok(())
+}
+
+/// Picks the newest snapshot in the first vault as the parent of an
+/// incremental backup. The parent has to exist in every target vault, since
+/// the new snapshot cannot be restored anywhere its parent is missing, and the
+/// vaults have to be on disk, so restoring does not download the whole chain.
+async fn find_incremental_parent(
+    vaults: &[Box<dyn VaultBackend>],
+    world_id: &str,
+) -> Option<(String, FileIndex)> {
+    let base_vault = vaults.first()?;
+
+    if vaults.iter().any(|vault| vault.local_path().is_none()) {
+        info!("Not every vault is on disk, creating a full backup");
+        return None;
+    }
+
+    let snapshot_ids = base_vault.list_snapshots(world_id).await.ok()?;
+    let snapshot_id = find_newest_snapshot(&snapshot_ids)?.to_string();
+
+    for vault in vaults {
+        if vault.stat_snapshot(world_id, &snapshot_id).await.is_err() {
+            info!("Newest snapshot is not in every vault, creating a full backup");
+            return None;
+        }
+    }
+
+    let snapshot = fetch_snapshot(base_vault.as_ref(), world_id, &snapshot_id)
+        .await
+        .ok()?;
+
+    let file_index = match read_file_index(&snapshot.path).await {
+        Ok(Some(file_index)) => file_index,
+        _ => {
+            info!("Newest snapshot has no file index, creating a full backup");
+            return None;
+        }
+    };
+
+    Some((snapshot_id, file_index))
+}
+
 pub async fn create_backup_from_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
     vaults: Option<Vec<String>>,
-) -> Result<String, String> {
+    incremental: bool,
+    in_use: Option<InUsePolicy>,
+    progress: &Progress,
+) -> Result<String, Error> {
     info!("Creating backup for world id: {}", world_id);
     match get_world_path_by_id(world_id, category, instance).await {
         Ok(world_path) => {
-            let world_backup_path = match create_world_backup(world_path.clone()).await {
-                Ok(backup_path) => backup_path,
-                Err(e) => {
-                    error!(
-                        "Failed to create backup for world folder {}: {:?}",
-                        world_path.display(),
-                        e
-                    );
-                    return Err(format!(
-                        "Failed to create backup for world folder {}: {:?}",
-                        world_path.display(),
-                        e
-                    ));
+            let in_use = match in_use {
+                Some(in_use) => in_use,
+                None => get_backup_config().await?.in_use,
+            };
+
+            let best_effort = check_world_in_use(&world_path, in_use, progress).await?;
+
+            let parent = match (&vaults, incremental) {
+                (Some(vault_ids), true) => {
+                    let backup_settings = get_backup_config().await?;
+
+                    // Deduplicated vaults are stored from the snapshot itself,
+                    // which then has to hold every file of the world.
+                    let deduplicated = vault_ids.iter().any(|vault_id| {
+                        backup_settings.vault_storage(vault_id) == VaultStorage::Deduplicated
+                    });
+
+                    let backup_vaults: Vec<Box<dyn VaultBackend>> = vault_ids
+                        .iter()
+                        .filter(|vault_id| {
+                            backup_settings.vault_storage(vault_id) == VaultStorage::Full
+                        })
+                        .filter_map(|vault_id| vault_backend(&backup_settings, vault_id).ok())
+                        .collect();
+
+                    match deduplicated {
+                        true => {
+                            info!("Storing in a deduplicated vault, creating a full backup");
+                            None
+                        }
+                        false => find_incremental_parent(&backup_vaults, world_id).await,
+                    }
                 }
+                _ => None,
             };
 
-            let backup_name = match world_backup_path.file_name() {
-                Some(name) => name,
-                None => {
+            let (backup_name, world_backup_path) = match create_world_backup(
+                world_path.clone(),
+                parent,
+                best_effort,
+                progress,
+            )
+            .await
+            {
+                Ok(backup) => backup,
+                Err(e) => {
                     error!(
-                        "Could not get backup name from path: {:?}",
-                        world_backup_path
+                        "Failed to create backup for world folder {}: {}",
+                        world_path.display(),
+                        e
                     );
-                    return Err(format!(
-                        "Could not get backup name from path: {:?}",
-                        world_backup_path
-                    ));
+                    return Err(e);
                 }
             };
 
-            if vaults.is_some() {
-                let mut vault_locations = HashMap::new();
-
-                let backup_settings = get_backup_config().await?;
-
-                for vault_id in vaults.unwrap() {
-                    if let Some(vault) = backup_settings.vaults.get(&vault_id) {
-                        vault_locations.insert(vault_id, vault);
-                    }
-                }
-
-                info!("Copying backup to all {} vaults", vault_locations.len());
-
-                for (vault_id, vault_path) in vault_locations {
-                    let backup_location = vault_path.join(world_id);
-                    if !backup_location.exists() {
-                        match tokio::fs::create_dir_all(&backup_location).await {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!("Failed to create vault folder {}: {:?}", vault_id, e);
-                                continue;
-                            }
-                        };
-                    }
-                    match tokio::fs::copy(&world_backup_path, backup_location.join(backup_name))
-                        .await
-                    {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!(
-                                "Failed to move backup to vault folder {}: {:?}",
-                                vault_id, e
-                            );
-                            continue;
-                        }
-                    };
-                }
+            if let Some(vaults) = vaults {
+                let copied = copy_backup_to_vaults(
+                    world_id,
+                    &world_backup_path,
+                    OsStr::new(&backup_name),
+                    vaults,
+                    progress,
+                )
+                .await;
 
                 if let Err(e) = tokio::fs::remove_file(&world_backup_path).await {
                     error!(
@@ -226,15 +537,30 @@ pub async fn create_backup_from_id(
                         world_backup_path.display(),
                         e
                     );
-                    return Err(format!(
-                        "Failed to remove backup file {}: {:?}",
-                        world_backup_path.display(),
-                        e
+                    return Err(Error::io(
+                        format!(
+                            "Failed to remove backup file {}",
+                            world_backup_path.display()
+                        ),
+                        e,
+                    ));
+                }
+
+                let failed = copied?;
+
+                // Vaults the backup already reached keep it, like vaults
+                // that failed on their own.
+                progress.check_cancelled()?;
+
+                if !failed.is_empty() {
+                    return Ok(format!(
+                        "Created backup, but could not store it in {}.",
+                        describe_failures(&failed)
                     ));
                 }
             } else {
                 let default_vault = get_default_vault().await;
-                match tokio::fs::rename(&world_backup_path, default_vault.join(backup_name)).await {
+                match commit_file(&world_backup_path, &default_vault.join(&backup_name)).await {
                     Ok(_) => {}
                     Err(e) => {
                         error!(
@@ -242,156 +568,724 @@ pub async fn create_backup_from_id(
                             default_vault.display(),
                             e
                         );
-                        return Err(format!(
-                            "Failed to move backup to default vault {}: {:?}",
-                            default_vault.display(),
-                            e
+                        let _ = tokio::fs::remove_file(&world_backup_path).await;
+                        return Err(Error::io(
+                            format!(
+                                "Failed to move backup to default vault {}",
+                                default_vault.display()
+                            ),
+                            e,
                         ));
                     }
                 };
             }
-            Ok("Successfully Createad Backup.".to_string())
+            Ok("Successfully created backup.".to_string())
         }
         Err(e) => {
-            error!("Failed to grab world by id {}: {:?}", world_id, e);
-            Err(format!("Failed to grab world by id {}: {:?}", world_id, e))
+            error!("Failed to grab world by id {}: {}", world_id, e);
+            Err(e)
         }
     }
 }
 
-pub async fn get_backup_meta_from_path(backup_path: PathBuf) -> Result<BackupMetadata, String> {
-    let mut zip =
-        match ZipFileReader::with_tokio(File::open(backup_path.clone()).await.unwrap()).await {
-            Ok(zip) => zip,
+/// Vaults a backup was stored in or queued for, and the ones it failed for.
+#[derive(Default)]
+struct VaultResults {
+    stored: usize,
+    failed: Vec<(String, Error)>,
+}
+
+impl VaultResults {
+    fn record(&mut self, vault_id: &str, result: Result<(), Error>) {
+        match result {
+            Ok(_) => self.stored += 1,
             Err(e) => {
-                return Err(format!(
-                    "Failed to open backup file {}: {:?}",
-                    backup_path.display(),
-                    e
-                ));
+                error!("Failed to store backup in vault {}: {}", vault_id, e);
+                self.failed.push((vault_id.to_string(), e));
+            }
+        }
+    }
+}
+
+fn describe_failures(failed: &[(String, Error)]) -> String {
+    failed
+        .iter()
+        .map(|(vault_id, e)| format!("{} ({})", vault_id, e))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Copies a snapshot from the temp folder into the given vaults, and queues
+/// its upload to vaults that are not on disk. Vaults that fail are skipped so
+/// the others still get the backup, and returned with their errors. Fails
+/// when the backup reached no vault at all.
+async fn copy_backup_to_vaults(
+    world_id: &str,
+    world_backup_path: &Path,
+    backup_name: &OsStr,
+    vaults: Vec<String>,
+    progress: &Progress,
+) -> Result<Vec<(String, Error)>, Error> {
+    let mut vault_locations = HashMap::new();
+    let mut store_locations = HashMap::new();
+    let mut results = VaultResults::default();
+
+    let backup_settings = get_backup_config().await?;
+
+    let backup_name = backup_name.to_string_lossy();
+    let snapshot_id = backup_name
+        .strip_suffix(SNAPSHOT_SUFFIX)
+        .unwrap_or(&backup_name);
+
+    for vault_id in vaults {
+        let vault = match vault_backend(&backup_settings, &vault_id) {
+            Ok(vault) => vault,
+            Err(e) => {
+                results.record(&vault_id, Err(e));
+                continue;
             }
         };
 
-    let mut metadata = String::new();
+        match backup_settings.vault_storage(&vault_id) {
+            VaultStorage::Full => {
+                vault_locations.insert(vault_id, vault);
+            }
+            VaultStorage::Deduplicated if backup_settings.vault_encryption(&vault_id).is_some() => {
+                results.record(
+                    &vault_id,
+                    Err(Error::Vault(format!(
+                        "Vault {} is encrypted, which deduplicated storage does not support",
+                        vault_id
+                    ))),
+                );
+            }
+            VaultStorage::Deduplicated => match vault.local_path() {
+                Some(vault_path) => {
+                    store_locations.insert(vault_id, (vault_path.to_path_buf(), vault));
+                }
+                None => results.record(
+                    &vault_id,
+                    Err(Error::Vault(format!(
+                        "Vault {} is not on disk, which deduplicated storage needs",
+                        vault_id
+                    ))),
+                ),
+            },
+        };
+    }
 
-    let mut reader = match zip.reader_with_entry(0).await {
-        Ok(reader) => reader,
-        Err(e) => {
-            return Err(format!(
-                "Failed to open metadata file in backup {}: {:?}",
+    let metadata = get_backup_meta_from_path(world_backup_path.to_path_buf()).await?;
+
+    if !store_locations.is_empty() {
+        store_backup_in_vaults(
+            world_id,
+            world_backup_path,
+            &backup_name,
+            &metadata,
+            store_locations,
+            progress,
+            &mut results,
+        )
+        .await?;
+    }
+
+    info!("Copying backup to all {} vaults", vault_locations.len());
+
+    let backup_size = tokio::fs::metadata(&world_backup_path)
+        .await
+        .map(|metadata| metadata.len())
+        .unwrap_or_default();
+
+    progress.start(
+        ProgressStage::Copying,
+        backup_size * vault_locations.len() as u64,
+    );
+
+    for (vault_id, vault) in vault_locations {
+        if progress.is_cancelled() {
+            break;
+        }
+
+        progress.advance(backup_size);
+
+        // Vaults that are not on disk get a staged copy and an upload job,
+        // so the backup does not wait for the network. Only vaults on disk
+        // can be encrypted, since their keys are kept by path.
+        let stored = match (
+            vault.local_path(),
+            backup_settings.vault_encryption(&vault_id),
+        ) {
+            (Some(_), None) => {
+                vault
+                    .put_snapshot(world_id, snapshot_id, world_backup_path)
+                    .await
+            }
+            (Some(vault_path), Some(encryption)) => match get_vault_key(vault_path) {
+                Some(key) => {
+                    let encrypted_path = get_temp_dir()
+                        .await
+                        .join(format!("{}.encrypted", uuid::Uuid::new_v4()));
+
+                    let stored = match write_encrypted_snapshot(
+                        world_backup_path,
+                        &encrypted_path,
+                        &key,
+                        encryption.plaintext_metadata,
+                    )
+                    .await
+                    {
+                        Ok(_) => {
+                            vault
+                                .put_snapshot(world_id, snapshot_id, &encrypted_path)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+
+                    let _ = tokio::fs::remove_file(&encrypted_path).await;
+                    stored
+                }
+                None => Err(Error::VaultLocked(format!(
+                    "Vault {} is locked, skipping it",
+                    vault_id
+                ))),
+            },
+            (None, None) => {
+                let staged_path = staged_snapshot_path().await;
+
+                let staged = match copy_atomic(world_backup_path, &staged_path).await {
+                    Ok(_) => enqueue_upload(world_id, snapshot_id, &vault_id, staged_path.clone())
+                        .await
+                        .map(|_| ()),
+                    Err(e) => Err(Error::io("Failed to stage backup for upload", e)),
+                };
+
+                if staged.is_err() {
+                    let _ = tokio::fs::remove_file(&staged_path).await;
+                }
+
+                // The upload job records the snapshot once it is in the vault.
+                results.record(&vault_id, staged);
+                continue;
+            }
+            (None, Some(_)) => Err(Error::Config(format!(
+                "Vault {} is not on disk, which encryption needs",
+                vault_id
+            ))),
+        };
+
+        if stored.is_ok() {
+            record_snapshot(
+                &vault_id,
+                vault.as_ref(),
+                VaultStorage::Full,
+                world_id,
+                snapshot_id,
+                Some(metadata.clone()),
+            )
+            .await
+        }
+
+        results.record(&vault_id, stored);
+    }
+
+    progress.check_cancelled()?;
+
+    if results.stored == 0 {
+        return Err(Error::Vault(match results.failed.is_empty() {
+            true => "No vault to store the backup in was given".to_string(),
+            false => format!(
+                "Backup could not be stored in any vault: {}",
+                describe_failures(&results.failed)
+            ),
+        }));
+    }
+
+    Ok(results.failed)
+}
+
+/// Stores a snapshot in deduplicated vaults. The world is unpacked from the
+/// snapshot, so the vaults hold exactly the files that were archived rather
+/// than whatever the world looks like by now.
+async fn store_backup_in_vaults(
+    world_id: &str,
+    world_backup_path: &Path,
+    backup_name: &str,
+    metadata: &BackupMetadata,
+    store_locations: HashMap<String, (PathBuf, Box<dyn VaultBackend>)>,
+    progress: &Progress,
+    results: &mut VaultResults,
+) -> Result<(), Error> {
+    if metadata.parent.is_some() {
+        for vault_id in store_locations.keys() {
+            results.record(
+                vault_id,
+                Err(Error::Vault(format!(
+                    "Vault {} is deduplicated, which incremental backups cannot be stored in",
+                    vault_id
+                ))),
+            );
+        }
+        return Ok(());
+    }
+
+    let snapshot_id = backup_name
+        .strip_suffix(SNAPSHOT_SUFFIX)
+        .unwrap_or(backup_name);
+
+    let serialized_metadata = serde_json::to_string(metadata).map_err(|e| {
+        Error::SnapshotFormat(format!("Failed to serialize backup metadata: {:?}", e))
+    })?;
+
+    let world_size = match read_file_index(world_backup_path).await {
+        Ok(Some(file_index)) => file_index.files.iter().map(|file| file.size).sum(),
+        _ => 0,
+    };
+
+    let unpacked_path = get_temp_dir()
+        .await
+        .join(format!("{}_world", uuid::Uuid::new_v4()));
+
+    progress.start(ProgressStage::Extracting, world_size);
+
+    let unpacked = match tokio::fs::create_dir_all(&unpacked_path).await {
+        Ok(_) => extract_world_data(world_backup_path, &unpacked_path, None, progress).await,
+        Err(e) => Err(Error::io(
+            format!("Failed to create {}", unpacked_path.display()),
+            e,
+        )),
+    };
+
+    if let Err(e) = unpacked {
+        let _ = tokio::fs::remove_dir_all(&unpacked_path).await;
+
+        if progress.is_cancelled() {
+            return Err(e);
+        }
+
+        for vault_id in store_locations.keys() {
+            results.record(
+                vault_id,
+                Err(Error::Vault(format!("Failed to unpack backup: {}", e))),
+            );
+        }
+        return Ok(());
+    }
+
+    info!(
+        "Storing backup in {} deduplicated vaults",
+        store_locations.len()
+    );
+
+    for (vault_id, (vault_path, vault)) in store_locations {
+        if progress.is_cancelled() {
+            break;
+        }
+
+        let stored = store_world_snapshot(
+            &unpacked_path,
+            &vault_path,
+            world_id,
+            backup_name,
+            &serialized_metadata,
+        )
+        .await
+        .map(|_| ());
+
+        if stored.is_ok() {
+            record_snapshot(
+                &vault_id,
+                vault.as_ref(),
+                VaultStorage::Deduplicated,
+                world_id,
+                snapshot_id,
+                Some(metadata.clone()),
+            )
+            .await
+        }
+
+        results.record(&vault_id, stored);
+    }
+
+    if let Err(e) = tokio::fs::remove_dir_all(&unpacked_path).await {
+        error!("Failed to remove {}: {}", unpacked_path.display(), e);
+    }
+
+    Ok(())
+}
+
+pub async fn get_backup_meta_from_path(backup_path: PathBuf) -> Result<BackupMetadata, Error> {
+    let mut zip = open_snapshot(&backup_path).await?;
+
+    let metadata = match read_named_entry(&mut zip, METADATA_FILE, &backup_path).await? {
+        Some(metadata) => metadata,
+        None => {
+            return Err(Error::SnapshotFormat(format!(
+                "Backup {} has no {}",
                 backup_path.display(),
-                e
-            ));
+                METADATA_FILE
+            )));
         }
     };
 
-    match reader.read_to_string_checked(&mut metadata).await {
-        Ok(_) => {}
+    parse_backup_metadata(&metadata, &backup_path)
+}
+
+pub(crate) fn parse_backup_metadata(
+    metadata: &str,
+    backup_path: &Path,
+) -> Result<BackupMetadata, Error> {
+    let metadata: serde_json::Value = match serde_json::from_str(metadata) {
+        Ok(metadata) => metadata,
         Err(e) => {
-            return Err(format!(
-                "Failed to read metadata file in backup {}: {:?}",
+            return Err(Error::SnapshotFormat(format!(
+                "Failed to parse metadata file in backup {}: {:?}",
                 backup_path.display(),
                 e
-            ));
+            )));
         }
     };
 
-    let metadata: BackupMetadata = match serde_json::from_str(&metadata) {
+    check_format_version(&metadata, backup_path)?;
+
+    let metadata: BackupMetadata = match serde_json::from_value(metadata) {
         Ok(metadata) => metadata,
         Err(e) => {
-            return Err(format!(
+            return Err(Error::SnapshotFormat(format!(
                 "Failed to parse metadata file in backup {}: {:?}",
                 backup_path.display(),
                 e
-            ));
+            )));
         }
     };
 
     Ok(metadata)
 }
 
+pub async fn read_file_index(backup_path: &Path) -> Result<Option<FileIndex>, Error> {
+    let mut zip = open_snapshot(backup_path).await?;
+
+    let file_index = match read_named_entry(&mut zip, FILE_INDEX, backup_path).await? {
+        Some(file_index) => file_index,
+        None => return Ok(None),
+    };
+
+    let file_index = serde_json::from_str(&file_index).map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to parse file index in backup {}: {:?}",
+            backup_path.display(),
+            e
+        ))
+    })?;
+
+    Ok(Some(file_index))
+}
+
+/// Restores a snapshot of `world_id` into `extract_path`. The parents of
+/// incremental snapshots are fetched from `vault`, which the snapshot has to
+/// come from.
 pub async fn extract_world_backup(
+    vault: &dyn VaultBackend,
+    world_id: &str,
     backup_path: PathBuf,
     extract_path: PathBuf,
-) -> Result<(), String> {
-    let mut zip =
-        match ZipFileReader::with_tokio(File::open(backup_path.clone()).await.unwrap()).await {
-            Ok(zip) => zip,
-            Err(e) => {
-                return Err(format!(
-                    "Failed to open backup file {}: {:?}",
-                    backup_path.display(),
-                    e
-                ));
+    progress: &Progress,
+) -> Result<(), Error> {
+    let metadata = get_backup_meta_from_path(backup_path.clone()).await?;
+
+    if let Some(manifest) = read_snapshot_manifest(&backup_path).await? {
+        progress.start(ProgressStage::Extracting, manifest.total_size());
+
+        let vault_path = match backup_path.parent().and_then(|world| world.parent()) {
+            Some(vault_path) => vault_path,
+            None => {
+                return Err(Error::Vault(format!(
+                    "Could not find vault for backup {}",
+                    backup_path.display()
+                )))
             }
         };
 
-    let mut reader = match zip.reader_with_entry(1).await {
-        Ok(reader) => reader,
-        Err(e) => {
-            return Err(format!(
-                "Failed to open metadata file in backup {}: {:?}",
-                backup_path.display(),
-                e
-            ));
+        return extract_store_snapshot(&manifest, vault_path, &extract_path, progress).await;
+    }
+
+    let world_size = match read_file_index(&backup_path).await {
+        Ok(Some(file_index)) => file_index.files.iter().map(|file| file.size).sum(),
+        _ => 0,
+    };
+
+    progress.start(ProgressStage::Extracting, world_size);
+
+    if metadata.parent.is_some() {
+        return extract_incremental_backup(vault, world_id, &backup_path, &extract_path, progress)
+            .await;
+    }
+
+    extract_world_data(&backup_path, &extract_path, None, progress).await
+}
+
+/// Rebuilds a world from an incremental snapshot by walking its parents until
+/// every file in its index has been restored from the newest snapshot that
+/// stored it.
+async fn extract_incremental_backup(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    backup_path: &Path,
+    extract_path: &Path,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let file_index = match read_file_index(backup_path).await? {
+        Some(file_index) => file_index,
+        None => {
+            return Err(Error::SnapshotFormat(format!(
+                "Incremental backup {} has no file index",
+                backup_path.display()
+            )))
         }
     };
 
-    let mut world_data = Vec::new();
+    let mut remaining: HashSet<String> =
+        file_index.files.into_iter().map(|file| file.path).collect();
 
-    match reader.read_to_end_checked(&mut world_data).await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(format!(
-                "Failed to read world data file in backup {}: {:?}",
-                backup_path.display(),
-                e
-            ));
+    let mut visited = HashSet::new();
+    let mut parent_snapshot: Option<LocalSnapshot> = None;
+
+    loop {
+        let current = parent_snapshot
+            .as_ref()
+            .map_or(backup_path, |snapshot| snapshot.path.as_path());
+
+        info!("Restoring files from {:?}", current);
+
+        extract_world_data(current, extract_path, Some(&mut remaining), progress).await?;
+
+        if remaining.is_empty() {
+            return Ok(());
+        }
+
+        let parent = match get_backup_meta_from_path(current.to_path_buf())
+            .await?
+            .parent
+        {
+            Some(parent) => parent,
+            None => break,
+        };
+
+        if !visited.insert(parent.clone()) {
+            return Err(Error::SnapshotFormat(format!(
+                "Backup chain of {} loops back on itself",
+                backup_path.display()
+            )));
+        }
+
+        parent_snapshot = match fetch_parent(vault, world_id, &parent).await? {
+            Some(snapshot) => Some(snapshot),
+            None => {
+                return Err(Error::SnapshotFormat(format!(
+                    "Parent snapshot {} of backup {} is missing",
+                    parent,
+                    backup_path.display()
+                )))
+            }
+        };
+    }
+
+    Err(Error::SnapshotFormat(format!(
+        "{} files of backup {} could not be found in its backup chain",
+        remaining.len(),
+        backup_path.display()
+    )))
+}
+
+/// Fetches the parent of an incremental snapshot from the vault it is in.
+/// Returns `None` when the vault does not have it.
+async fn fetch_parent(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    parent: &str,
+) -> Result<Option<LocalSnapshot>, Error> {
+    match fetch_snapshot(vault, world_id, parent).await {
+        Ok(snapshot) => Ok(Some(snapshot)),
+        Err(Error::SnapshotNotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Copies the nested world data zip of a snapshot into the temp folder so it
+/// can be read entry by entry without holding it in memory.
+async fn unpack_world_data(backup_path: &Path) -> Result<PathBuf, Error> {
+    let mut zip = open_snapshot(backup_path).await?;
+
+    let (index, encrypted) = match find_world_data_entry(&zip) {
+        Some(entry) => entry,
+        None => {
+            return Err(Error::SnapshotFormat(format!(
+                "Backup {} has no world data",
+                backup_path.display()
+            )))
         }
     };
 
-    let cursor = std::io::Cursor::new(world_data);
+    let key = match encrypted {
+        true => Some(get_snapshot_key(backup_path)?),
+        false => None,
+    };
 
-    let mut world_data_zip = match ZipFileReader::with_tokio(cursor).await {
+    let mut reader = zip.reader_with_entry(index).await.map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to open world data file in backup {}: {:?}",
+            backup_path.display(),
+            e
+        ))
+    })?;
+
+    let world_data_path = get_temp_dir()
+        .await
+        .join(format!("{}_data.zip", uuid::Uuid::new_v4()));
+
+    let world_data_file = File::create(&world_data_path)
+        .await
+        .map_err(|e| Error::io(format!("Failed to create {}", world_data_path.display()), e))?;
+
+    let mut world_data_file = world_data_file.compat_write();
+
+    let copied = match key {
+        Some(key) => decrypt_stream(&key, &mut reader, &mut world_data_file).await,
+        None => match futures_util::io::copy(&mut reader, &mut world_data_file).await {
+            Ok(_) => world_data_file.flush().await.map_err(Error::from),
+            Err(e) => Err(Error::from(e)),
+        },
+    };
+
+    if let Err(e) = copied {
+        let _ = tokio::fs::remove_file(&world_data_path).await;
+        return Err(match e {
+            Error::Io(e) => Error::io(
+                format!(
+                    "Failed to read world data file in backup {}",
+                    backup_path.display()
+                ),
+                e,
+            ),
+            e => e,
+        });
+    }
+
+    Ok(world_data_path)
+}
+
+/// Extracts the world data of a single snapshot. When `remaining` is given,
+/// only the files in it are extracted and removed from it as they are found.
+async fn extract_world_data(
+    backup_path: &Path,
+    extract_path: &Path,
+    remaining: Option<&mut HashSet<String>>,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let world_data_path = unpack_world_data(backup_path).await?;
+
+    let result =
+        extract_world_data_entries(&world_data_path, extract_path, remaining, progress).await;
+
+    let _ = tokio::fs::remove_file(&world_data_path).await;
+
+    result
+}
+
+/// Streams a zip entry into `writer` in fixed-size chunks, like
+/// `write_file_to_zip`, and checks its CRC32 once all of it was read. Returns
+/// the size of the entry.
+async fn copy_zip_entry<R, W>(
+    zip_entry: &mut ZipEntryReader<'_, R, WithEntry<'_>>,
+    writer: &mut W,
+) -> Result<u64, ZipError>
+where
+    R: futures_util::io::AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let read = zip_entry.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read]).await?;
+        size += read as u64;
+    }
+
+    match zip_entry.compute_hash() == zip_entry.entry().crc32() {
+        true => Ok(size),
+        false => Err(ZipError::CRC32CheckError),
+    }
+}
+
+async fn extract_world_data_entries(
+    world_data_path: &Path,
+    extract_path: &Path,
+    mut remaining: Option<&mut HashSet<String>>,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let world_data_file = File::open(world_data_path).await?;
+
+    let mut world_data_zip = match ZipFileReader::with_tokio(BufReader::new(world_data_file)).await
+    {
         Ok(zip) => zip,
         Err(e) => {
-            return Err(format!(
-                "Failed to open world data zip in backup {}: {:?}",
-                backup_path.display(),
+            return Err(Error::SnapshotFormat(format!(
+                "Failed to open world data zip {}: {:?}",
+                world_data_path.display(),
                 e
-            ));
+            )));
         }
     };
 
     let mut index = 0;
     while let Ok(mut zip_entry) = world_data_zip.reader_with_entry(index).await {
+        progress.check_cancelled()?;
+
         let entry = zip_entry.entry();
 
-        let path = extract_path.join(entry.filename().as_str().unwrap());
+        let name = entry.filename().as_str().map_err(|e| {
+            Error::SnapshotFormat(format!(
+                "Invalid entry name in {}: {:?}",
+                world_data_path.display(),
+                e
+            ))
+        })?;
 
-        if entry.dir().unwrap() {
-            tokio::fs::create_dir_all(&path)
-                .await
-                .map_err(|e| e.to_string())?;
+        if let Some(remaining) = remaining.as_deref_mut() {
+            if !remaining.remove(name) {
+                index += 1;
+                continue;
+            }
+        }
+
+        if !is_safe_relative_path(Path::new(name)) {
+            return Err(Error::SnapshotFormat(format!(
+                "Refusing to extract unsafe path {}",
+                name
+            )));
+        }
+
+        let path = extract_path.join(name);
+
+        if entry.dir()? {
+            tokio::fs::create_dir_all(&path).await?;
         } else {
             if let Some(parent) = path.parent() {
                 if !parent.exists() {
-                    tokio::fs::create_dir_all(&parent)
-                        .await
-                        .map_err(|e| e.to_string())?;
+                    tokio::fs::create_dir_all(&parent).await?;
                 }
             }
 
-            let mut file = tokio::fs::File::create(&path)
-                .await
-                .map_err(|e| e.to_string())?;
+            let mut file = tokio::fs::File::create(&path).await?;
 
-            let mut buffer = Vec::new();
-            zip_entry
-                .read_to_end_checked(&mut buffer)
-                .await
-                .map_err(|e| e.to_string())?;
-            file.write_all(&buffer).await.map_err(|e| e.to_string())?;
+            let size = copy_zip_entry(&mut zip_entry, &mut file).await?;
+            file.flush().await?;
+
+            progress.advance(size);
         }
 
         index += 1;
@@ -400,71 +1294,418 @@ pub async fn extract_world_backup(
     Ok(())
 }
 
-pub async fn delete_backup(
+async fn hash_zip_entry<R: futures_util::io::AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<(String, u64), std::io::Error> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Checks the entries of an unpacked world data zip against the file index.
+/// Entries that are checked are removed from `expected`; entries that are not
+/// in it are reported as extra when `report_extra` is set.
+async fn verify_world_data_entries(
+    world_data_path: &Path,
+    expected: &mut HashMap<String, FileIndexEntry>,
+    report: &mut VerifyReport,
+    report_extra: bool,
+) -> Result<(), Error> {
+    let world_data_file = File::open(world_data_path).await?;
+
+    let mut world_data_zip = ZipFileReader::with_tokio(BufReader::new(world_data_file))
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!(
+                "Failed to open world data zip {}: {:?}",
+                world_data_path.display(),
+                e
+            ))
+        })?;
+
+    let mut index = 0;
+    while let Ok(mut zip_entry) = world_data_zip.reader_with_entry(index).await {
+        index += 1;
+
+        let name = match zip_entry.entry().filename().as_str() {
+            Ok(name) => name.to_string(),
+            Err(_) => continue,
+        };
+
+        let expected_entry = match expected.remove(&name) {
+            Some(expected_entry) => expected_entry,
+            None => {
+                if report_extra {
+                    report.extra.push(name);
+                }
+                continue;
+            }
+        };
+
+        report.checked += 1;
+
+        match hash_zip_entry(&mut zip_entry).await {
+            Ok((hash, size)) => {
+                let hash_matches = expected_entry.hash.is_empty() || expected_entry.hash == hash;
+
+                if !hash_matches || size != expected_entry.size {
+                    report.corrupted.push(name);
+                }
+            }
+            Err(_) => report.corrupted.push(name),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads every entry of an unpacked world data zip, relying on the zip
+/// checksums for snapshots that have no file index.
+async fn verify_world_data_checksums(
+    world_data_path: &Path,
+    report: &mut VerifyReport,
+) -> Result<(), Error> {
+    let world_data_file = File::open(world_data_path).await?;
+
+    let mut world_data_zip = ZipFileReader::with_tokio(BufReader::new(world_data_file))
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!(
+                "Failed to open world data zip {}: {:?}",
+                world_data_path.display(),
+                e
+            ))
+        })?;
+
+    let mut index = 0;
+    while let Ok(mut zip_entry) = world_data_zip.reader_with_entry(index).await {
+        index += 1;
+
+        let name = zip_entry
+            .entry()
+            .filename()
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        report.checked += 1;
+
+        if copy_zip_entry(&mut zip_entry, &mut tokio::io::sink())
+            .await
+            .is_err()
+        {
+            report.corrupted.push(name);
+        }
+    }
+
+    Ok(())
+}
+
+async fn verify_snapshot(
+    vault: &dyn VaultBackend,
+    backup_path: &Path,
     world_id: &str,
-    vault: Option<&str>,
     snapshot_id: &str,
-) -> Result<(), String> {
-    let backup_settings = get_backup_config().await?;
+) -> Result<VerifyReport, Error> {
+    info!("Verifying backup {} for {}", snapshot_id, world_id);
 
-    let vault_path = match vault {
-        Some(vault_id) => {
-            if let Some(vault) = backup_settings.vaults.get(vault_id) {
-                vault.to_owned()
-            } else {
-                return Err(format!("Vault {} does not exist.", vault_id));
+    let mut report = VerifyReport {
+        world_id: world_id.to_string(),
+        snapshot_id: snapshot_id.to_string(),
+        ..Default::default()
+    };
+
+    get_backup_meta_from_path(backup_path.to_path_buf()).await?;
+
+    if let Some(manifest) = read_snapshot_manifest(backup_path).await? {
+        let vault_path = match backup_path.parent().and_then(|world| world.parent()) {
+            Some(vault_path) => vault_path,
+            None => {
+                return Err(Error::Vault(format!(
+                    "Could not find vault for backup {}",
+                    backup_path.display()
+                )))
             }
+        };
+
+        verify_store_snapshot(&manifest, vault_path, &mut report).await;
+
+        return Ok(report);
+    }
+
+    let file_index = match read_file_index(backup_path).await? {
+        Some(file_index) => file_index,
+        None => {
+            report.checksums_only = true;
+
+            let world_data_path = unpack_world_data(backup_path).await?;
+            let result = verify_world_data_checksums(&world_data_path, &mut report).await;
+            let _ = tokio::fs::remove_file(&world_data_path).await;
+            result?;
+
+            return Ok(report);
         }
-        None => get_default_vault().await,
     };
 
-    let backup_path = vault_path
-        .join(world_id)
-        .join(format!("{}.chunkvault-snapshot", snapshot_id));
+    let mut expected: HashMap<String, FileIndexEntry> = file_index
+        .files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect();
+
+    let mut visited = HashSet::from([snapshot_id.to_string()]);
+    let mut parent_snapshot: Option<LocalSnapshot> = None;
+
+    loop {
+        let current = parent_snapshot
+            .as_ref()
+            .map_or(backup_path, |snapshot| snapshot.path.as_path());
+
+        let world_data_path = unpack_world_data(current).await?;
+        let result = verify_world_data_entries(
+            &world_data_path,
+            &mut expected,
+            &mut report,
+            current == backup_path,
+        )
+        .await;
+        let _ = tokio::fs::remove_file(&world_data_path).await;
+        result?;
+
+        if expected.is_empty() {
+            break;
+        }
 
-    info!("Removing backup {} for {}", snapshot_id, world_id);
+        let parent = match get_backup_meta_from_path(current.to_path_buf())
+            .await?
+            .parent
+        {
+            Some(parent) => parent,
+            None => break,
+        };
 
-    match tokio::fs::remove_file(backup_path.clone()).await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(format!(
-                "Failed to remove backup file {}: {:?}",
-                backup_path.display(),
-                e
-            ));
+        if !visited.insert(parent.clone()) {
+            break;
         }
-    };
-    Ok(())
+
+        parent_snapshot = match fetch_parent(vault, world_id, &parent).await? {
+            Some(snapshot) => Some(snapshot),
+            None => break,
+        };
+    }
+
+    report.missing.extend(expected.into_keys());
+    report.missing.sort();
+
+    Ok(report)
 }
 
-pub async fn delete_world_backups(world_id: &str, vault: Option<&str>) -> Result<(), String> {
+/// Checks a snapshot against its integrity manifest, reporting files that are
+/// missing, do not match their recorded SHA-256 and size, or are not listed.
+pub async fn verify_backup(
+    world_id: &str,
+    vault: Option<&str>,
+    snapshot_id: &str,
+) -> Result<VerifyReport, Error> {
+    let vault = open_vault(vault).await?;
+
+    let snapshot = fetch_snapshot(vault.as_ref(), world_id, snapshot_id).await?;
+
+    verify_snapshot(vault.as_ref(), &snapshot.path, world_id, snapshot_id).await
+}
+
+/// Files in a vault on disk that are neither world folders nor snapshots.
+async fn find_extra_files(vault_path: &Path) -> Result<Vec<String>, Error> {
+    let read_error = |e| Error::io(format!("Failed to read vault {}", vault_path.display()), e);
+
+    let mut extra = Vec::new();
+
+    let mut world_dirs = tokio::fs::read_dir(vault_path).await.map_err(read_error)?;
+
+    while let Some(world_dir) = world_dirs.next_entry().await.map_err(read_error)? {
+        let world_id = world_dir.file_name().to_string_lossy().to_string();
+
+        if world_id == OBJECTS_DIR || world_id == "temp" {
+            continue;
+        }
+
+        if !world_dir.path().is_dir() {
+            extra.push(world_id);
+            continue;
+        }
+
+        let mut snapshots = tokio::fs::read_dir(world_dir.path())
+            .await
+            .map_err(read_error)?;
+
+        while let Some(snapshot) = snapshots.next_entry().await.map_err(read_error)? {
+            let file_name = snapshot.file_name().to_string_lossy().to_string();
+
+            if !file_name.ends_with(SNAPSHOT_SUFFIX) {
+                extra.push(format!("{}/{}", world_id, file_name));
+            }
+        }
+    }
+
+    Ok(extra)
+}
+
+/// Verifies every snapshot in a vault, and reports files that do not belong
+/// in it.
+pub async fn verify_vault(vault_id: &str) -> Result<VaultVerifyReport, Error> {
     let backup_settings = get_backup_config().await?;
 
-    let vault_path = match vault {
-        Some(vault_id) => {
-            if let Some(vault) = backup_settings.vaults.get(vault_id) {
-                vault.to_owned()
-            } else {
-                return Err(format!("Vault {} does not exist.", vault_id));
+    let vault = vault_backend(&backup_settings, vault_id)?;
+
+    info!("Verifying vault {}", vault_id);
+
+    let mut report = VaultVerifyReport::default();
+
+    for world_id in vault.list_worlds().await? {
+        for snapshot_id in vault.list_snapshots(&world_id).await? {
+            let verified = match fetch_snapshot(vault.as_ref(), &world_id, &snapshot_id).await {
+                Ok(snapshot) => {
+                    verify_snapshot(vault.as_ref(), &snapshot.path, &world_id, &snapshot_id).await
+                }
+                Err(e) => Err(e),
+            };
+
+            match verified {
+                Ok(snapshot_report) => report.snapshots.push(snapshot_report),
+                Err(e) => {
+                    error!(
+                        "Failed to verify backup {} of {}: {}",
+                        snapshot_id, world_id, e
+                    );
+                    report.snapshots.push(VerifyReport {
+                        world_id: world_id.clone(),
+                        corrupted: vec![snapshot_file_name(&snapshot_id)],
+                        snapshot_id,
+                        ..Default::default()
+                    });
+                }
             }
         }
-        None => get_default_vault().await,
-    };
+    }
 
-    let backups_path = vault_path.join(world_id);
+    if let Some(vault_path) = vault.local_path() {
+        report.extra.extend(find_extra_files(vault_path).await?);
+
+        if backup_settings.vault_storage(vault_id) == VaultStorage::Deduplicated {
+            match find_unreferenced_objects(vault_path).await {
+                Ok(unreferenced) => report.extra.extend(
+                    unreferenced
+                        .into_iter()
+                        .map(|(hash, _)| format!("{}/{}", OBJECTS_DIR, hash)),
+                ),
+                Err(e) => error!("Failed to look for unreferenced objects: {}", e),
+            }
+        }
+    }
 
-    info!("Removing all backups for {}", world_id);
+    Ok(report)
+}
 
-    match tokio::fs::remove_dir_all(backups_path.clone()).await {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(format!(
-                "Failed to remove backup folder {}: {:?}",
-                backups_path.display(),
-                e
-            ));
+async fn find_dependent_snapshot(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+) -> Option<String> {
+    let snapshot_ids = vault.list_snapshots(world_id).await.ok()?;
+
+    for other_id in snapshot_ids {
+        if let Ok(metadata) = read_snapshot_metadata(vault, world_id, &other_id).await {
+            if metadata.parent.as_deref() == Some(snapshot_id) {
+                return Some(other_id);
+            }
         }
-    };
+    }
+
+    None
+}
+
+/// Removes orphaned objects after snapshots were deleted from a deduplicated
+/// vault.
+pub(crate) async fn collect_vault_garbage(
+    vault: &dyn VaultBackend,
+    storage: VaultStorage,
+) -> Result<(), Error> {
+    if let (VaultStorage::Deduplicated, Some(vault_path)) = (storage, vault.local_path()) {
+        collect_garbage(vault_path).await?;
+    }
 
     Ok(())
 }
+
+pub async fn delete_backup(
+    world_id: &str,
+    vault: Option<&str>,
+    snapshot_id: &str,
+) -> Result<(), Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let storage = match vault {
+        Some(vault_id) => backup_settings.vault_storage(vault_id),
+        None => VaultStorage::Full,
+    };
+
+    let vault_id = vault;
+    let vault = open_vault(vault_id).await?;
+
+    // Snapshots in deduplicated vaults never depend on each other, older ones
+    // may still name the parent of the snapshot they were stored from.
+    let dependent = match storage {
+        VaultStorage::Full => find_dependent_snapshot(vault.as_ref(), world_id, snapshot_id).await,
+        VaultStorage::Deduplicated => None,
+    };
+
+    if let Some(dependent) = dependent {
+        return Err(Error::Vault(format!(
+            "Backup {} is the base of incremental backup {} and cannot be removed.",
+            snapshot_id, dependent
+        )));
+    }
+
+    info!("Removing backup {} for {}", snapshot_id, world_id);
+
+    vault.delete_snapshot(world_id, snapshot_id).await?;
+
+    if let Some(vault_id) = vault_id {
+        forget_snapshots(vault_id, [(world_id, snapshot_id)]).await;
+    }
+
+    collect_vault_garbage(vault.as_ref(), storage).await
+}
+
+pub async fn delete_world_backups(world_id: &str, vault: Option<&str>) -> Result<(), Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let storage = match vault {
+        Some(vault_id) => backup_settings.vault_storage(vault_id),
+        None => VaultStorage::Full,
+    };
+
+    let vault_id = vault;
+    let vault = open_vault(vault_id).await?;
+
+    info!("Removing all backups for {}", world_id);
+
+    vault.delete_world(world_id).await?;
+
+    if let Some(vault_id) = vault_id {
+        forget_world(vault_id, world_id).await;
+    }
+
+    collect_vault_garbage(vault.as_ref(), storage).await
+}
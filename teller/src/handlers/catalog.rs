@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use chrono::Utc;
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::config::get_config_folder;
+use crate::handlers::vault::{
+    local_snapshot_path, read_snapshot_metadata, vault_backend, VaultBackend,
+};
+use crate::types::backup::{
+    BackupMetadata, CatalogSnapshot, CatalogWorld, VaultCatalog, VaultStorage,
+};
+use crate::types::error::Error;
+use crate::types::world::WorldData;
+
+use super::atomic::write_atomic;
+use super::store::read_snapshot_manifest;
+
+/// Folder in the config folder the catalogs of the vaults are kept in.
+const CATALOGS_DIR: &str = "catalogs";
+
+/// Serializes changes to the catalog files.
+static CATALOG_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+fn catalog_lock() -> &'static Mutex<()> {
+    CATALOG_LOCK.get_or_init(|| Mutex::new(()))
+}
+
+/// Vault ids are picked by the user, so catalogs are named after their hash.
+fn catalog_path(vault_id: &str) -> PathBuf {
+    let name = hex::encode(Sha256::digest(vault_id.as_bytes()));
+
+    get_config_folder()
+        .join(CATALOGS_DIR)
+        .join(format!("{}.json", name))
+}
+
+async fn load_catalog(vault_id: &str) -> Option<VaultCatalog> {
+    let catalog_path = catalog_path(vault_id);
+    let catalog = tokio::fs::read_to_string(&catalog_path).await.ok()?;
+
+    match serde_json::from_str::<VaultCatalog>(&catalog) {
+        Ok(catalog) if catalog.vault_id == vault_id => Some(catalog),
+        Ok(_) => None,
+        Err(e) => {
+            error!("Could not parse catalog at {:?}: {:?}", catalog_path, e);
+            None
+        }
+    }
+}
+
+async fn save_catalog(catalog: &VaultCatalog) -> Result<(), Error> {
+    let catalog_path = catalog_path(&catalog.vault_id);
+
+    let data = serde_json::to_vec(catalog)
+        .map_err(|e| Error::Config(format!("Could not serialize catalog: {:?}", e)))?;
+
+    if let Some(catalogs_dir) = catalog_path.parent() {
+        tokio::fs::create_dir_all(catalogs_dir)
+            .await
+            .map_err(|e| Error::io(format!("Could not create {:?}", catalogs_dir), e))?;
+    }
+
+    write_atomic(&catalog_path, &data)
+        .await
+        .map_err(|e| Error::io(format!("Could not write catalog at {:?}", catalog_path), e))
+}
+
+/// Applies a change to a catalog and drops the worlds it left without
+/// snapshots. Returns `None` when there is no catalog to change.
+fn apply_change(
+    catalog: Option<VaultCatalog>,
+    change: impl FnOnce(&mut VaultCatalog),
+) -> Option<VaultCatalog> {
+    let mut catalog = catalog?;
+
+    change(&mut catalog);
+    catalog
+        .worlds
+        .retain(|_, world| !world.snapshots.is_empty());
+
+    Some(catalog)
+}
+
+/// Applies a change to the catalog of a vault. Vaults without a catalog are
+/// left alone, they are indexed the next time they are listed.
+async fn update_catalog(vault_id: &str, change: impl FnOnce(&mut VaultCatalog)) {
+    let _guard = catalog_lock().lock().await;
+
+    let catalog = match apply_change(load_catalog(vault_id).await, change) {
+        Some(catalog) => catalog,
+        None => return,
+    };
+
+    if let Err(e) = save_catalog(&catalog).await {
+        error!("Failed to update catalog of vault {}: {}", vault_id, e);
+    }
+}
+
+/// Reads what the catalog keeps of a snapshot. `metadata` saves reading it
+/// back from the vault when the caller already has it.
+async fn scan_snapshot(
+    vault: &dyn VaultBackend,
+    storage: VaultStorage,
+    world_id: &str,
+    snapshot_id: &str,
+    metadata: Option<BackupMetadata>,
+) -> Result<(CatalogSnapshot, Option<WorldData>), Error> {
+    let created = snapshot_id.parse::<i64>().map_err(|_| {
+        Error::SnapshotFormat(format!("{} of {} is not a snapshot", snapshot_id, world_id))
+    })?;
+
+    let file_size = vault.stat_snapshot(world_id, snapshot_id).await?.size;
+
+    let size = match (storage, local_snapshot_path(vault, world_id, snapshot_id)) {
+        (VaultStorage::Deduplicated, Some(path)) => match read_snapshot_manifest(&path).await {
+            Ok(Some(manifest)) => manifest.total_size(),
+            _ => file_size,
+        },
+        _ => file_size,
+    };
+
+    let metadata = match metadata {
+        Some(metadata) => Some(metadata),
+        None => match read_snapshot_metadata(vault, world_id, snapshot_id).await {
+            Ok(metadata) => Some(metadata),
+            Err(e) => {
+                warn!(
+                    "Could not read metadata of backup {} of {}: {}",
+                    snapshot_id, world_id, e
+                );
+                None
+            }
+        },
+    };
+
+    let snapshot = CatalogSnapshot {
+        snapshot_id: snapshot_id.to_string(),
+        created,
+        size,
+        parent: match storage {
+            VaultStorage::Full => metadata
+                .as_ref()
+                .and_then(|metadata| metadata.parent.clone()),
+            VaultStorage::Deduplicated => None,
+        },
+        best_effort: metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.best_effort),
+    };
+
+    Ok((snapshot, metadata.map(|metadata| metadata.entry)))
+}
+
+fn insert_snapshot(world: &mut CatalogWorld, snapshot: CatalogSnapshot, entry: Option<WorldData>) {
+    world
+        .snapshots
+        .retain(|recorded| recorded.snapshot_id != snapshot.snapshot_id);
+
+    let newest = world
+        .snapshots
+        .iter()
+        .all(|recorded| recorded.created <= snapshot.created);
+
+    if newest && entry.is_some() {
+        world.entry = entry;
+    }
+
+    world.snapshots.push(snapshot);
+    world.snapshots.sort_by_key(|snapshot| snapshot.created);
+}
+
+/// Rebuilds the catalog of a vault from what the vault holds, for when it
+/// has drifted, like after snapshots were copied in by hand.
+pub async fn reindex_vault(vault_id: &str) -> Result<VaultCatalog, Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault = vault_backend(&backup_settings, vault_id)?;
+
+    let storage = backup_settings.vault_storage(vault_id);
+
+    info!("Indexing vault {}", vault_id);
+
+    let _guard = catalog_lock().lock().await;
+
+    let mut catalog = VaultCatalog {
+        vault_id: vault_id.to_string(),
+        indexed: Utc::now().timestamp(),
+        worlds: HashMap::new(),
+    };
+
+    for world_id in vault.list_worlds().await? {
+        let snapshot_ids = match vault.list_snapshots(&world_id).await {
+            Ok(snapshot_ids) => snapshot_ids,
+            Err(e) => {
+                warn!("Skipping {} in vault: {}", world_id, e);
+                continue;
+            }
+        };
+
+        let mut world = CatalogWorld::default();
+
+        for snapshot_id in snapshot_ids {
+            match scan_snapshot(vault.as_ref(), storage, &world_id, &snapshot_id, None).await {
+                Ok((snapshot, entry)) => insert_snapshot(&mut world, snapshot, entry),
+                Err(e) => warn!("Skipping {} of {}: {}", snapshot_id, world_id, e),
+            }
+        }
+
+        if !world.snapshots.is_empty() {
+            catalog.worlds.insert(world_id, world);
+        }
+    }
+
+    save_catalog(&catalog).await?;
+
+    Ok(catalog)
+}
+
+/// The catalog of a vault, indexing the vault first when it has none yet.
+pub async fn get_vault_catalog(vault_id: &str) -> Result<VaultCatalog, Error> {
+    match load_catalog(vault_id).await {
+        Some(catalog) => Ok(catalog),
+        None => reindex_vault(vault_id).await,
+    }
+}
+
+/// Adds a snapshot that was just stored in a vault to its catalog.
+pub(crate) async fn record_snapshot(
+    vault_id: &str,
+    vault: &dyn VaultBackend,
+    storage: VaultStorage,
+    world_id: &str,
+    snapshot_id: &str,
+    metadata: Option<BackupMetadata>,
+) {
+    match scan_snapshot(vault, storage, world_id, snapshot_id, metadata).await {
+        Ok((snapshot, entry)) => {
+            update_catalog(vault_id, |catalog| {
+                let world = catalog.worlds.entry(world_id.to_string()).or_default();
+                insert_snapshot(world, snapshot, entry);
+            })
+            .await
+        }
+        Err(e) => error!(
+            "Failed to add backup {} to catalog of vault {}: {}",
+            snapshot_id, vault_id, e
+        ),
+    }
+}
+
+/// Drops snapshots that were removed from a vault from its catalog, given
+/// as world id and snapshot id.
+pub(crate) async fn forget_snapshots<'a>(
+    vault_id: &str,
+    snapshots: impl IntoIterator<Item = (&'a str, &'a str)>,
+) {
+    update_catalog(vault_id, |catalog| remove_snapshots(catalog, snapshots)).await
+}
+
+fn remove_snapshots<'a>(
+    catalog: &mut VaultCatalog,
+    snapshots: impl IntoIterator<Item = (&'a str, &'a str)>,
+) {
+    for (world_id, snapshot_id) in snapshots {
+        if let Some(world) = catalog.worlds.get_mut(world_id) {
+            world
+                .snapshots
+                .retain(|snapshot| snapshot.snapshot_id != snapshot_id);
+        }
+    }
+}
+
+pub(crate) async fn forget_world(vault_id: &str, world_id: &str) {
+    update_catalog(vault_id, |catalog| {
+        catalog.worlds.remove(world_id);
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(created: i64) -> CatalogSnapshot {
+        CatalogSnapshot {
+            snapshot_id: created.to_string(),
+            created,
+            size: created as u64,
+            parent: None,
+            best_effort: false,
+        }
+    }
+
+    fn entry(name: &str) -> WorldData {
+        WorldData {
+            id: "world".to_string(),
+            name: name.to_string(),
+            image: String::new(),
+            path: String::new(),
+            size: 0,
+            last_played: None,
+            game_type: None,
+        }
+    }
+
+    fn snapshot_ids(world: &CatalogWorld) -> Vec<i64> {
+        world
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.created)
+            .collect()
+    }
+
+    fn entry_name(world: &CatalogWorld) -> Option<&str> {
+        world.entry.as_ref().map(|entry| entry.name.as_str())
+    }
+
+    fn catalog(worlds: &[(&str, &[i64])]) -> VaultCatalog {
+        VaultCatalog {
+            vault_id: "vault".to_string(),
+            indexed: 0,
+            worlds: worlds
+                .iter()
+                .map(|(world_id, created)| {
+                    let world = CatalogWorld {
+                        entry: None,
+                        snapshots: created.iter().map(|created| snapshot(*created)).collect(),
+                    };
+                    (world_id.to_string(), world)
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn inserts_snapshots_oldest_first() {
+        let mut world = CatalogWorld::default();
+
+        for created in [20, 10, 30] {
+            insert_snapshot(&mut world, snapshot(created), None);
+        }
+
+        assert_eq!(snapshot_ids(&world), vec![10, 20, 30]);
+    }
+
+    #[test]
+    fn replaces_snapshots_with_the_same_id() {
+        let mut world = CatalogWorld::default();
+        insert_snapshot(&mut world, snapshot(10), None);
+        insert_snapshot(&mut world, snapshot(20), None);
+
+        insert_snapshot(
+            &mut world,
+            CatalogSnapshot {
+                size: 99,
+                ..snapshot(10)
+            },
+            None,
+        );
+
+        assert_eq!(snapshot_ids(&world), vec![10, 20]);
+        assert_eq!(world.snapshots[0].size, 99);
+    }
+
+    #[test]
+    fn keeps_the_entry_of_the_newest_snapshot() {
+        let mut world = CatalogWorld::default();
+
+        insert_snapshot(&mut world, snapshot(20), Some(entry("newer")));
+        insert_snapshot(&mut world, snapshot(10), Some(entry("older")));
+        assert_eq!(entry_name(&world), Some("newer"));
+
+        // A newest snapshot without readable metadata keeps the last entry.
+        insert_snapshot(&mut world, snapshot(30), None);
+        assert_eq!(entry_name(&world), Some("newer"));
+
+        insert_snapshot(&mut world, snapshot(40), Some(entry("newest")));
+        assert_eq!(entry_name(&world), Some("newest"));
+    }
+
+    #[test]
+    fn forgetting_the_last_snapshot_removes_the_world() {
+        let catalog = catalog(&[("world", &[10]), ("other", &[10, 20])]);
+
+        let catalog = apply_change(Some(catalog), |catalog| {
+            remove_snapshots(catalog, [("world", "10"), ("other", "10"), ("gone", "10")])
+        })
+        .unwrap();
+
+        assert!(!catalog.worlds.contains_key("world"));
+        assert_eq!(snapshot_ids(&catalog.worlds["other"]), vec![20]);
+    }
+
+    #[test]
+    fn changes_drop_worlds_left_empty() {
+        let catalog = catalog(&[("world", &[10]), ("empty", &[])]);
+
+        let catalog = apply_change(Some(catalog), |catalog| {
+            catalog
+                .worlds
+                .insert("new".to_string(), CatalogWorld::default());
+        })
+        .unwrap();
+
+        let mut world_ids: Vec<&String> = catalog.worlds.keys().collect();
+        world_ids.sort();
+        assert_eq!(world_ids, vec!["world"]);
+    }
+
+    #[test]
+    fn changes_skip_vaults_without_a_catalog() {
+        let mut changed = false;
+
+        assert!(apply_change(None, |_| changed = true).is_none());
+        assert!(!changed);
+    }
+}
@@ -1,10 +1,31 @@
-use tokio::fs;
-
 use log::{error, info};
 
-use crate::{handlers::config::get_config_folder, types::backup::BackupSettings};
+use crate::{
+    handlers::{atomic::write_atomic, config::get_config_folder, scheduler::cron::CronSchedule},
+    types::{
+        backup::{BackupProfile, BackupSettings},
+        error::Error,
+    },
+};
+
+/// Vault keys are only kept for vaults on disk, so snapshots for any other
+/// vault could never be encrypted.
+fn check_encrypted_vaults(settings: &BackupSettings) -> Result<(), Error> {
+    for (vault_id, vault_settings) in &settings.vault_settings {
+        if vault_settings.encryption.is_some() && !settings.vaults.contains_key(vault_id) {
+            return Err(Error::Config(format!(
+                "Vault {} is not on disk, which encryption needs",
+                vault_id
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn update_backup_config(settings_data: BackupSettings) -> Result<BackupSettings, Error> {
+    check_encrypted_vaults(&settings_data)?;
 
-pub async fn update_backup_config(settings_data: BackupSettings) -> Result<BackupSettings, String> {
     let config_dir = get_config_folder();
 
     let config_path = config_dir.join("backup_settings.json");
@@ -24,10 +45,10 @@ pub async fn update_backup_config(settings_data: BackupSettings) -> Result<Backu
                 "Could not load backup config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not load backup config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -38,24 +59,29 @@ pub async fn update_backup_config(settings_data: BackupSettings) -> Result<Backu
                 "Could not parse backup config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not parse backup config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
-    match fs::write(&config_path, serde_json::to_string(&settings_data).unwrap()).await {
+    match write_atomic(
+        &config_path,
+        serde_json::to_string(&settings_data).unwrap().as_bytes(),
+    )
+    .await
+    {
         Ok(_) => (),
         Err(e) => {
             error!(
                 "Could not write backup config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not write backup config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     }
 
@@ -64,7 +90,7 @@ pub async fn update_backup_config(settings_data: BackupSettings) -> Result<Backu
     Ok(parsed_settings)
 }
 
-pub async fn get_backup_config() -> Result<BackupSettings, String> {
+pub async fn get_backup_config() -> Result<BackupSettings, Error> {
     let config_dir = get_config_folder();
 
     let config_path = config_dir.join("backup_settings.json");
@@ -78,10 +104,10 @@ pub async fn get_backup_config() -> Result<BackupSettings, String> {
                     "Could not create backup config file at {:?}: {:?}",
                     config_path, e
                 );
-                return Err(format!(
+                return Err(Error::Config(format!(
                     "Could not create backup config file at {:?}: {:?}",
                     config_path, e
-                ));
+                )));
             }
         }
     }
@@ -95,10 +121,10 @@ pub async fn get_backup_config() -> Result<BackupSettings, String> {
         Ok(s) => s,
         Err(e) => {
             error!("Could not load config file at {:?}: {:?}", config_path, e);
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not load config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -109,10 +135,10 @@ pub async fn get_backup_config() -> Result<BackupSettings, String> {
                 "Could not parse backup config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not parse backup config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -120,3 +146,44 @@ pub async fn get_backup_config() -> Result<BackupSettings, String> {
 
     Ok(parsed_settings)
 }
+
+pub async fn update_backup_profile(
+    world_id: &str,
+    profile: BackupProfile,
+) -> Result<BackupSettings, Error> {
+    CronSchedule::parse(&profile.schedule)?;
+
+    let mut backup_settings = get_backup_config().await?;
+
+    for vault in profile.vaults.iter().flatten() {
+        if !backup_settings.has_vault(vault) {
+            return Err(Error::VaultNotFound(format!(
+                "Vault {} does not exist.",
+                vault
+            )));
+        }
+    }
+
+    info!("Updating backup profile for {}", world_id);
+
+    backup_settings
+        .profiles
+        .insert(world_id.to_string(), profile);
+
+    update_backup_config(backup_settings).await
+}
+
+pub async fn remove_backup_profile(world_id: &str) -> Result<BackupSettings, Error> {
+    let mut backup_settings = get_backup_config().await?;
+
+    if backup_settings.profiles.remove(world_id).is_none() {
+        return Err(Error::Config(format!(
+            "World {} has no backup profile.",
+            world_id
+        )));
+    }
+
+    info!("Removed backup profile for {}", world_id);
+
+    update_backup_config(backup_settings).await
+}
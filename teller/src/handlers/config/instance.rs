@@ -8,7 +8,10 @@ use std::{
 use log::{error, info};
 use tokio::fs;
 
-use crate::{handlers::config::get_config_folder, types::config::DirectorySettings};
+use crate::{
+    handlers::config::get_config_folder,
+    types::{config::DirectorySettings, error::Error},
+};
 
 pub fn get_minecraft_save_location() -> Option<PathBuf> {
     let os = env::consts::OS;
@@ -60,7 +63,7 @@ pub fn get_minecraft_save_location() -> Option<PathBuf> {
 
 pub fn get_local_directories_config<P: AsRef<Path>>(
     config_dir: P,
-) -> Result<DirectorySettings, String> {
+) -> Result<DirectorySettings, Error> {
     let config_path = config_dir.as_ref().join("local-directories.json");
 
     info!("Loading config file at {:?}", config_path);
@@ -97,7 +100,7 @@ pub fn get_local_directories_config<P: AsRef<Path>>(
 
 pub async fn create_local_directories_config(
     settings_data: DirectorySettings,
-) -> Result<DirectorySettings, String> {
+) -> Result<DirectorySettings, Error> {
     let config_dir = get_config_folder();
 
     let config_path = config_dir.join("local-directories.json");
@@ -117,10 +120,10 @@ pub async fn create_local_directories_config(
                 "Could not load directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not load directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -131,10 +134,10 @@ pub async fn create_local_directories_config(
                 "Could not parse directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not parse directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -145,10 +148,10 @@ pub async fn create_local_directories_config(
                 "Could not write directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not write directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     }
 
@@ -159,7 +162,7 @@ pub async fn create_local_directories_config(
 
 pub async fn update_local_directories_config(
     settings_data: DirectorySettings,
-) -> Result<DirectorySettings, String> {
+) -> Result<DirectorySettings, Error> {
     let config_dir = get_config_folder();
 
     let config_path = config_dir.join("local-directories.json");
@@ -179,10 +182,10 @@ pub async fn update_local_directories_config(
                 "Could not load directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not load directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -193,10 +196,10 @@ pub async fn update_local_directories_config(
                 "Could not parse directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not parse directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     };
 
@@ -207,10 +210,10 @@ pub async fn update_local_directories_config(
                 "Could not write directories config file at {:?}: {:?}",
                 config_path, e
             );
-            return Err(format!(
+            return Err(Error::Config(format!(
                 "Could not write directories config file at {:?}: {:?}",
                 config_path, e
-            ));
+            )));
         }
     }
 
@@ -0,0 +1,497 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::generic_array::GenericArray;
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use log::info;
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::handlers::config::backup::{get_backup_config, update_backup_config};
+use crate::types::backup::{BackupSettings, VaultEncryption, VaultStorage};
+use crate::types::error::Error;
+
+/// Suffix of snapshot entries whose contents are encrypted.
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+/// The STREAM construction uses 5 bytes of the nonce for its counter.
+const STREAM_NONCE_SIZE: usize = NONCE_SIZE - 5;
+const TAG_SIZE: usize = 16;
+const CHUNK_SIZE: usize = 64 * 1024;
+const KEY_CHECK: &[u8] = b"chunkvault-vault-key";
+
+const DEFAULT_MEMORY_KIB: u32 = 64 * 1024;
+const DEFAULT_ITERATIONS: u32 = 3;
+const DEFAULT_PARALLELISM: u32 = 1;
+
+pub type VaultKey = [u8; KEY_SIZE];
+
+/// Keys of unlocked vaults, by vault path. Keys only live in memory and have
+/// to be unlocked again after a restart.
+static VAULT_KEYS: OnceLock<Mutex<HashMap<PathBuf, VaultKey>>> = OnceLock::new();
+
+fn vault_keys() -> &'static Mutex<HashMap<PathBuf, VaultKey>> {
+    VAULT_KEYS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub fn get_vault_key(vault_path: &Path) -> Option<VaultKey> {
+    vault_keys().lock().ok()?.get(vault_path).copied()
+}
+
+/// Returns the key of the vault a snapshot at `<vault>/<world_id>/<snapshot>`
+/// belongs to.
+pub fn get_snapshot_key(backup_path: &Path) -> Result<VaultKey, Error> {
+    backup_path
+        .parent()
+        .and_then(|world| world.parent())
+        .and_then(get_vault_key)
+        .ok_or_else(|| {
+            Error::VaultLocked(format!(
+                "Backup {} is encrypted and its vault is locked",
+                backup_path.display()
+            ))
+        })
+}
+
+fn derive_key(passphrase: &str, encryption: &VaultEncryption) -> Result<VaultKey, Error> {
+    let salt = STANDARD
+        .decode(&encryption.salt)
+        .map_err(|e| Error::Config(format!("Invalid vault salt: {}", e)))?;
+
+    let params = Params::new(
+        encryption.memory_kib,
+        encryption.iterations,
+        encryption.parallelism,
+        Some(KEY_SIZE),
+    )
+    .map_err(|e| Error::Config(format!("Invalid key derivation parameters: {}", e)))?;
+
+    let mut key = [0; KEY_SIZE];
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| Error::Encryption(format!("Failed to derive vault key: {}", e)))?;
+
+    Ok(key)
+}
+
+/// Encrypts a small payload in one piece, prefixed with its random nonce.
+pub fn encrypt_bytes(key: &VaultKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let cipher = XChaCha20Poly1305::new(GenericArray::from_slice(key));
+
+    let mut nonce = [0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), data)
+        .map_err(|_| Error::Encryption("Failed to encrypt data".to_string()))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+
+    Ok(sealed)
+}
+
+pub fn decrypt_bytes(key: &VaultKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < NONCE_SIZE + TAG_SIZE {
+        return Err(Error::Encryption("Encrypted data is truncated".to_string()));
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+
+    XChaCha20Poly1305::new(GenericArray::from_slice(key))
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            Error::Encryption(
+                "Failed to decrypt data, it is corrupted or the key is wrong".to_string(),
+            )
+        })
+}
+
+/// Reads until `buffer` is full or the reader is exhausted.
+async fn fill_buffer<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+) -> Result<usize, Error> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        let read = reader.read(&mut buffer[filled..]).await?;
+
+        if read == 0 {
+            break;
+        }
+
+        filled += read;
+    }
+
+    Ok(filled)
+}
+
+/// Encrypts a stream in fixed size chunks so that memory use does not depend
+/// on the size of the payload. Each chunk is authenticated on its own and the
+/// last one is marked, so truncated or reordered payloads fail to decrypt.
+pub async fn encrypt_stream<R, W>(
+    key: &VaultKey,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut nonce = [0; STREAM_NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(GenericArray::from_slice(key));
+    let mut encryptor = EncryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce));
+
+    writer.write_all(&nonce).await?;
+
+    let mut current = vec![0; CHUNK_SIZE];
+    let mut next = vec![0; CHUNK_SIZE];
+    let mut current_len = fill_buffer(reader, &mut current).await?;
+
+    loop {
+        let next_len = if current_len == CHUNK_SIZE {
+            fill_buffer(reader, &mut next).await?
+        } else {
+            0
+        };
+
+        if next_len == 0 {
+            let chunk = encryptor
+                .encrypt_last(&current[..current_len])
+                .map_err(|_| Error::Encryption("Failed to encrypt data".to_string()))?;
+            writer.write_all(&chunk).await?;
+            break;
+        }
+
+        let chunk = encryptor
+            .encrypt_next(&current[..current_len])
+            .map_err(|_| Error::Encryption("Failed to encrypt data".to_string()))?;
+        writer.write_all(&chunk).await?;
+
+        std::mem::swap(&mut current, &mut next);
+        current_len = next_len;
+    }
+
+    writer.flush().await?;
+
+    Ok(())
+}
+
+pub async fn decrypt_stream<R, W>(
+    key: &VaultKey,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut nonce = [0; STREAM_NONCE_SIZE];
+    if fill_buffer(reader, &mut nonce).await? != STREAM_NONCE_SIZE {
+        return Err(Error::Encryption("Encrypted data is truncated".to_string()));
+    }
+
+    let cipher = XChaCha20Poly1305::new(GenericArray::from_slice(key));
+    let mut decryptor = DecryptorBE32::from_aead(cipher, GenericArray::from_slice(&nonce));
+
+    let mut current = vec![0; CHUNK_SIZE + TAG_SIZE];
+    let mut next = vec![0; CHUNK_SIZE + TAG_SIZE];
+    let mut current_len = fill_buffer(reader, &mut current).await?;
+
+    loop {
+        let next_len = if current_len == current.len() {
+            fill_buffer(reader, &mut next).await?
+        } else {
+            0
+        };
+
+        if next_len == 0 {
+            let chunk = decryptor
+                .decrypt_last(&current[..current_len])
+                .map_err(|_| {
+                    Error::Encryption(
+                        "Failed to decrypt data, it is corrupted or the key is wrong".to_string(),
+                    )
+                })?;
+            writer.write_all(&chunk).await?;
+            break;
+        }
+
+        let chunk = decryptor
+            .decrypt_next(&current[..current_len])
+            .map_err(|_| {
+                Error::Encryption(
+                    "Failed to decrypt data, it is corrupted or the key is wrong".to_string(),
+                )
+            })?;
+        writer.write_all(&chunk).await?;
+
+        std::mem::swap(&mut current, &mut next);
+        current_len = next_len;
+    }
+
+    writer.flush().await?;
+
+    Ok(())
+}
+
+fn get_vault_path<'a>(
+    backup_settings: &'a BackupSettings,
+    vault_id: &str,
+) -> Result<&'a PathBuf, Error> {
+    backup_settings
+        .vaults
+        .get(vault_id)
+        .ok_or_else(|| Error::VaultNotFound(format!("Vault {} does not exist.", vault_id)))
+}
+
+/// Turns on encryption for new snapshots in a vault and unlocks it. Snapshots
+/// that are already in the vault stay readable as they are.
+pub async fn enable_vault_encryption(
+    vault_id: &str,
+    passphrase: &str,
+    plaintext_metadata: bool,
+) -> Result<BackupSettings, Error> {
+    let mut backup_settings = get_backup_config().await?;
+
+    let vault_path = get_vault_path(&backup_settings, vault_id)?.clone();
+
+    if backup_settings.vault_encryption(vault_id).is_some() {
+        return Err(Error::Vault(format!(
+            "Vault {} is already encrypted.",
+            vault_id
+        )));
+    }
+
+    if backup_settings.vault_storage(vault_id) == VaultStorage::Deduplicated {
+        return Err(Error::Vault(format!(
+            "Vault {} uses deduplicated storage, which cannot be encrypted.",
+            vault_id
+        )));
+    }
+
+    if passphrase.is_empty() {
+        return Err(Error::Encryption(
+            "The passphrase cannot be empty.".to_string(),
+        ));
+    }
+
+    let mut salt = [0; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+
+    let mut encryption = VaultEncryption {
+        salt: STANDARD.encode(salt),
+        memory_kib: DEFAULT_MEMORY_KIB,
+        iterations: DEFAULT_ITERATIONS,
+        parallelism: DEFAULT_PARALLELISM,
+        key_check: String::new(),
+        plaintext_metadata,
+    };
+
+    let key = derive_key(passphrase, &encryption)?;
+    encryption.key_check = STANDARD.encode(encrypt_bytes(&key, KEY_CHECK)?);
+
+    info!("Enabling encryption for vault {}", vault_id);
+
+    backup_settings
+        .vault_settings
+        .entry(vault_id.to_string())
+        .or_default()
+        .encryption = Some(encryption);
+
+    let backup_settings = update_backup_config(backup_settings).await?;
+
+    if let Ok(mut keys) = vault_keys().lock() {
+        keys.insert(vault_path, key);
+    }
+
+    Ok(backup_settings)
+}
+
+pub async fn unlock_vault(vault_id: &str, passphrase: &str) -> Result<(), Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault_path = get_vault_path(&backup_settings, vault_id)?;
+
+    let encryption = match backup_settings.vault_encryption(vault_id) {
+        Some(encryption) => encryption,
+        None => {
+            return Err(Error::Vault(format!(
+                "Vault {} is not encrypted.",
+                vault_id
+            )))
+        }
+    };
+
+    let key = derive_key(passphrase, encryption)?;
+
+    let key_check = STANDARD
+        .decode(&encryption.key_check)
+        .map_err(|e| Error::Config(format!("Invalid vault key check: {}", e)))?;
+
+    match decrypt_bytes(&key, &key_check) {
+        Ok(check) if check == KEY_CHECK => {}
+        _ => {
+            return Err(Error::Encryption(format!(
+                "Wrong passphrase for vault {}.",
+                vault_id
+            )))
+        }
+    }
+
+    info!("Unlocked vault {}", vault_id);
+
+    vault_keys()
+        .lock()
+        .map_err(|e| Error::Encryption(e.to_string()))?
+        .insert(vault_path.clone(), key);
+
+    Ok(())
+}
+
+pub async fn lock_vault(vault_id: &str) -> Result<(), Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault_path = get_vault_path(&backup_settings, vault_id)?;
+
+    vault_keys()
+        .lock()
+        .map_err(|e| Error::Encryption(e.to_string()))?
+        .remove(vault_path);
+
+    Ok(())
+}
+
+pub async fn is_vault_unlocked(vault_id: &str) -> Result<bool, Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault_path = get_vault_path(&backup_settings, vault_id)?;
+
+    Ok(get_vault_key(vault_path).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::io::Cursor;
+
+    use super::*;
+
+    async fn encrypt(key: &VaultKey, data: &[u8]) -> Vec<u8> {
+        let mut encrypted = Vec::new();
+        encrypt_stream(key, &mut Cursor::new(data), &mut encrypted)
+            .await
+            .unwrap();
+        encrypted
+    }
+
+    async fn decrypt(key: &VaultKey, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut decrypted = Vec::new();
+        decrypt_stream(key, &mut Cursor::new(data), &mut decrypted).await?;
+        Ok(decrypted)
+    }
+
+    fn test_encryption(passphrase: &str) -> (VaultEncryption, VaultKey) {
+        // Far cheaper than the defaults, which only slow the tests down.
+        let mut encryption = VaultEncryption {
+            salt: STANDARD.encode([7; SALT_SIZE]),
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            key_check: String::new(),
+            plaintext_metadata: false,
+        };
+
+        let key = derive_key(passphrase, &encryption).unwrap();
+        encryption.key_check = STANDARD.encode(encrypt_bytes(&key, KEY_CHECK).unwrap());
+
+        (encryption, key)
+    }
+
+    #[tokio::test]
+    async fn round_trips_streams_at_chunk_boundaries() {
+        let key = [1; KEY_SIZE];
+
+        for size in [0, CHUNK_SIZE, CHUNK_SIZE + 1] {
+            let data: Vec<u8> = (0..size).map(|i| i as u8).collect();
+
+            let encrypted = encrypt(&key, &data).await;
+            let chunks = size.div_ceil(CHUNK_SIZE).max(1);
+            assert_eq!(
+                encrypted.len(),
+                STREAM_NONCE_SIZE + size + chunks * TAG_SIZE,
+                "size {}",
+                size
+            );
+
+            assert_eq!(
+                decrypt(&key, &encrypted).await.unwrap(),
+                data,
+                "size {}",
+                size
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_streams() {
+        let key = [1; KEY_SIZE];
+        let encrypted = encrypt(&key, &vec![0; CHUNK_SIZE + 1]).await;
+
+        // Dropping the last chunk leaves a stream whose final chunk is not
+        // marked as the last one.
+        let without_last_chunk = &encrypted[..STREAM_NONCE_SIZE + CHUNK_SIZE + TAG_SIZE];
+
+        for truncated in [
+            without_last_chunk,
+            &encrypted[..encrypted.len() - 1],
+            &encrypted[..STREAM_NONCE_SIZE - 1],
+        ] {
+            assert!(matches!(
+                decrypt(&key, truncated).await,
+                Err(Error::Encryption(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_streams_with_the_wrong_key() {
+        let encrypted = encrypt(&[1; KEY_SIZE], b"level.dat").await;
+
+        assert!(matches!(
+            decrypt(&[2; KEY_SIZE], &encrypted).await,
+            Err(Error::Encryption(_))
+        ));
+    }
+
+    #[test]
+    fn checks_keys_against_the_key_check() {
+        let (encryption, key) = test_encryption("correct horse");
+        let key_check = STANDARD.decode(&encryption.key_check).unwrap();
+
+        assert_eq!(derive_key("correct horse", &encryption).unwrap(), key);
+        assert_eq!(decrypt_bytes(&key, &key_check).unwrap(), KEY_CHECK);
+
+        let wrong_key = derive_key("battery staple", &encryption).unwrap();
+        assert!(matches!(
+            decrypt_bytes(&wrong_key, &key_check),
+            Err(Error::Encryption(_))
+        ));
+        assert!(matches!(
+            decrypt_bytes(&key, &key_check[..NONCE_SIZE + TAG_SIZE - 1]),
+            Err(Error::Encryption(_))
+        ));
+    }
+}
@@ -0,0 +1,158 @@
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use log::{error, info};
+
+use crate::handlers::config::get_config_folder;
+use crate::handlers::config::instance::{
+    get_local_directories_config, get_minecraft_save_location,
+};
+use crate::handlers::progress::Progress;
+use crate::types::backup::InUsePolicy;
+use crate::types::error::Error;
+
+/// Java Edition locks `session.lock` for as long as a world is open, Bedrock
+/// Edition locks the LevelDB `LOCK` file.
+const LOCK_FILES: [&str; 2] = ["session.lock", "db/LOCK"];
+
+/// How often a waiting backup checks whether the world was closed.
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a backup waits for the world to be closed before giving up.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Asks whether another process could take a write lock on the file. The game
+/// locks with `fcntl`, so `flock` based checks would not see its locks.
+#[cfg(unix)]
+fn is_file_locked(path: &Path) -> io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = std::fs::File::open(path)?;
+
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+/// Windows locks are mandatory, so reading a locked file fails.
+#[cfg(windows)]
+fn is_file_locked(path: &Path) -> io::Result<bool> {
+    use std::io::Read;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    match std::fs::File::open(path).and_then(|mut file| file.read(&mut [0; 1])) {
+        Ok(_) => Ok(false),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+            ) =>
+        {
+            Ok(true)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_file_locked(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Returns whether the game currently has the world open.
+pub fn is_world_in_use(world_path: &Path) -> bool {
+    LOCK_FILES.iter().any(|lock_file| {
+        let lock_path = world_path.join(lock_file);
+
+        if !lock_path.exists() {
+            return false;
+        }
+
+        match is_file_locked(&lock_path) {
+            Ok(locked) => locked,
+            Err(e) => {
+                error!("Could not check lock on {:?}: {:?}", lock_path, e);
+                false
+            }
+        }
+    })
+}
+
+/// Returns whether the game has any world open in the default save folder or
+/// one of the configured instances.
+pub fn is_minecraft_running() -> bool {
+    let mut save_folders: Vec<_> = get_minecraft_save_location().into_iter().collect();
+
+    if let Ok(directory_settings) = get_local_directories_config(get_config_folder()) {
+        for vault_entries in directory_settings.categories.into_values() {
+            save_folders.extend(vault_entries.paths.into_values());
+        }
+    }
+
+    save_folders.iter().any(|save_folder| {
+        std::fs::read_dir(save_folder)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .any(|entry| entry.path().is_dir() && is_world_in_use(&entry.path()))
+            })
+            .unwrap_or(false)
+    })
+}
+
+/// Applies an `InUsePolicy` before a world is backed up. Returns whether the
+/// backup has to be taken while the world is open, in which case it is only a
+/// best-effort snapshot.
+pub async fn check_world_in_use(
+    world_path: &Path,
+    policy: InUsePolicy,
+    progress: &Progress,
+) -> Result<bool, Error> {
+    if !is_world_in_use(world_path) {
+        return Ok(false);
+    }
+
+    match policy {
+        InUsePolicy::Refuse => Err(Error::WorldInUse(format!(
+            "World {} is open in Minecraft, close it before backing up",
+            world_path.display()
+        ))),
+        InUsePolicy::Wait => {
+            info!("Waiting for {:?} to be closed", world_path);
+
+            let started = tokio::time::Instant::now();
+
+            while is_world_in_use(world_path) {
+                progress.check_cancelled()?;
+
+                if started.elapsed() >= WAIT_TIMEOUT {
+                    return Err(Error::WorldInUse(format!(
+                        "World {} is still open in Minecraft after {} minutes",
+                        world_path.display(),
+                        WAIT_TIMEOUT.as_secs() / 60
+                    )));
+                }
+
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            }
+
+            Ok(false)
+        }
+        InUsePolicy::BestEffort => {
+            info!(
+                "World {:?} is open, taking a best-effort snapshot",
+                world_path
+            );
+            Ok(true)
+        }
+    }
+}
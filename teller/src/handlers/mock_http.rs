@@ -0,0 +1,194 @@
+//! A minimal HTTP/1.1 server for testing the clients of remote vaults. Every
+//! request is answered by a handler and recorded, and every connection is
+//! closed after its response.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+#[derive(Clone, Debug)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn json(self, value: &serde_json::Value) -> Self {
+        self.header("Content-Type", "application/json")
+            .body(value.to_string())
+    }
+}
+
+pub(crate) struct MockServer {
+    /// Base url of the server, ending in a slash.
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    /// Requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Starts a server answering requests with `handler` until the test ends.
+pub(crate) async fn serve<F>(handler: F) -> MockServer
+where
+    F: Fn(&Request) -> Reply + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler = Arc::new(handler);
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let recorded = recorded.clone();
+            let handler = handler.clone();
+
+            tokio::spawn(answer_request(stream, move |request| {
+                recorded.lock().unwrap().push(request.clone());
+                handler(request)
+            }));
+        }
+    });
+
+    MockServer { url, requests }
+}
+
+/// A world version as the ChunkVault backend returns it.
+pub(crate) fn version_json(version: i32, backup_path: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": format!("version-{}", version),
+        "world_id": "6f2c0a3e-5d4b-4c8e-9a7f-1b2c3d4e5f60",
+        "version": version,
+        "backup_path": backup_path,
+        "created_at": "2023-10-01T12:00:00",
+        "difficulty": "normal",
+        "allow_cheats": false,
+        "difficulty_locked": false,
+        "spawn_x": 0,
+        "spawn_y": 64,
+        "spawn_z": 0,
+        "time": 1000,
+        "weather": "clear",
+        "hardcore": false,
+        "do_daylight_cycle": true,
+        "do_mob_spawning": true,
+        "do_weather_cycle": true,
+        "keep_inventory": false,
+        "size": 2048,
+        "level_name": "New World",
+        "additional_data": null
+    })
+}
+
+async fn answer_request(stream: TcpStream, answer: impl FnOnce(&Request) -> Reply) -> Option<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+
+    if request
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"))
+    {
+        loop {
+            let mut size = String::new();
+            stream.read_line(&mut size).await.ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+
+            let mut chunk = vec![0; size + 2];
+            stream.read_exact(&mut chunk).await.ok()?;
+
+            if size == 0 {
+                break;
+            }
+
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = request.header("Content-Length") {
+        let mut body = vec![0; length.parse().ok()?];
+        stream.read_exact(&mut body).await.ok()?;
+        request.body = body;
+    }
+
+    let reply = answer(&request);
+
+    let mut response = format!("HTTP/1.1 {} Mock\r\n", reply.status);
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        reply.body.len()
+    ));
+
+    let stream = stream.get_mut();
+    stream.write_all(response.as_bytes()).await.ok()?;
+    stream.write_all(&reply.body).await.ok()?;
+    stream.shutdown().await.ok()
+}
@@ -1,6 +1,24 @@
+pub mod archive;
+pub mod atomic;
 pub mod backup;
+pub mod catalog;
 pub mod config;
+pub mod encryption;
+pub mod lock;
+#[cfg(test)]
+pub(crate) mod mock_http;
 pub mod player;
+pub mod progress;
+pub mod queue;
+pub mod recovery;
+pub mod region;
+pub mod remote;
+pub mod retention;
+pub mod scheduler;
 pub mod search;
 pub mod snapshot;
+pub mod store;
+pub mod sync;
+pub mod transfer;
+pub mod vault;
 pub mod world;
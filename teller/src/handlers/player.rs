@@ -10,13 +10,16 @@ use crate::{
         search::worlds::is_minecraft_world,
         world::{parse_world_data, read_dat_file, GameType},
     },
-    types::player::{Item, PlayerData},
+    types::{
+        error::Error,
+        player::{Item, PlayerData},
+    },
 };
 
 pub async fn get_player_meta_from_uuid(
     client: reqwest::Client,
     player_uuid_str: String,
-) -> Result<Value, String> {
+) -> Result<Value, Error> {
     if player_uuid_str == *"~local_player" {
         let player_avatar =
             "https://crafthead.net/avatar/8667ba71b85a4004af54457a9734eed7?scale=32&overlay=false";
@@ -33,7 +36,7 @@ pub async fn get_player_meta_from_uuid(
 
     let player_uuid = match Uuid::parse_str(&player_uuid_str) {
         Ok(uuid) => uuid,
-        Err(_) => return Err("Error parsing UUID".to_string()),
+        Err(_) => return Err(Error::World("Error parsing UUID".to_string())),
     };
 
     let url = format!("https://playerdb.co/api/player/minecraft/{}", player_uuid);
@@ -54,7 +57,9 @@ pub async fn get_player_meta_from_uuid(
                     {
                         Ok(player.take())
                     } else {
-                        Err("Player data not found in response".to_string())
+                        Err(Error::Network(
+                            "Player data not found in response".to_string(),
+                        ))
                     }
                 } else {
                     let player_avatar = "https://crafthead.net/avatar/8667ba71b85a4004af54457a9734eed7?scale=32&overlay=false";
@@ -69,15 +74,15 @@ pub async fn get_player_meta_from_uuid(
                     Ok(player_meta)
                 }
             }
-            Err(_) => Err("Error parsing player data".to_string()),
+            Err(_) => Err(Error::Network("Error parsing player data".to_string())),
         },
-        Err(_) => Err("Error fetching player data".to_string()),
+        Err(_) => Err(Error::Network("Error fetching player data".to_string())),
     }
 }
 
 pub async fn get_players_meta_from_uuids(
     player_data_list: Vec<PlayerData>,
-) -> Result<HashMap<String, Value>, String> {
+) -> Result<HashMap<String, Value>, Error> {
     let mut player_data_map: HashMap<String, Value> = HashMap::new();
     let client = reqwest::Client::new();
 
@@ -95,7 +100,7 @@ pub async fn get_players_meta_from_uuids(
     Ok(player_data_map)
 }
 
-pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<PlayerData, String> {
+pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<PlayerData, Error> {
     info!("Grabbing player from UUID: {}", player_uuid);
 
     let game_type = is_minecraft_world(path);
@@ -118,10 +123,10 @@ pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<Play
             };
 
             if local_player_data.is_none() {
-                return Err("Failed to read player data".into());
+                return Err(Error::Nbt("Failed to read player data".to_string()));
             }
             let player_data = serde_json::to_value(local_player_data.unwrap())
-                .map_err(|e| format!("Failed to parse player data: {:?}", e))?;
+                .map_err(|e| Error::Nbt(format!("Failed to parse player data: {:?}", e)))?;
 
             let player_data = PlayerData {
                 id: player_uuid.to_owned(),
@@ -161,13 +166,15 @@ pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<Play
                 let level_dat_path = path.join("level.dat");
 
                 let level_data = read_dat_file(level_dat_path, game_type)
-                    .map_err(|e| format!("Failed to read level.dat: {:?}", e))?;
+                    .map_err(|e| Error::Nbt(format!("Failed to read level.dat: {:?}", e)))?;
 
                 let level_data = parse_world_data(level_data, game_type)?;
 
                 match level_data.get("Player") {
                     Some(data) => data.to_owned(),
-                    None => return Err("Could not find Player in level.dat".into()),
+                    None => {
+                        return Err(Error::Nbt("Could not find Player in level.dat".to_string()))
+                    }
                 }
             } else {
                 let player = path.join("playerdata").join(format!("{}.dat", player_uuid));
@@ -179,9 +186,9 @@ pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<Play
                     false,
                 ) {
                     Ok((_, data)) => serde_json::to_value(data)
-                        .map_err(|e| format!("Failed to parse player data: {:?}", e))?,
+                        .map_err(|e| Error::Nbt(format!("Failed to parse player data: {:?}", e)))?,
                     Err(e) => {
-                        return Err(format!("Failed to read player data: {:?}", e));
+                        return Err(Error::Nbt(format!("Failed to read player data: {:?}", e)));
                     }
                 }
             };
@@ -222,11 +229,11 @@ pub fn grab_player_from_uuid(player_uuid: String, path: &PathBuf) -> Result<Play
 
             Ok(player_data)
         }
-        GameType::None => Err("Game type not found".into()),
+        GameType::None => Err(Error::World("Game type not found".to_string())),
     }
 }
 
-pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<Value>, String> {
+pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<Value>, Error> {
     match game_type {
         GameType::Bedrock => {
             info!("Fetching Bedrock player data");
@@ -278,7 +285,9 @@ pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<
 
                 let player_data = match level_data.get("Player") {
                     Some(data) => data,
-                    None => return Err("Could not find Player in level.dat".into()),
+                    None => {
+                        return Err(Error::Nbt("Could not find Player in level.dat".to_string()))
+                    }
                 };
 
                 let player_uuid = match player_data.get("UUID") {
@@ -320,7 +329,7 @@ pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<
             let mut player_data = match fs::read_dir(&player_data_path).await {
                 Ok(data) => data,
                 Err(e) => {
-                    return Err(format!("Failed to read player data: {:?}", e));
+                    return Err(Error::Nbt(format!("Failed to read player data: {:?}", e)));
                 }
             };
 
@@ -329,7 +338,7 @@ pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<
             while let Some(player) = player_data
                 .next_entry()
                 .await
-                .map_err(|e| format!("Failed to read player data: {:?}", e))?
+                .map_err(|e| Error::Nbt(format!("Failed to read player data: {:?}", e)))?
             {
                 let player = player.path();
 
@@ -357,7 +366,7 @@ pub async fn get_player_data(path: &PathBuf, game_type: GameType) -> Result<Vec<
 
             Ok(all_players)
         }
-        GameType::None => Err("Game type not specified".into()),
+        GameType::None => Err(Error::World("Game type not specified".to_string())),
     }
 }
 
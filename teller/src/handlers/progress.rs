@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::error::Error;
+use crate::types::progress::{ProgressEvent, ProgressStage};
+
+/// Shortest time between two reports of the same stage, so that worlds with
+/// many small files do not flood the sink.
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+struct ProgressState {
+    stage: ProgressStage,
+    done: u64,
+    total: u64,
+    last_report: Option<Instant>,
+}
+
+/// Reports the progress of a long-running operation and lets its caller
+/// cancel it. Clones share their state, so one can be handed to the
+/// operation while another is kept to cancel it. The default reports nowhere.
+#[derive(Clone)]
+pub struct Progress {
+    sink: Option<Arc<dyn Fn(ProgressEvent) + Send + Sync>>,
+    cancelled: Arc<AtomicBool>,
+    state: Arc<Mutex<ProgressState>>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            state: Arc::new(Mutex::new(ProgressState {
+                stage: ProgressStage::Measuring,
+                done: 0,
+                total: 0,
+                last_report: None,
+            })),
+        }
+    }
+}
+
+impl Progress {
+    pub fn new<F>(sink: F) -> Self
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        Self {
+            sink: Some(Arc::new(sink)),
+            ..Default::default()
+        }
+    }
+
+    /// Starts a new stage of the operation, which always gets reported.
+    pub fn start(&self, stage: ProgressStage, total: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.stage = stage;
+            state.done = 0;
+            state.total = total;
+            state.last_report = None;
+        }
+
+        self.report(true);
+    }
+
+    pub fn advance(&self, bytes: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.done += bytes;
+        }
+
+        self.report(false);
+    }
+
+    fn report(&self, force: bool) {
+        let sink = match &self.sink {
+            Some(sink) => sink,
+            None => return,
+        };
+
+        let event = match self.state.lock() {
+            Ok(mut state) => {
+                let due = match state.last_report {
+                    Some(last_report) => last_report.elapsed() >= REPORT_INTERVAL,
+                    None => true,
+                };
+
+                if !force && !due {
+                    return;
+                }
+
+                state.last_report = Some(Instant::now());
+
+                ProgressEvent {
+                    stage: state.stage,
+                    done: state.done,
+                    total: state.total,
+                }
+            }
+            Err(_) => return,
+        };
+
+        sink(event);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns `Error::Cancelled` once the operation was cancelled, for use
+    /// with `?` between steps.
+    pub fn check_cancelled(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{error, info};
+use tokio::sync::{Mutex, Notify};
+
+use crate::handlers::backup::get_backup_meta_from_path;
+use crate::handlers::catalog::record_snapshot;
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::config::get_config_folder;
+use crate::handlers::progress::Progress;
+use crate::handlers::retention::{prune_vault, prune_world};
+use crate::handlers::sync::sync_vaults;
+use crate::handlers::vault::{snapshot_file_name, vault_backend};
+use crate::types::error::Error;
+use crate::types::queue::{JobQueue, JobStatus, QueueEvent, QueuedJob, QueuedTask};
+
+use super::atomic::write_atomic;
+
+const QUEUE_FILE: &str = "job_queue.json";
+
+/// Folder in the config folder snapshots wait in until they are uploaded.
+const QUEUE_DIR: &str = "queue";
+
+/// A job that failed this many times is marked failed and left for the user.
+const MAX_ATTEMPTS: u32 = 8;
+
+/// Seconds before the first retry of a failed job, doubled for every attempt
+/// after it up to `MAX_RETRY_DELAY`.
+const RETRY_DELAY: i64 = 30;
+const MAX_RETRY_DELAY: i64 = 60 * 60;
+
+/// Longest time the queue sleeps before looking for due jobs again, when
+/// nothing is queued in the meantime.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Serializes changes to the queue file between the runner and callers.
+static QUEUE_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+
+/// Wakes the runner when a job is queued or retried.
+static QUEUE_CHANGED: OnceLock<Notify> = OnceLock::new();
+
+/// The job the runner is working on and its progress, to cancel it.
+static ACTIVE_JOB: OnceLock<std::sync::Mutex<Option<(String, Progress)>>> = OnceLock::new();
+
+fn queue_lock() -> &'static Mutex<()> {
+    QUEUE_LOCK.get_or_init(|| Mutex::new(()))
+}
+
+fn queue_changed() -> &'static Notify {
+    QUEUE_CHANGED.get_or_init(Notify::new)
+}
+
+fn active_job() -> &'static std::sync::Mutex<Option<(String, Progress)>> {
+    ACTIVE_JOB.get_or_init(|| std::sync::Mutex::new(None))
+}
+
+async fn get_queue_dir() -> PathBuf {
+    let queue_dir = get_config_folder().join(QUEUE_DIR);
+
+    if !queue_dir.exists() {
+        let _ = tokio::fs::create_dir_all(&queue_dir).await;
+    }
+
+    queue_dir
+}
+
+/// A new path in the queue folder for a snapshot waiting to be uploaded.
+pub(crate) async fn staged_snapshot_path() -> PathBuf {
+    get_queue_dir()
+        .await
+        .join(snapshot_file_name(&uuid::Uuid::new_v4().to_string()))
+}
+
+async fn load_queue() -> JobQueue {
+    let queue_path = get_config_folder().join(QUEUE_FILE);
+
+    match tokio::fs::read_to_string(&queue_path).await {
+        Ok(queue) => serde_json::from_str(&queue).unwrap_or_else(|e| {
+            error!("Could not parse job queue at {:?}: {:?}", queue_path, e);
+            JobQueue::default()
+        }),
+        Err(_) => JobQueue::default(),
+    }
+}
+
+async fn save_queue(queue: &JobQueue) -> Result<(), Error> {
+    let queue_path = get_config_folder().join(QUEUE_FILE);
+
+    let data = serde_json::to_vec_pretty(queue)
+        .map_err(|e| Error::Config(format!("Could not serialize job queue: {:?}", e)))?;
+
+    write_atomic(&queue_path, &data)
+        .await
+        .map_err(|e| Error::io(format!("Could not write job queue at {:?}", queue_path), e))
+}
+
+/// Applies a change to the stored queue, holding the lock from reading it to
+/// writing it back.
+async fn update_queue<T>(change: impl FnOnce(&mut JobQueue) -> T) -> Result<T, Error> {
+    let _guard = queue_lock().lock().await;
+
+    let mut queue = load_queue().await;
+    let result = change(&mut queue);
+    save_queue(&queue).await?;
+
+    Ok(result)
+}
+
+fn staged_source(task: &QueuedTask) -> Option<&Path> {
+    match task {
+        QueuedTask::Upload { source, .. } => Some(source),
+        _ => None,
+    }
+}
+
+async fn remove_staged_source(task: &QueuedTask) {
+    if let Some(source) = staged_source(task) {
+        if let Err(e) = tokio::fs::remove_file(source).await {
+            error!("Failed to remove queued backup {}: {}", source.display(), e);
+        }
+    }
+}
+
+pub async fn enqueue(task: QueuedTask) -> Result<QueuedJob, Error> {
+    let now = Utc::now().timestamp();
+
+    let job = QueuedJob {
+        id: uuid::Uuid::new_v4().to_string(),
+        task,
+        status: JobStatus::Queued,
+        attempts: 0,
+        created: now,
+        next_attempt: now,
+        last_error: None,
+    };
+
+    update_queue(|queue| queue.jobs.push(job.clone())).await?;
+
+    info!("Queued job {}", job.id);
+    queue_changed().notify_one();
+
+    Ok(job)
+}
+
+/// Queues the upload of a snapshot that was written to
+/// `staged_snapshot_path`. The queue owns the file from then on.
+pub(crate) async fn enqueue_upload(
+    world_id: &str,
+    snapshot_id: &str,
+    vault_id: &str,
+    staged: PathBuf,
+) -> Result<QueuedJob, Error> {
+    let task = QueuedTask::Upload {
+        world_id: world_id.to_string(),
+        snapshot_id: snapshot_id.to_string(),
+        vault_id: vault_id.to_string(),
+        source: staged.clone(),
+    };
+
+    match enqueue(task).await {
+        Ok(job) => Ok(job),
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&staged).await;
+            Err(e)
+        }
+    }
+}
+
+/// Queued, active and failed jobs, in the order they were queued.
+pub async fn list_jobs() -> Vec<QueuedJob> {
+    let _guard = queue_lock().lock().await;
+
+    load_queue().await.jobs
+}
+
+/// Runs a queued or failed job again right away, with a fresh set of
+/// attempts.
+pub async fn retry_job(job_id: &str) -> Result<(), Error> {
+    let now = Utc::now().timestamp();
+
+    let found = update_queue(|queue| {
+        match queue
+            .jobs
+            .iter_mut()
+            .find(|job| job.id == job_id && job.status != JobStatus::Active)
+        {
+            Some(job) => {
+                job.status = JobStatus::Queued;
+                job.attempts = 0;
+                job.next_attempt = now;
+                true
+            }
+            None => false,
+        }
+    })
+    .await?;
+
+    if !found {
+        return Err(Error::JobNotFound(format!(
+            "No queued or failed job {}",
+            job_id
+        )));
+    }
+
+    queue_changed().notify_one();
+
+    Ok(())
+}
+
+/// Removes a job from the queue. An active job is stopped, uploads that can
+/// resume keep what they sent so far.
+pub async fn cancel_job(job_id: &str) -> Result<(), Error> {
+    let removed = update_queue(|queue| {
+        let index = queue.jobs.iter().position(|job| job.id == job_id)?;
+        Some(queue.jobs.remove(index))
+    })
+    .await?;
+
+    let job = match removed {
+        Some(job) => job,
+        None => return Err(Error::JobNotFound(format!("No job {}", job_id))),
+    };
+
+    info!("Cancelled job {}", job_id);
+
+    // The runner cleans up after an active job once it stopped.
+    if job.status == JobStatus::Active {
+        if let Ok(active) = active_job().lock() {
+            if let Some((active_id, progress)) = active.as_ref() {
+                if active_id == job_id {
+                    progress.cancel();
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    remove_staged_source(&job.task).await;
+
+    Ok(())
+}
+
+async fn upload_staged(
+    world_id: &str,
+    snapshot_id: &str,
+    vault_id: &str,
+    source: &Path,
+) -> Result<(), Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let metadata = get_backup_meta_from_path(source.to_path_buf()).await.ok();
+
+    let vault = vault_backend(&backup_settings, vault_id)?;
+
+    vault.put_snapshot(world_id, snapshot_id, source).await?;
+
+    record_snapshot(
+        vault_id,
+        vault.as_ref(),
+        backup_settings.vault_storage(vault_id),
+        world_id,
+        snapshot_id,
+        metadata,
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn run_task(task: &QueuedTask, progress: &Progress) -> Result<(), Error> {
+    match task {
+        QueuedTask::Upload {
+            world_id,
+            snapshot_id,
+            vault_id,
+            source,
+        } => upload_staged(world_id, snapshot_id, vault_id, source).await,
+        QueuedTask::Replicate {
+            source,
+            destination,
+            worlds,
+            mirror,
+        } => {
+            let report =
+                sync_vaults(source, destination, worlds.as_deref(), *mirror, progress).await?;
+
+            if report.cancelled {
+                return Err(Error::Cancelled);
+            }
+
+            match report.failed.len() {
+                0 => Ok(()),
+                failed => Err(Error::Vault(format!(
+                    "{} backups could not be synced to vault {}",
+                    failed, destination
+                ))),
+            }
+        }
+        QueuedTask::Prune {
+            vault_id,
+            world_id: Some(world_id),
+        } => prune_world(vault_id, world_id, false).await.map(|_| ()),
+        QueuedTask::Prune {
+            vault_id,
+            world_id: None,
+        } => prune_vault(vault_id, false).await.map(|_| ()),
+    }
+}
+
+async fn wait_cancelled(progress: &Progress) {
+    while !progress.is_cancelled() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+fn retry_delay(attempts: u32) -> i64 {
+    RETRY_DELAY
+        .saturating_mul(1 << attempts.saturating_sub(1).min(20))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Marks the first due job active and returns it.
+async fn take_due_job() -> Result<Option<QueuedJob>, Error> {
+    let now = Utc::now().timestamp();
+
+    update_queue(|queue| {
+        let job = queue
+            .jobs
+            .iter_mut()
+            .find(|job| job.status == JobStatus::Queued && job.next_attempt <= now)?;
+
+        job.status = JobStatus::Active;
+        Some(job.clone())
+    })
+    .await
+}
+
+async fn run_job<F>(job: QueuedJob, on_event: &F)
+where
+    F: Fn(QueueEvent) + Send + Sync,
+{
+    info!("Running job {}", job.id);
+
+    on_event(QueueEvent::JobStarted {
+        job_id: job.id.clone(),
+    });
+
+    let progress = Progress::default();
+
+    if let Ok(mut active) = active_job().lock() {
+        *active = Some((job.id.clone(), progress.clone()));
+    }
+
+    let result = tokio::select! {
+        result = run_task(&job.task, &progress) => result,
+        _ = wait_cancelled(&progress) => Err(Error::Cancelled),
+    };
+
+    if let Ok(mut active) = active_job().lock() {
+        *active = None;
+    }
+
+    let error = result.err();
+    let now = Utc::now().timestamp();
+
+    let finished = update_queue(|queue| {
+        let index = match queue.jobs.iter().position(|queued| queued.id == job.id) {
+            Some(index) => index,
+            // Cancelled while it ran.
+            None => return true,
+        };
+
+        let e = match &error {
+            Some(e) => e,
+            None => {
+                queue.jobs.remove(index);
+                return true;
+            }
+        };
+
+        let queued = &mut queue.jobs[index];
+        queued.attempts += 1;
+        queued.last_error = Some(e.to_string());
+
+        match queued.attempts >= MAX_ATTEMPTS {
+            true => queued.status = JobStatus::Failed,
+            false => {
+                queued.status = JobStatus::Queued;
+                queued.next_attempt = now + retry_delay(queued.attempts);
+            }
+        }
+
+        false
+    })
+    .await;
+
+    match finished {
+        Ok(true) => remove_staged_source(&job.task).await,
+        Ok(false) => {}
+        Err(e) => error!("Could not update job queue: {}", e),
+    }
+
+    if let Some(e) = &error {
+        error!("Job {} failed: {}", job.id, e);
+    }
+
+    on_event(QueueEvent::JobFinished {
+        job_id: job.id,
+        error: error.map(|e| e.to_string()),
+    });
+}
+
+/// Works through the job queue one job at a time for as long as the returned
+/// future is polled. Failed jobs are retried with exponential backoff, and
+/// jobs that were running when the app stopped run again on start.
+pub async fn run_queue<F>(on_event: F)
+where
+    F: Fn(QueueEvent) + Send + Sync,
+{
+    let restarted = update_queue(|queue| {
+        for job in queue
+            .jobs
+            .iter_mut()
+            .filter(|job| job.status == JobStatus::Active)
+        {
+            job.status = JobStatus::Queued;
+        }
+    })
+    .await;
+
+    if let Err(e) = restarted {
+        error!("Could not restore job queue: {}", e);
+    }
+
+    info!("Starting job queue");
+
+    loop {
+        match take_due_job().await {
+            Ok(Some(job)) => run_job(job, &on_event).await,
+            Ok(None) => {
+                tokio::select! {
+                    _ = queue_changed().notified() => {}
+                    _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                }
+            }
+            Err(e) => {
+                error!("Could not read job queue: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
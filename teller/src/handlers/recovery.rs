@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{error, info, warn};
+use tokio::fs;
+
+use crate::types::backup::RecoveryReport;
+use crate::types::error::Error;
+
+use super::archive::open_snapshot;
+use super::atomic::PARTIAL_SUFFIX;
+use super::backup::{get_default_vault, get_temp_dir};
+use super::config::backup::get_backup_config;
+use super::store::OBJECTS_DIR;
+use super::vault::SNAPSHOT_SUFFIX;
+
+/// Temp files written by the object store before partial files existed.
+const LEGACY_TEMP_SUFFIX: &str = ".tmp";
+
+/// Files that were modified after recovery started belong to a backup that is
+/// running right now, and are left alone.
+async fn is_stale(path: &Path, started: SystemTime) -> bool {
+    match fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+    {
+        Ok(modified) => modified < started,
+        Err(_) => false,
+    }
+}
+
+async fn remove_file(path: PathBuf, removed: &mut Vec<PathBuf>) {
+    match fs::remove_file(&path).await {
+        Ok(_) => removed.push(path),
+        Err(e) => error!("Failed to remove {}: {}", path.display(), e),
+    }
+}
+
+async fn read_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Could not read {} during recovery: {}", dir.display(), e);
+            return files;
+        }
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        files.push(entry.path());
+    }
+
+    files
+}
+
+/// Removes leftovers of interrupted writes from one folder of a vault. Only
+/// snapshots whose archive cannot be read at all count as half written; a
+/// snapshot that opens is left for verification to judge.
+async fn recover_folder(dir: &Path, started: SystemTime, report: &mut RecoveryReport) {
+    for path in read_files(dir).await {
+        if !path.is_file() || !is_stale(&path, started).await {
+            continue;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        if name.ends_with(PARTIAL_SUFFIX) {
+            remove_file(path, &mut report.temp_files).await;
+        } else if name.ends_with(SNAPSHOT_SUFFIX) {
+            if let Err(Error::SnapshotFormat(e)) = open_snapshot(&path).await {
+                warn!("Removing half written snapshot: {}", e);
+                remove_file(path, &mut report.snapshots).await;
+            }
+        }
+    }
+}
+
+async fn recover_object_store(
+    objects_dir: &Path,
+    started: SystemTime,
+    report: &mut RecoveryReport,
+) {
+    for path in read_files(objects_dir).await {
+        if path.is_dir() {
+            recover_folder(&path, started, report).await;
+            continue;
+        }
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        if (name.ends_with(PARTIAL_SUFFIX) || name.ends_with(LEGACY_TEMP_SUFFIX))
+            && is_stale(&path, started).await
+        {
+            remove_file(path, &mut report.temp_files).await;
+        }
+    }
+}
+
+async fn recover_vault(vault_path: &Path, started: SystemTime, report: &mut RecoveryReport) {
+    info!("Recovering vault {}", vault_path.display());
+
+    recover_folder(vault_path, started, report).await;
+
+    for path in read_files(vault_path).await {
+        if !path.is_dir() {
+            continue;
+        }
+
+        match path.file_name() {
+            Some(name) if name == OBJECTS_DIR => recover_object_store(&path, started, report).await,
+            Some(name) if name == "temp" => {}
+            _ => recover_folder(&path, started, report).await,
+        }
+    }
+}
+
+/// Cleans up after backups that were interrupted by a crash or power loss:
+/// everything left in the temp folder, partial files in the vaults, and
+/// snapshots that were cut off before vault writes became atomic. Meant to be
+/// run once on startup.
+pub async fn recover_vaults() -> RecoveryReport {
+    let started = SystemTime::now();
+    let mut report = RecoveryReport::default();
+
+    for path in read_files(&get_temp_dir().await).await {
+        if path.is_file() && is_stale(&path, started).await {
+            remove_file(path, &mut report.temp_files).await;
+        }
+    }
+
+    let mut vaults = vec![get_default_vault().await];
+
+    match get_backup_config().await {
+        Ok(backup_settings) => {
+            for vault_path in backup_settings.vaults.into_values() {
+                if !vaults.contains(&vault_path) {
+                    vaults.push(vault_path);
+                }
+            }
+        }
+        Err(e) => error!("Could not read vaults to recover: {}", e),
+    }
+
+    for vault_path in vaults {
+        if vault_path.is_dir() {
+            recover_vault(&vault_path, started, &mut report).await;
+        }
+    }
+
+    info!(
+        "Recovery removed {} temp files and {} half written snapshots",
+        report.temp_files.len(),
+        report.snapshots.len()
+    );
+
+    report
+}
@@ -0,0 +1,200 @@
+use std::{ffi::OsStr, path::Path};
+
+use crate::types::backup::RegionChunk;
+
+pub const SECTOR_SIZE: u64 = 4096;
+pub const HEADER_SIZE: usize = 2 * SECTOR_SIZE as usize;
+pub const CHUNKS_PER_REGION: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkLocation {
+    pub index: usize,
+    pub offset: u32,
+    pub sectors: u8,
+    pub timestamp: u32,
+}
+
+pub fn is_region_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("mca"))
+}
+
+/// Reads the location and timestamp tables of an Anvil region header,
+/// returning only the chunks that are present in the region.
+pub fn parse_region_header(header: &[u8; HEADER_SIZE]) -> Vec<ChunkLocation> {
+    let mut locations = Vec::new();
+
+    for index in 0..CHUNKS_PER_REGION {
+        let location = &header[index * 4..index * 4 + 4];
+        let offset = u32::from_be_bytes([0, location[0], location[1], location[2]]);
+        let sectors = location[3];
+
+        if offset == 0 && sectors == 0 {
+            continue;
+        }
+
+        let timestamp_start = SECTOR_SIZE as usize + index * 4;
+        let timestamp = u32::from_be_bytes([
+            header[timestamp_start],
+            header[timestamp_start + 1],
+            header[timestamp_start + 2],
+            header[timestamp_start + 3],
+        ]);
+
+        locations.push(ChunkLocation {
+            index,
+            offset,
+            sectors,
+            timestamp,
+        });
+    }
+
+    locations
+}
+
+/// Writes the header of a region holding `chunks`. Chunks with an index
+/// outside of the region are left out.
+pub fn write_region_header(chunks: &[RegionChunk]) -> Vec<u8> {
+    let mut header = vec![0; HEADER_SIZE];
+
+    for chunk in chunks
+        .iter()
+        .filter(|chunk| chunk.index < CHUNKS_PER_REGION)
+    {
+        let location = (chunk.offset << 8 | chunk.sectors as u32).to_be_bytes();
+        header[chunk.index * 4..chunk.index * 4 + 4].copy_from_slice(&location);
+
+        let timestamp_start = SECTOR_SIZE as usize + chunk.index * 4;
+        header[timestamp_start..timestamp_start + 4]
+            .copy_from_slice(&chunk.timestamp.to_be_bytes());
+    }
+
+    header
+}
+
+/// Checks that a chunk's sectors lie after the header and inside the file.
+pub fn is_valid_location(location: &ChunkLocation, file_size: u64) -> bool {
+    location.offset >= 2
+        && location.sectors > 0
+        && (location.offset as u64 + location.sectors as u64) * SECTOR_SIZE <= file_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(index: usize, offset: u32, sectors: u8, timestamp: u32) -> RegionChunk {
+        RegionChunk {
+            index,
+            offset,
+            sectors,
+            timestamp,
+            hash: String::new(),
+        }
+    }
+
+    fn header_of(chunks: &[RegionChunk]) -> [u8; HEADER_SIZE] {
+        write_region_header(chunks).try_into().unwrap()
+    }
+
+    #[test]
+    fn empty_header_has_no_chunks() {
+        assert!(parse_region_header(&[0; HEADER_SIZE]).is_empty());
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let chunks = [
+            chunk(0, 2, 1, 1_700_000_000),
+            chunk(31, 3, 2, 42),
+            chunk(CHUNKS_PER_REGION - 1, 0x00FF_FFFF, 255, u32::MAX),
+        ];
+
+        let locations = parse_region_header(&header_of(&chunks));
+
+        let expected: Vec<ChunkLocation> = chunks
+            .iter()
+            .map(|chunk| ChunkLocation {
+                index: chunk.index,
+                offset: chunk.offset,
+                sectors: chunk.sectors,
+                timestamp: chunk.timestamp,
+            })
+            .collect();
+
+        assert_eq!(locations, expected);
+    }
+
+    #[test]
+    fn keeps_chunks_with_only_an_offset_or_sector_count() {
+        let mut header = [0; HEADER_SIZE];
+        header[2] = 5;
+        header[7] = 1;
+
+        let locations = parse_region_header(&header);
+
+        assert_eq!(locations.len(), 2);
+        assert_eq!((locations[0].index, locations[0].offset), (0, 5));
+        assert_eq!((locations[1].index, locations[1].sectors), (1, 1));
+    }
+
+    #[test]
+    fn garbage_headers_do_not_panic() {
+        let header = [0xFF; HEADER_SIZE];
+        let locations = parse_region_header(&header);
+
+        assert_eq!(locations.len(), CHUNKS_PER_REGION);
+        assert!(locations
+            .iter()
+            .all(|location| !is_valid_location(location, 1 << 30)));
+    }
+
+    #[test]
+    fn leaves_out_chunks_outside_of_region() {
+        let header = write_region_header(&[
+            chunk(CHUNKS_PER_REGION, 2, 1, 7),
+            chunk(usize::MAX, 2, 1, 7),
+        ]);
+
+        assert_eq!(header.len(), HEADER_SIZE);
+        assert!(header.iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn checks_chunk_locations() {
+        let file_size = 4 * SECTOR_SIZE;
+
+        let cases = [
+            ("inside the file", 2, 2, true),
+            ("inside the header", 1, 1, false),
+            ("no sectors", 2, 0, false),
+            ("past the end", 3, 2, false),
+            ("offset past the end", 5, 1, false),
+            ("largest offset", 0x00FF_FFFF, 255, false),
+        ];
+
+        for (case, offset, sectors, valid) in cases {
+            let location = ChunkLocation {
+                index: 0,
+                offset,
+                sectors,
+                timestamp: 0,
+            };
+
+            assert_eq!(is_valid_location(&location, file_size), valid, "{}", case);
+        }
+    }
+
+    #[test]
+    fn recognizes_region_files() {
+        let cases = [
+            ("region/r.0.0.mca", true),
+            ("region/r.0.0.mcr", false),
+            ("level.dat", false),
+            ("mca", false),
+        ];
+
+        for (path, expected) in cases {
+            assert_eq!(is_region_file(Path::new(path)), expected, "{}", path);
+        }
+    }
+}
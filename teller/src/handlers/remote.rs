@@ -0,0 +1,440 @@
+use std::path::Path;
+
+use log::info;
+use reqwest::{Client, Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::types::backup::RemoteBackup;
+use crate::types::error::Error;
+use crate::types::world::{World, WorldVersion};
+
+use super::atomic::{commit_file, discard_partial, partial_path};
+use super::config::backup::get_backup_config;
+use super::transfer::{shared_throttle, throttled_body, Throttle};
+
+/// Header carrying the id of an uploaded snapshot, so the backend can keep the
+/// local snapshot name.
+const SNAPSHOT_ID_HEADER: &str = "X-Snapshot-Id";
+
+/// Client for remote vaults hosted by the ChunkVault backend.
+///
+/// Every request is authorized with the vault's API key as a bearer token.
+/// The backend exposes:
+///
+/// - `GET /worlds` lists the worlds in the vault.
+/// - `GET /worlds/{world_id}/versions` lists the versions of a world.
+/// - `POST /worlds/{world_id}/versions` uploads a snapshot as the next
+///   version. The world is created on its first upload from the snapshot
+///   metadata.
+/// - `GET /worlds/{world_id}/versions/{version}/download` returns a snapshot.
+/// - `DELETE /worlds/{world_id}/versions/{version}` removes a version.
+pub struct RemoteVaultClient {
+    client: Client,
+    base_url: Url,
+    api_key: String,
+    throttle: Throttle,
+}
+
+impl RemoteVaultClient {
+    pub fn new(remote: &RemoteBackup) -> Result<Self, Error> {
+        Self::with_client(Client::new(), remote)
+    }
+
+    /// Uses the given HTTP client, for example one with a timeout or proxy.
+    pub fn with_client(client: Client, remote: &RemoteBackup) -> Result<Self, Error> {
+        let base_url = Url::parse(&remote.remote_url).map_err(|e| {
+            Error::Config(format!(
+                "Invalid remote vault url {}: {}",
+                remote.remote_url, e
+            ))
+        })?;
+
+        if base_url.cannot_be_a_base() {
+            return Err(Error::Config(format!(
+                "Invalid remote vault url {}",
+                remote.remote_url
+            )));
+        }
+
+        Ok(Self {
+            client,
+            base_url,
+            api_key: remote.api_key.clone(),
+            throttle: Throttle::default(),
+        })
+    }
+
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    /// Looks up a remote vault by id in the backup settings.
+    pub async fn from_settings(vault: &str) -> Result<Self, Error> {
+        let backup_settings = get_backup_config().await?;
+
+        match backup_settings.remote_vaults.get(vault) {
+            Some(remote) => {
+                Ok(Self::new(remote)?.with_throttle(shared_throttle(&backup_settings.bandwidth)))
+            }
+            None => Err(Error::VaultNotFound(format!(
+                "Remote vault {} does not exist.",
+                vault
+            ))),
+        }
+    }
+
+    fn endpoint(&self, segments: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+
+        if let Ok(mut path) = url.path_segments_mut() {
+            path.pop_if_empty().extend(segments);
+        }
+
+        url
+    }
+
+    async fn check_response(response: Response) -> Result<Response, Error> {
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let url = response.url().clone();
+        let body = response.text().await.unwrap_or_default();
+
+        Err(match status {
+            StatusCode::NOT_FOUND => {
+                Error::SnapshotNotFound(format!("{} was not found on the remote vault", url))
+            }
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Error::Vault(format!("Remote vault rejected the API key: {}", body))
+            }
+            _ => Error::Network(format!(
+                "Remote vault returned {} for {}: {}",
+                status, url, body
+            )),
+        })
+    }
+
+    pub async fn list_worlds(&self) -> Result<Vec<World>, Error> {
+        let response = self
+            .client
+            .get(self.endpoint(&["worlds"]))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+
+        Ok(Self::check_response(response).await?.json().await?)
+    }
+
+    pub async fn list_versions(&self, world_id: &str) -> Result<Vec<WorldVersion>, Error> {
+        let response = self
+            .client
+            .get(self.endpoint(&["worlds", world_id, "versions"]))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+
+        Ok(Self::check_response(response).await?.json().await?)
+    }
+
+    /// Streams a snapshot to the backend, which stores it as the next version
+    /// of the world. The backend only takes a snapshot in a single request
+    /// and cannot continue one, so unlike uploads to S3, SFTP and WebDAV
+    /// vaults an interrupted upload cannot resume from its last part. It is
+    /// throttled all the same, and sent again whole when its job is retried.
+    pub async fn upload_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        snapshot_path: &Path,
+    ) -> Result<WorldVersion, Error> {
+        let file = File::open(snapshot_path)
+            .await
+            .map_err(|e| Error::io(format!("Failed to open {}", snapshot_path.display()), e))?;
+        let size = file.metadata().await?.len();
+
+        info!(
+            "Uploading snapshot {} of {} to remote vault",
+            snapshot_id, world_id
+        );
+
+        let response = self
+            .client
+            .post(self.endpoint(&["worlds", world_id, "versions"]))
+            .bearer_auth(&self.api_key)
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header(reqwest::header::CONTENT_LENGTH, size)
+            .header(SNAPSHOT_ID_HEADER, snapshot_id)
+            .body(throttled_body(file, self.throttle.clone()))
+            .send()
+            .await?;
+
+        Ok(Self::check_response(response).await?.json().await?)
+    }
+
+    /// Starts downloading a version, for reading it as a stream.
+    pub async fn request_version(&self, world_id: &str, version: i32) -> Result<Response, Error> {
+        let version = version.to_string();
+
+        let response = self
+            .client
+            .get(self.endpoint(&["worlds", world_id, "versions", &version, "download"]))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+
+        Self::check_response(response).await
+    }
+
+    /// Downloads a version into `destination`, which only appears once the
+    /// whole snapshot has been received.
+    pub async fn download_version(
+        &self,
+        world_id: &str,
+        version: i32,
+        destination: &Path,
+    ) -> Result<(), Error> {
+        let mut response = self.request_version(world_id, version).await?;
+
+        let partial = partial_path(destination);
+
+        let downloaded = async {
+            let mut file = File::create(&partial).await?;
+
+            while let Some(chunk) = response.chunk().await? {
+                file.write_all(&chunk).await?;
+            }
+
+            file.flush().await?;
+            drop(file);
+
+            commit_file(&partial, destination).await?;
+
+            Ok(())
+        }
+        .await;
+
+        if downloaded.is_err() {
+            discard_partial(&partial).await;
+        }
+
+        downloaded
+    }
+
+    pub async fn delete_version(&self, world_id: &str, version: i32) -> Result<(), Error> {
+        let response = self
+            .client
+            .delete(self.endpoint(&["worlds", world_id, "versions", &version.to_string()]))
+            .bearer_auth(&self.api_key)
+            .send()
+            .await?;
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::handlers::mock_http::{serve, version_json, Reply};
+
+    use super::*;
+
+    fn remote(url: &str) -> RemoteBackup {
+        RemoteBackup {
+            remote_url: url.to_string(),
+            api_key: "secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn rejects_urls_that_cannot_be_a_base() {
+        assert!(RemoteVaultClient::new(&remote("not a url")).is_err());
+        assert!(RemoteVaultClient::new(&remote("mailto:vault@example.com")).is_err());
+        assert!(RemoteVaultClient::new(&remote("https://vault.example.com/api")).is_ok());
+    }
+
+    #[test]
+    fn joins_endpoints_to_the_base_path() {
+        let client = RemoteVaultClient::new(&remote("https://vault.example.com/api/")).unwrap();
+
+        assert_eq!(
+            client.endpoint(&["worlds", "a b", "versions"]).as_str(),
+            "https://vault.example.com/api/worlds/a%20b/versions"
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_worlds() {
+        let server = serve(|_| {
+            Reply::new(200).json(&json!([{
+                "id": "6f2c0a3e-5d4b-4c8e-9a7f-1b2c3d4e5f60",
+                "user_id": "0d9e8f7a-6b5c-4d3e-8f1a-2b3c4d5e6f70",
+                "name": "New World",
+                "seed": -42,
+                "current_version": 3,
+                "edition": "java",
+                "createdAt": "2023-10-01T12:00:00",
+                "updatedAt": null
+            }]))
+        })
+        .await;
+
+        let client = RemoteVaultClient::new(&remote(&format!("{}api", server.url))).unwrap();
+        let worlds = client.list_worlds().await.unwrap();
+
+        assert_eq!(worlds.len(), 1);
+        assert_eq!(worlds[0].name, "New World");
+        assert_eq!(worlds[0].current_version, 3);
+        assert!(worlds[0].updated_at.is_none());
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path, "/api/worlds");
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer secret"));
+    }
+
+    #[tokio::test]
+    async fn decodes_versions() {
+        let server = serve(|_| {
+            Reply::new(200).json(&json!([
+                version_json(1, "worlds/1.zip"),
+                version_json(2, "worlds/2.zip")
+            ]))
+        })
+        .await;
+
+        let client = RemoteVaultClient::new(&remote(&server.url)).unwrap();
+        let versions = client.list_versions("world").await.unwrap();
+
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(server.requests()[0].path, "/worlds/world/versions");
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_bodies() {
+        let server = serve(|_| Reply::new(200).json(&json!({ "worlds": [] }))).await;
+
+        let client = RemoteVaultClient::new(&remote(&server.url)).unwrap();
+
+        assert!(client.list_worlds().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn maps_error_statuses() {
+        let server = serve(|request| match request.path.as_str() {
+            "/worlds/missing/versions" => Reply::new(404),
+            "/worlds/locked/versions" => Reply::new(401).body("bad key"),
+            _ => Reply::new(503).body("maintenance"),
+        })
+        .await;
+
+        let client = RemoteVaultClient::new(&remote(&server.url)).unwrap();
+
+        assert!(matches!(
+            client.list_versions("missing").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+        assert!(matches!(
+            client.list_versions("locked").await,
+            Err(Error::Vault(message)) if message.contains("bad key")
+        ));
+        assert!(matches!(
+            client.list_versions("other").await,
+            Err(Error::Network(message)) if message.contains("maintenance")
+        ));
+    }
+
+    #[tokio::test]
+    async fn uploads_snapshots() {
+        let server = serve(|_| Reply::new(201).json(&version_json(4, "worlds/4.zip"))).await;
+
+        let dir = std::env::temp_dir().join(format!("teller-remote-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let snapshot_path = dir.join("snapshot");
+        tokio::fs::write(&snapshot_path, b"snapshot data")
+            .await
+            .unwrap();
+
+        let client = RemoteVaultClient::new(&remote(&server.url)).unwrap();
+        let version = client
+            .upload_snapshot("world", "1700000000", &snapshot_path)
+            .await
+            .unwrap();
+
+        assert_eq!(version.version, 4);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/worlds/world/versions");
+        assert_eq!(requests[0].header(SNAPSHOT_ID_HEADER), Some("1700000000"));
+        assert_eq!(requests[0].body, b"snapshot data");
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn downloads_versions() {
+        let server = serve(|request| match request.path.as_str() {
+            "/worlds/world/versions/2/download" => Reply::new(200).body("version two"),
+            _ => Reply::new(404),
+        })
+        .await;
+
+        let dir = std::env::temp_dir().join(format!("teller-remote-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let destination = dir.join("download");
+
+        let client = RemoteVaultClient::new(&remote(&server.url)).unwrap();
+
+        client
+            .download_version("world", 2, &destination)
+            .await
+            .unwrap();
+        assert_eq!(tokio::fs::read(&destination).await.unwrap(), b"version two");
+
+        assert!(client
+            .download_version("world", 3, &dir.join("missing"))
+            .await
+            .is_err());
+        assert!(!dir.join("missing").exists());
+        assert!(!partial_path(&dir.join("missing")).exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    /// Runs against a ChunkVault backend given by `TELLER_TEST_CHUNKVAULT_URL`
+    /// and `TELLER_TEST_CHUNKVAULT_KEY`.
+    #[tokio::test]
+    #[ignore]
+    async fn lists_a_chunkvault_backend() {
+        let (Ok(url), Ok(api_key)) = (
+            std::env::var("TELLER_TEST_CHUNKVAULT_URL"),
+            std::env::var("TELLER_TEST_CHUNKVAULT_KEY"),
+        ) else {
+            return;
+        };
+
+        let client = RemoteVaultClient::new(&RemoteBackup {
+            remote_url: url,
+            api_key,
+        })
+        .unwrap();
+
+        for world in client.list_worlds().await.unwrap() {
+            let versions = client.list_versions(&world.id.to_string()).await.unwrap();
+            assert!(versions.iter().all(|version| version.version > 0));
+        }
+    }
+}
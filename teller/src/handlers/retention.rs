@@ -0,0 +1,729 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Datelike, Local, TimeZone, Timelike};
+use log::{error, info, warn};
+
+use crate::handlers::backup::collect_vault_garbage;
+use crate::handlers::catalog::forget_snapshots;
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::vault::{
+    local_snapshot_path, read_snapshot_metadata, vault_backend, VaultBackend,
+};
+use crate::types::backup::{PruneReport, PrunedSnapshot, RetentionPolicy, VaultStorage};
+use crate::types::error::Error;
+
+use super::store::read_snapshot_manifest;
+
+struct SnapshotEntry {
+    id: i64,
+    size: u64,
+    parent: Option<i64>,
+}
+
+/// Indexes of the hour, day, week, month and year a snapshot was taken in,
+/// in local time. Consecutive periods have consecutive indexes.
+fn period_indexes(timestamp: i64) -> Option<[i64; 5]> {
+    let time = Local.timestamp_opt(timestamp, 0).earliest()?;
+    let days = time.num_days_from_ce() as i64;
+
+    Some([
+        days * 24 + time.hour() as i64,
+        days,
+        // Day 1 of the common era was a Monday.
+        (days - 1).div_euclid(7),
+        time.year() as i64 * 12 + time.month0() as i64,
+        time.year() as i64,
+    ])
+}
+
+/// The oldest kept snapshot `id` builds on, following its parents.
+fn chain_root(parents: &HashMap<i64, i64>, keep: &HashSet<i64>, id: i64) -> i64 {
+    let mut current = id;
+    let mut visited = HashSet::from([id]);
+
+    while let Some(parent) = parents.get(&current) {
+        if !keep.contains(parent) || !visited.insert(*parent) {
+            break;
+        }
+        current = *parent;
+    }
+
+    current
+}
+
+/// Returns the ids of the snapshots a policy does not keep. `snapshots` must
+/// be sorted newest first. Parents of kept incremental snapshots are always
+/// kept, since the snapshot could not be restored without them.
+fn select_prunable(
+    snapshots: &[SnapshotEntry],
+    policy: &RetentionPolicy,
+    now: i64,
+) -> HashSet<i64> {
+    let newest = match snapshots.first() {
+        Some(newest) => newest.id,
+        None => return HashSet::new(),
+    };
+
+    let mut keep = HashSet::from([newest]);
+
+    let rules = [
+        policy.keep_hourly,
+        policy.keep_daily,
+        policy.keep_weekly,
+        policy.keep_monthly,
+        policy.keep_yearly,
+    ];
+
+    if policy.keep_last.is_none() && rules.iter().all(Option::is_none) {
+        keep.extend(snapshots.iter().map(|snapshot| snapshot.id));
+    }
+
+    if let Some(keep_last) = policy.keep_last {
+        keep.extend(snapshots.iter().take(keep_last).map(|snapshot| snapshot.id));
+    }
+
+    if let Some(now_periods) = period_indexes(now) {
+        for (period, count) in rules.iter().enumerate() {
+            let count = match count {
+                Some(count) => *count as i64,
+                None => continue,
+            };
+
+            let mut seen = HashSet::new();
+
+            for snapshot in snapshots {
+                let periods = match period_indexes(snapshot.id) {
+                    Some(periods) => periods,
+                    None => continue,
+                };
+
+                if now_periods[period] - periods[period] >= count {
+                    continue;
+                }
+
+                if seen.insert(periods[period]) {
+                    keep.insert(snapshot.id);
+                }
+            }
+        }
+    }
+
+    let parents: HashMap<i64, i64> = snapshots
+        .iter()
+        .filter_map(|snapshot| snapshot.parent.map(|parent| (snapshot.id, parent)))
+        .collect();
+
+    for id in keep.clone() {
+        let mut current = id;
+        while let Some(parent) = parents.get(&current) {
+            if !keep.insert(*parent) {
+                break;
+            }
+            current = *parent;
+        }
+    }
+
+    if let Some(max_world_size) = policy.max_world_size {
+        loop {
+            let total: u64 = snapshots
+                .iter()
+                .filter(|snapshot| keep.contains(&snapshot.id))
+                .map(|snapshot| snapshot.size)
+                .sum();
+
+            if total <= max_world_size {
+                break;
+            }
+
+            // A snapshot can only go once nothing kept builds on it, so the
+            // chain reaching back the furthest is removed from its end.
+            let oldest = snapshots
+                .iter()
+                .filter(|snapshot| {
+                    snapshot.id != newest
+                        && keep.contains(&snapshot.id)
+                        && !snapshots.iter().any(|other| {
+                            keep.contains(&other.id) && other.parent == Some(snapshot.id)
+                        })
+                })
+                .min_by_key(|snapshot| (chain_root(&parents, &keep, snapshot.id), snapshot.id));
+
+            match oldest {
+                Some(oldest) => keep.remove(&oldest.id),
+                None => break,
+            };
+        }
+    }
+
+    snapshots
+        .iter()
+        .map(|snapshot| snapshot.id)
+        .filter(|id| !keep.contains(id))
+        .collect()
+}
+
+async fn read_snapshot_entry(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+    id: i64,
+    storage: VaultStorage,
+) -> Result<SnapshotEntry, Error> {
+    let file_size = vault.stat_snapshot(world_id, snapshot_id).await?.size;
+
+    let (size, parent) = match storage {
+        VaultStorage::Full => {
+            let parent = read_snapshot_metadata(vault, world_id, snapshot_id)
+                .await?
+                .parent
+                .and_then(|parent| parent.parse::<i64>().ok());
+
+            (file_size, parent)
+        }
+        // Deduplicated vaults are always on disk, so the manifest is read in
+        // place rather than fetched.
+        VaultStorage::Deduplicated => match local_snapshot_path(vault, world_id, snapshot_id) {
+            Some(path) => match read_snapshot_manifest(&path).await? {
+                Some(manifest) => (manifest.total_size(), None),
+                None => (file_size, None),
+            },
+            None => (file_size, None),
+        },
+    };
+
+    Ok(SnapshotEntry { id, size, parent })
+}
+
+/// Reads the snapshots of a world, newest first. Snapshots that cannot be
+/// read are logged and left out, so they are neither pruned nor counted as
+/// kept.
+async fn read_world_snapshots(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    storage: VaultStorage,
+) -> Result<Vec<SnapshotEntry>, Error> {
+    let mut snapshots = Vec::new();
+
+    for snapshot_id in vault.list_snapshots(world_id).await? {
+        let id = match snapshot_id.parse::<i64>() {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+
+        match read_snapshot_entry(vault, world_id, &snapshot_id, id, storage).await {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => warn!(
+                "Leaving backup {} of {} out of pruning: {}",
+                snapshot_id, world_id, e
+            ),
+        }
+    }
+
+    snapshots.sort_by_key(|snapshot| Reverse(snapshot.id));
+
+    Ok(snapshots)
+}
+
+async fn prune_world_snapshots(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    storage: VaultStorage,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+    report: &mut PruneReport,
+) -> Result<(), Error> {
+    let snapshots = read_world_snapshots(vault, world_id, storage).await?;
+
+    let prunable = select_prunable(&snapshots, policy, chrono::Utc::now().timestamp());
+
+    report.kept += snapshots.len() - prunable.len();
+
+    // Newest first, so an incremental snapshot is always removed before its
+    // parent.
+    for snapshot in snapshots.iter().filter(|s| prunable.contains(&s.id)) {
+        if !dry_run {
+            vault
+                .delete_snapshot(world_id, &snapshot.id.to_string())
+                .await?;
+
+            info!("Pruned backup {} for {}", snapshot.id, world_id);
+        }
+
+        report.freed += snapshot.size;
+        report.deleted.push(PrunedSnapshot {
+            world_id: world_id.to_string(),
+            snapshot_id: snapshot.id.to_string(),
+            size: snapshot.size,
+        });
+    }
+
+    Ok(())
+}
+
+async fn forget_pruned(vault_id: &str, report: &PruneReport) {
+    let pruned = report
+        .deleted
+        .iter()
+        .map(|snapshot| (snapshot.world_id.as_str(), snapshot.snapshot_id.as_str()));
+
+    forget_snapshots(vault_id, pruned).await;
+}
+
+/// Applies the retention policies of a vault to every world in it. On a dry
+/// run nothing is deleted and the report lists what would have been.
+pub async fn prune_vault(vault_id: &str, dry_run: bool) -> Result<PruneReport, Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault = vault_backend(&backup_settings, vault_id)?;
+
+    let storage = backup_settings.vault_storage(vault_id);
+
+    info!("Pruning vault {} (dry run: {})", vault_id, dry_run);
+
+    let mut report = PruneReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    for world_id in vault.list_worlds().await? {
+        let policy = match backup_settings.retention_policy(vault_id, &world_id) {
+            Some(policy) => policy,
+            None => continue,
+        };
+
+        if let Err(e) = prune_world_snapshots(
+            vault.as_ref(),
+            &world_id,
+            storage,
+            policy,
+            dry_run,
+            &mut report,
+        )
+        .await
+        {
+            error!("Failed to prune {} in vault {}: {}", world_id, vault_id, e);
+        }
+    }
+
+    if !dry_run && !report.deleted.is_empty() {
+        forget_pruned(vault_id, &report).await;
+        collect_vault_garbage(vault.as_ref(), storage).await?;
+    }
+
+    Ok(report)
+}
+
+/// Applies the retention policy of a single world in a vault.
+pub async fn prune_world(
+    vault_id: &str,
+    world_id: &str,
+    dry_run: bool,
+) -> Result<PruneReport, Error> {
+    let backup_settings = get_backup_config().await?;
+
+    let vault = vault_backend(&backup_settings, vault_id)?;
+
+    let storage = backup_settings.vault_storage(vault_id);
+
+    let mut report = PruneReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let policy = match backup_settings.retention_policy(vault_id, world_id) {
+        Some(policy) => policy,
+        None => return Ok(report),
+    };
+
+    info!(
+        "Pruning {} in vault {} (dry run: {})",
+        world_id, vault_id, dry_run
+    );
+
+    prune_world_snapshots(
+        vault.as_ref(),
+        world_id,
+        storage,
+        policy,
+        dry_run,
+        &mut report,
+    )
+    .await?;
+
+    if !dry_run && !report.deleted.is_empty() {
+        forget_pruned(vault_id, &report).await;
+        collect_vault_garbage(vault.as_ref(), storage).await?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: u32, hour: u32) -> i64 {
+        Local
+            .with_ymd_and_hms(2024, 6, day, hour, 0, 0)
+            .unwrap()
+            .timestamp()
+    }
+
+    fn entry(id: i64, size: u64, parent: Option<i64>) -> SnapshotEntry {
+        SnapshotEntry { id, size, parent }
+    }
+
+    /// Snapshots without parents, sorted newest first like
+    /// `read_world_snapshots` returns them.
+    fn snapshots(ids: &[i64]) -> Vec<SnapshotEntry> {
+        let mut snapshots: Vec<SnapshotEntry> = ids.iter().map(|id| entry(*id, 1, None)).collect();
+        snapshots.sort_by_key(|snapshot| Reverse(snapshot.id));
+        snapshots
+    }
+
+    fn pruned(snapshots: &[SnapshotEntry], policy: &RetentionPolicy, now: i64) -> Vec<i64> {
+        let mut pruned: Vec<i64> = select_prunable(snapshots, policy, now)
+            .into_iter()
+            .collect();
+        pruned.sort();
+        pruned
+    }
+
+    #[test]
+    fn consecutive_periods_have_consecutive_indexes() {
+        let monday = period_indexes(at(3, 12)).unwrap();
+        let sunday = period_indexes(at(9, 23)).unwrap();
+        let next_monday = period_indexes(at(10, 0)).unwrap();
+        let next_month = period_indexes(
+            Local
+                .with_ymd_and_hms(2024, 7, 1, 0, 0, 0)
+                .unwrap()
+                .timestamp(),
+        )
+        .unwrap();
+        let next_year = period_indexes(
+            Local
+                .with_ymd_and_hms(2025, 1, 1, 0, 0, 0)
+                .unwrap()
+                .timestamp(),
+        )
+        .unwrap();
+
+        assert_eq!(period_indexes(at(3, 13)).unwrap()[0], monday[0] + 1);
+        assert_eq!(sunday[1], monday[1] + 6);
+        assert_eq!(sunday[2], monday[2]);
+        assert_eq!(next_monday[2], monday[2] + 1);
+        assert_eq!(sunday[3], monday[3]);
+        assert_eq!(next_month[3], monday[3] + 1);
+        assert_eq!(next_year[3], monday[3] + 7);
+        assert_eq!(next_year[4], monday[4] + 1);
+    }
+
+    #[test]
+    fn keeps_everything_without_rules() {
+        let snapshots = snapshots(&[at(1, 0), at(2, 0), at(3, 0)]);
+
+        assert!(pruned(&snapshots, &RetentionPolicy::default(), at(30, 0)).is_empty());
+    }
+
+    #[test]
+    fn keeps_nothing_from_empty_worlds() {
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+
+        assert!(select_prunable(&[], &policy, at(30, 0)).is_empty());
+    }
+
+    #[test]
+    fn keeps_last() {
+        let ids = [at(1, 0), at(2, 0), at(3, 0), at(4, 0), at(5, 0)];
+        let snapshots = snapshots(&ids);
+
+        let cases = [
+            (0, vec![ids[0], ids[1], ids[2], ids[3]]),
+            (1, vec![ids[0], ids[1], ids[2], ids[3]]),
+            (2, vec![ids[0], ids[1], ids[2]]),
+            (5, vec![]),
+            (10, vec![]),
+        ];
+
+        for (keep_last, expected) in cases {
+            let policy = RetentionPolicy {
+                keep_last: Some(keep_last),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                pruned(&snapshots, &policy, at(30, 0)),
+                expected,
+                "keep_last {}",
+                keep_last
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_newest_of_each_period() {
+        let snapshots = snapshots(&[
+            at(10, 8),
+            at(10, 9),
+            at(10, 9) + 60,
+            at(11, 9),
+            at(11, 20),
+            at(12, 9),
+            at(12, 10),
+        ]);
+        let now = at(12, 11);
+
+        let cases = [
+            (
+                "hourly",
+                RetentionPolicy {
+                    keep_hourly: Some(2),
+                    ..Default::default()
+                },
+                vec![
+                    at(10, 8),
+                    at(10, 9),
+                    at(10, 9) + 60,
+                    at(11, 9),
+                    at(11, 20),
+                    at(12, 9),
+                ],
+            ),
+            (
+                "daily",
+                RetentionPolicy {
+                    keep_daily: Some(2),
+                    ..Default::default()
+                },
+                vec![at(10, 8), at(10, 9), at(10, 9) + 60, at(11, 9), at(12, 9)],
+            ),
+            (
+                "daily going back further than the snapshots",
+                RetentionPolicy {
+                    keep_daily: Some(30),
+                    ..Default::default()
+                },
+                vec![at(10, 8), at(10, 9), at(11, 9), at(12, 9)],
+            ),
+            (
+                "weekly",
+                RetentionPolicy {
+                    keep_weekly: Some(1),
+                    ..Default::default()
+                },
+                vec![
+                    at(10, 8),
+                    at(10, 9),
+                    at(10, 9) + 60,
+                    at(11, 9),
+                    at(11, 20),
+                    at(12, 9),
+                ],
+            ),
+            (
+                "daily and keep last",
+                RetentionPolicy {
+                    keep_last: Some(3),
+                    keep_daily: Some(3),
+                    ..Default::default()
+                },
+                vec![at(10, 8), at(10, 9), at(11, 9)],
+            ),
+        ];
+
+        for (case, policy, expected) in cases {
+            assert_eq!(pruned(&snapshots, &policy, now), expected, "{}", case);
+        }
+    }
+
+    #[test]
+    fn keeps_newest_of_each_month_and_year() {
+        let day = |year: i32, month: u32, day: u32| {
+            Local
+                .with_ymd_and_hms(year, month, day, 12, 0, 0)
+                .unwrap()
+                .timestamp()
+        };
+
+        let snapshots = snapshots(&[
+            day(2022, 6, 1),
+            day(2023, 1, 1),
+            day(2023, 12, 1),
+            day(2024, 5, 1),
+            day(2024, 5, 20),
+            day(2024, 6, 1),
+        ]);
+        let now = day(2024, 6, 2);
+
+        let monthly = RetentionPolicy {
+            keep_monthly: Some(2),
+            ..Default::default()
+        };
+        let yearly = RetentionPolicy {
+            keep_yearly: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            pruned(&snapshots, &monthly, now),
+            vec![
+                day(2022, 6, 1),
+                day(2023, 1, 1),
+                day(2023, 12, 1),
+                day(2024, 5, 1)
+            ]
+        );
+        assert_eq!(
+            pruned(&snapshots, &yearly, now),
+            vec![
+                day(2022, 6, 1),
+                day(2023, 1, 1),
+                day(2024, 5, 1),
+                day(2024, 5, 20)
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_parents_of_kept_snapshots() {
+        // 4 builds on 3, which builds on 1. 2 is a full snapshot.
+        let snapshots = vec![
+            entry(4, 1, Some(3)),
+            entry(3, 1, Some(1)),
+            entry(2, 1, None),
+            entry(1, 1, None),
+        ];
+
+        let policy = RetentionPolicy {
+            keep_last: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(pruned(&snapshots, &policy, 5), vec![2]);
+    }
+
+    #[test]
+    fn caps_total_size_from_the_oldest() {
+        let snapshots = vec![
+            entry(5, 10, None),
+            entry(4, 10, None),
+            entry(3, 10, None),
+            entry(2, 10, None),
+            entry(1, 10, None),
+        ];
+
+        let cases = [
+            (50, vec![]),
+            (49, vec![1]),
+            (30, vec![1, 2]),
+            (10, vec![1, 2, 3, 4]),
+            // The newest snapshot stays, even when it alone is too big.
+            (0, vec![1, 2, 3, 4]),
+        ];
+
+        for (max_world_size, expected) in cases {
+            let policy = RetentionPolicy {
+                max_world_size: Some(max_world_size),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                pruned(&snapshots, &policy, 6),
+                expected,
+                "max_world_size {}",
+                max_world_size
+            );
+        }
+    }
+
+    #[test]
+    fn size_cap_skips_parents_of_kept_snapshots() {
+        // 3 builds on 1, so 1 can only go after 3, and both go before 2.
+        let snapshots = vec![
+            entry(4, 10, None),
+            entry(3, 10, Some(1)),
+            entry(2, 10, None),
+            entry(1, 10, None),
+        ];
+
+        let cases = [(30, vec![3]), (20, vec![1, 3]), (10, vec![1, 2, 3])];
+
+        for (max_world_size, expected) in cases {
+            let policy = RetentionPolicy {
+                max_world_size: Some(max_world_size),
+                ..Default::default()
+            };
+
+            assert_eq!(
+                pruned(&snapshots, &policy, 5),
+                expected,
+                "max_world_size {}",
+                max_world_size
+            );
+        }
+    }
+
+    #[test]
+    fn size_cap_applies_after_other_rules() {
+        let snapshots = vec![
+            entry(4, 10, None),
+            entry(3, 10, None),
+            entry(2, 10, None),
+            entry(1, 10, None),
+        ];
+
+        let policy = RetentionPolicy {
+            keep_last: Some(3),
+            max_world_size: Some(25),
+            ..Default::default()
+        };
+
+        assert_eq!(pruned(&snapshots, &policy, 5), vec![1, 2]);
+    }
+
+    #[test]
+    fn parent_loops_do_not_hang() {
+        let snapshots = vec![
+            entry(4, 10, None),
+            entry(3, 10, Some(2)),
+            entry(2, 10, Some(3)),
+            entry(1, 10, Some(1)),
+        ];
+
+        let policy = RetentionPolicy {
+            keep_last: Some(2),
+            max_world_size: Some(10),
+            ..Default::default()
+        };
+
+        assert_eq!(pruned(&snapshots, &policy, 5), vec![1]);
+    }
+
+    #[test]
+    fn size_cap_leaves_chains_ending_in_newest() {
+        let snapshots = vec![
+            entry(3, 10, Some(2)),
+            entry(2, 10, Some(1)),
+            entry(1, 10, None),
+        ];
+
+        let policy = RetentionPolicy {
+            max_world_size: Some(10),
+            ..Default::default()
+        };
+
+        assert!(pruned(&snapshots, &policy, 4).is_empty());
+    }
+
+    #[test]
+    fn reads_size_caps_saved_under_their_old_name() {
+        let policy: RetentionPolicy = serde_json::from_str(r#"{"max_total_size": 10}"#).unwrap();
+
+        assert_eq!(policy.max_world_size, Some(10));
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+
+use crate::types::error::Error;
+
+const MIN_YEAR: u32 = 1970;
+const MAX_YEAR: u32 = 2099;
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// A parsed cron expression. Accepts the classic five fields
+/// (`minute hour day month weekday`), six fields with leading seconds, and
+/// seven fields with leading seconds and a trailing year, which is the form
+/// `BackupSettings::schedule` defaults to. Weekdays run from 0 (Sunday) to 7
+/// (Sunday again).
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    seconds: BTreeSet<u32>,
+    minutes: BTreeSet<u32>,
+    hours: BTreeSet<u32>,
+    days: BTreeSet<u32>,
+    months: BTreeSet<u32>,
+    weekdays: BTreeSet<u32>,
+    years: BTreeSet<u32>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_value(value: &str, min: u32, max: u32, names: &[&str]) -> Result<u32, Error> {
+    let upper = value.to_uppercase();
+
+    let parsed = match names.iter().position(|name| *name == upper) {
+        Some(position) => min + position as u32,
+        None => value
+            .parse::<u32>()
+            .map_err(|_| Error::Config(format!("Invalid value {}", value)))?,
+    };
+
+    if parsed < min || parsed > max {
+        return Err(Error::Config(format!(
+            "Value {} is out of range {}-{}",
+            parsed, min, max
+        )));
+    }
+
+    Ok(parsed)
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<BTreeSet<u32>, Error> {
+    let mut values = BTreeSet::new();
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .map_err(|_| Error::Config(format!("Invalid step {}", step)))?;
+
+                if step == 0 {
+                    return Err(Error::Config("Step cannot be 0".to_string()));
+                }
+
+                (range, Some(step))
+            }
+            None => (part, None),
+        };
+
+        let (start, end) = match range {
+            "*" | "?" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    parse_value(start, min, max, names)?,
+                    parse_value(end, min, max, names)?,
+                ),
+                None => {
+                    let start = parse_value(range, min, max, names)?;
+                    match step {
+                        Some(_) => (start, max),
+                        None => (start, start),
+                    }
+                }
+            },
+        };
+
+        if start > end {
+            return Err(Error::Config(format!("Invalid range {}", range)));
+        }
+
+        values.extend((start..=end).step_by(step.unwrap_or(1) as usize));
+    }
+
+    Ok(values)
+}
+
+fn is_wildcard(field: &str) -> bool {
+    field == "*" || field == "?"
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, Error> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+
+        let (second, rest, year) = match fields.len() {
+            5 => ("0", &fields[..], "*"),
+            6 => (fields[0], &fields[1..], "*"),
+            7 => (fields[0], &fields[1..6], fields[6]),
+            count => {
+                return Err(Error::Config(format!(
+                    "Expected 5, 6 or 7 fields in cron expression {}, found {}",
+                    expression, count
+                )))
+            }
+        };
+
+        let parse = |field: &str, min: u32, max: u32, names: &[&str]| {
+            parse_field(field, min, max, names).map_err(|e| {
+                Error::Config(format!("Invalid cron expression {}: {}", expression, e))
+            })
+        };
+
+        let weekdays = parse(rest[4], 0, 7, &WEEKDAY_NAMES)?
+            .into_iter()
+            .map(|weekday| weekday % 7)
+            .collect();
+
+        Ok(Self {
+            seconds: parse(second, 0, 59, &[])?,
+            minutes: parse(rest[0], 0, 59, &[])?,
+            hours: parse(rest[1], 0, 23, &[])?,
+            days: parse(rest[2], 1, 31, &[])?,
+            months: parse(rest[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            years: parse(year, MIN_YEAR, MAX_YEAR, &[])?,
+            any_day: is_wildcard(rest[2]),
+            any_weekday: is_wildcard(rest[4]),
+        })
+    }
+
+    /// As in classic cron, a day matches if either the day of the month or
+    /// the weekday matches when both are restricted.
+    fn day_matches(&self, date: NaiveDate) -> bool {
+        let day = self.days.contains(&date.day());
+        let weekday = self
+            .weekdays
+            .contains(&date.weekday().num_days_from_sunday());
+
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// Returns the first time after `after` the schedule fires, in local time.
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut time = after.naive_local().with_nanosecond(0)? + Duration::seconds(1);
+
+        loop {
+            let year = time.year() as u32;
+
+            if year > MAX_YEAR {
+                return None;
+            }
+
+            if !self.years.contains(&year) {
+                time = start_of_day(NaiveDate::from_ymd_opt(year as i32 + 1, 1, 1)?)?;
+                continue;
+            }
+
+            if !self.months.contains(&time.month()) {
+                time = start_of_day(next_month(time.date())?)?;
+                continue;
+            }
+
+            if !self.day_matches(time.date()) {
+                time = start_of_day(time.date().succ_opt()?)?;
+                continue;
+            }
+
+            if !self.hours.contains(&time.hour()) {
+                time = time.date().and_hms_opt(time.hour(), 0, 0)? + Duration::hours(1);
+                continue;
+            }
+
+            if !self.minutes.contains(&time.minute()) {
+                time =
+                    time.date().and_hms_opt(time.hour(), time.minute(), 0)? + Duration::minutes(1);
+                continue;
+            }
+
+            if !self.seconds.contains(&time.second()) {
+                time += Duration::seconds(1);
+                continue;
+            }
+
+            // Times skipped by a daylight saving change never happen locally.
+            match Local.from_local_datetime(&time).earliest() {
+                Some(next) => return Some(next),
+                None => time += Duration::seconds(1),
+            }
+        }
+    }
+}
+
+fn start_of_day(date: NaiveDate) -> Option<NaiveDateTime> {
+    date.and_hms_opt(0, 0, 0)
+}
+
+fn next_month(date: NaiveDate) -> Option<NaiveDate> {
+    match date.month() {
+        12 => NaiveDate::from_ymd_opt(date.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(date.year(), month + 1, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+    ) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, second)
+            .unwrap()
+    }
+
+    fn next(expression: &str, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        CronSchedule::parse(expression).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn parses_valid_expressions() {
+        let cases = [
+            "* * * * *",
+            "0 0 * * *",
+            "*/15 9-17 * * MON-FRI",
+            "0 30 4 1,15 * ?",
+            "0 0 0 * * * *",
+            "0 0 12 ? JAN,jul SUN 2024-2030",
+            "5/10 * * * *",
+            "0 0 * * 7",
+            "59 23 31 12 6",
+        ];
+
+        for expression in cases {
+            assert!(
+                CronSchedule::parse(expression).is_ok(),
+                "{} should parse",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let cases = [
+            "",
+            "* * * *",
+            "* * * * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * 32 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "* * * FOO *",
+            "*/0 * * * *",
+            "*/x * * * *",
+            "5-1 * * * *",
+            "1-2-3 * * * *",
+            "-1 * * * *",
+            "1, * * * *",
+            "0 0 0 * * * 1969",
+            "0 0 0 * * * 2100",
+            "99999999999 * * * *",
+            "ü * * * *",
+        ];
+
+        for expression in cases {
+            assert!(
+                matches!(CronSchedule::parse(expression), Err(Error::Config(_))),
+                "{:?} should be rejected",
+                expression
+            );
+        }
+    }
+
+    #[test]
+    fn parses_fields() {
+        // Field, range, names and the values it stands for.
+        type FieldCase = (
+            &'static str,
+            u32,
+            u32,
+            &'static [&'static str],
+            &'static [u32],
+        );
+
+        let cases: [FieldCase; 7] = [
+            ("*", 0, 5, &[], &[0, 1, 2, 3, 4, 5]),
+            ("*/2", 0, 5, &[], &[0, 2, 4]),
+            ("1/2", 0, 5, &[], &[1, 3, 5]),
+            ("1-3", 0, 5, &[], &[1, 2, 3]),
+            ("1-5/2,0", 0, 5, &[], &[0, 1, 3, 5]),
+            ("MAR-may", 1, 12, &MONTH_NAMES, &[3, 4, 5]),
+            ("SAT,sun", 0, 7, &WEEKDAY_NAMES, &[0, 6]),
+        ];
+
+        for (field, min, max, names, expected) in cases {
+            let values: Vec<u32> = parse_field(field, min, max, names)
+                .unwrap()
+                .into_iter()
+                .collect();
+
+            assert_eq!(values, expected, "{}", field);
+        }
+    }
+
+    #[test]
+    fn finds_next_run() {
+        let after = local(2024, 6, 12, 10, 20, 30);
+
+        let cases = [
+            ("* * * * *", local(2024, 6, 12, 10, 21, 0)),
+            ("*/15 * * * *", local(2024, 6, 12, 10, 30, 0)),
+            ("0 3 * * *", local(2024, 6, 13, 3, 0, 0)),
+            ("45 * * * * *", local(2024, 6, 12, 10, 20, 45)),
+            ("0 0 12 * * ? *", local(2024, 6, 12, 12, 0, 0)),
+            ("0 0 1 * *", local(2024, 7, 1, 0, 0, 0)),
+            ("0 0 * * SAT", local(2024, 6, 15, 0, 0, 0)),
+            ("0 0 * * 7", local(2024, 6, 16, 0, 0, 0)),
+            ("0 0 1 JAN *", local(2025, 1, 1, 0, 0, 0)),
+            ("0 0 0 1 1 ? 2030", local(2030, 1, 1, 0, 0, 0)),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(next(expression, after), Some(expected), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn next_run_is_strictly_after() {
+        let after = local(2024, 6, 12, 3, 0, 0);
+
+        assert_eq!(next("0 3 * * *", after), Some(local(2024, 6, 13, 3, 0, 0)));
+        assert_eq!(
+            next("* * * * * *", after + Duration::milliseconds(500)),
+            Some(local(2024, 6, 12, 3, 0, 1))
+        );
+    }
+
+    #[test]
+    fn matches_either_day_or_weekday() {
+        // The 13th, or any Monday.
+        let schedule = "0 0 13 * MON";
+
+        assert_eq!(
+            next(schedule, local(2024, 6, 12, 12, 0, 0)),
+            Some(local(2024, 6, 13, 0, 0, 0))
+        );
+        assert_eq!(
+            next(schedule, local(2024, 6, 13, 12, 0, 0)),
+            Some(local(2024, 6, 17, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn skips_to_leap_days() {
+        assert_eq!(
+            next("0 0 29 2 *", local(2024, 3, 1, 0, 0, 0)),
+            Some(local(2028, 2, 29, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_impossible_schedules() {
+        let after = local(2024, 6, 12, 0, 0, 0);
+
+        let cases = ["0 0 31 2 *", "0 0 0 30 2 ? *", "0 0 0 * * * 2020"];
+
+        for expression in cases {
+            assert_eq!(next(expression, after), None, "{}", expression);
+        }
+    }
+}
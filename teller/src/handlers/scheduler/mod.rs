@@ -0,0 +1,264 @@
+pub mod cron;
+pub mod watcher;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, TimeZone};
+use log::{error, info};
+
+use crate::handlers::atomic::write_atomic;
+use crate::handlers::backup::create_backup_from_id;
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::config::get_config_folder;
+use crate::handlers::progress::Progress;
+use crate::handlers::queue::enqueue;
+use crate::types::backup::{BackupSettings, CatchUpPolicy, ScheduledWorld};
+use crate::types::queue::QueuedTask;
+use crate::types::scheduler::{SchedulerEvent, SchedulerState};
+
+use self::cron::CronSchedule;
+
+const STATE_FILE: &str = "scheduler_state.json";
+
+/// Key of the global schedule in `SchedulerState::last_runs`.
+const GLOBAL_SCHEDULE: &str = "global";
+
+/// Longest time the scheduler sleeps before looking at the clock and the
+/// settings again. Sleeping in short steps and comparing wall clock times
+/// keeps it on time across system sleep, where monotonic timers stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs that start more than this many seconds after they were due count as
+/// missed, and are handled by the catch-up policy.
+const MISSED_RUN_GRACE: i64 = 5 * 60;
+
+async fn load_state() -> SchedulerState {
+    let state_path = get_config_folder().join(STATE_FILE);
+
+    match tokio::fs::read_to_string(&state_path).await {
+        Ok(state) => serde_json::from_str(&state).unwrap_or_default(),
+        Err(_) => SchedulerState::default(),
+    }
+}
+
+async fn save_state(state: &SchedulerState) {
+    let state_path = get_config_folder().join(STATE_FILE);
+
+    let state = match serde_json::to_string(state) {
+        Ok(state) => state,
+        Err(e) => {
+            error!("Could not serialize scheduler state: {:?}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = write_atomic(&state_path, state.as_bytes()).await {
+        error!(
+            "Could not write scheduler state at {:?}: {:?}",
+            state_path, e
+        );
+    }
+}
+
+/// A schedule and the worlds it backs up.
+struct ScheduleJob {
+    key: String,
+    schedule: String,
+    worlds: Vec<ScheduledWorld>,
+    vaults: Option<Vec<String>>,
+    prune: bool,
+}
+
+fn collect_jobs(backup_settings: &BackupSettings) -> Vec<ScheduleJob> {
+    // An empty list of vaults would copy the backup nowhere, so the default
+    // vault is used instead.
+    let default_vaults = backup_settings
+        .default_vaults
+        .clone()
+        .filter(|vaults| !vaults.is_empty());
+
+    let mut jobs = Vec::new();
+
+    if backup_settings.auto_backup {
+        let worlds: Vec<ScheduledWorld> = backup_settings
+            .scheduled_worlds
+            .iter()
+            .filter(|world| !backup_settings.profiles.contains_key(&world.world_id))
+            .cloned()
+            .collect();
+
+        if !worlds.is_empty() {
+            jobs.push(ScheduleJob {
+                key: GLOBAL_SCHEDULE.to_string(),
+                schedule: backup_settings.schedule.clone(),
+                worlds,
+                vaults: default_vaults.clone(),
+                prune: false,
+            });
+        }
+    }
+
+    for (world_id, profile) in &backup_settings.profiles {
+        if !profile.enabled {
+            continue;
+        }
+
+        jobs.push(ScheduleJob {
+            key: world_id.clone(),
+            schedule: profile.schedule.clone(),
+            worlds: vec![ScheduledWorld {
+                world_id: world_id.clone(),
+                category: profile.category.clone(),
+                instance: profile.instance.clone(),
+            }],
+            vaults: profile
+                .vaults
+                .clone()
+                .filter(|vaults| !vaults.is_empty())
+                .or_else(|| default_vaults.clone()),
+            prune: profile.retention.is_some(),
+        });
+    }
+
+    jobs
+}
+
+async fn run_scheduled_backups<F>(job: &ScheduleJob, scheduled_for: DateTime<Local>, on_event: &F)
+where
+    F: Fn(SchedulerEvent) + Send + Sync,
+{
+    for world in &job.worlds {
+        info!("Running scheduled backup for {}", world.world_id);
+
+        on_event(SchedulerEvent::BackupStarted {
+            world_id: world.world_id.clone(),
+            scheduled_for: scheduled_for.timestamp(),
+        });
+
+        let result = create_backup_from_id(
+            &world.world_id,
+            world.category.as_deref(),
+            world.instance.as_deref(),
+            job.vaults.clone(),
+            false,
+            None,
+            &Progress::default(),
+        )
+        .await;
+
+        match &result {
+            // Queued behind the uploads of the backup, so uploads that have
+            // not finished yet are not pruned against.
+            Ok(_) if job.prune => {
+                for vault in job.vaults.iter().flatten() {
+                    let prune = QueuedTask::Prune {
+                        vault_id: vault.clone(),
+                        world_id: Some(world.world_id.clone()),
+                    };
+
+                    if let Err(e) = enqueue(prune).await {
+                        error!(
+                            "Failed to queue pruning {} in vault {}: {}",
+                            world.world_id, vault, e
+                        );
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Scheduled backup for {} failed: {}", world.world_id, e),
+        }
+
+        on_event(SchedulerEvent::BackupFinished {
+            world_id: world.world_id.clone(),
+            scheduled_for: scheduled_for.timestamp(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+}
+
+/// Takes backups according to the global schedule and the backup profiles in
+/// `BackupSettings` for as long as the returned future is polled. Settings
+/// are read again on every step, so changes apply without a restart.
+pub async fn run_scheduler<F>(on_event: F)
+where
+    F: Fn(SchedulerEvent) + Send + Sync,
+{
+    let mut state = load_state().await;
+
+    let mut last_runs: HashMap<String, DateTime<Local>> = state
+        .last_runs
+        .iter()
+        .filter_map(|(key, last_run)| {
+            Local
+                .timestamp_opt(*last_run, 0)
+                .single()
+                .map(|last_run| (key.clone(), last_run))
+        })
+        .collect();
+
+    info!("Starting backup scheduler");
+
+    loop {
+        let backup_settings = match get_backup_config().await {
+            Ok(backup_settings) => backup_settings,
+            Err(e) => {
+                error!("Scheduler could not read backup settings: {}", e);
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let jobs = collect_jobs(&backup_settings);
+
+        // Runs are only due from the moment a schedule is turned on.
+        last_runs.retain(|key, _| jobs.iter().any(|job| &job.key == key));
+
+        let mut wait = POLL_INTERVAL;
+
+        for job in &jobs {
+            let now = Local::now();
+
+            let last_run = *last_runs.entry(job.key.clone()).or_insert(now);
+
+            let next_run = match CronSchedule::parse(&job.schedule) {
+                Ok(schedule) => schedule.next_after(last_run),
+                Err(e) => {
+                    error!("Invalid backup schedule for {}: {}", job.key, e);
+                    None
+                }
+            };
+
+            let next_run = match next_run {
+                Some(next_run) => next_run,
+                None => {
+                    last_runs.insert(job.key.clone(), now);
+                    continue;
+                }
+            };
+
+            if next_run > now {
+                wait = wait.min((next_run - now).to_std().unwrap_or_default());
+                continue;
+            }
+
+            last_runs.insert(job.key.clone(), now);
+
+            let missed = (now - next_run).num_seconds() > MISSED_RUN_GRACE;
+
+            if missed && backup_settings.catch_up == CatchUpPolicy::Skip {
+                info!("Skipping {} backup missed at {}", job.key, next_run);
+            } else {
+                run_scheduled_backups(job, next_run, &on_event).await;
+            }
+
+            state.last_runs = last_runs
+                .iter()
+                .map(|(key, last_run)| (key.clone(), last_run.timestamp()))
+                .collect();
+            save_state(&state).await;
+        }
+
+        tokio::time::sleep(wait).await;
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use log::{error, info};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use crate::handlers::backup::create_backup_from_id;
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::config::get_config_folder;
+use crate::handlers::config::instance::get_local_directories_config;
+use crate::handlers::lock::is_world_in_use;
+use crate::handlers::progress::Progress;
+use crate::handlers::world::get_vault_id;
+use crate::types::backup::BackupSettings;
+use crate::types::scheduler::SchedulerEvent;
+
+/// How often the watcher reloads its settings and the save folders to watch.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often worlds are checked for having gone quiet.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Written by teller itself, so changes to it never mean the world was played.
+const VAULT_FILE: &str = ".chunkvault";
+
+/// A world that changed and has not been backed up since.
+struct PendingWorld {
+    category: String,
+    last_change: Instant,
+}
+
+/// Returns whether a changed file is part of the world's save data: its
+/// `level.dat`, a region file of any dimension, or the LevelDB of a Bedrock
+/// world.
+fn is_world_data(relative_path: &Path) -> bool {
+    let components: Vec<&str> = relative_path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect();
+
+    if components.contains(&VAULT_FILE) {
+        return false;
+    }
+
+    match components.as_slice() {
+        ["level.dat"] => true,
+        ["db", ..] => true,
+        [.., "region", file] => file.ends_with(".mca"),
+        _ => false,
+    }
+}
+
+/// Maps a changed path to the world folder it belongs to, if it is world data
+/// in one of the watched save folders.
+fn find_changed_world<'a>(
+    path: &Path,
+    save_folders: &'a HashMap<PathBuf, String>,
+) -> Option<(PathBuf, &'a String)> {
+    for (save_folder, category) in save_folders {
+        let relative_path = match path.strip_prefix(save_folder) {
+            Ok(relative_path) => relative_path,
+            Err(_) => continue,
+        };
+
+        let mut components = relative_path.components();
+        let world_folder = match components.next() {
+            Some(Component::Normal(world_folder)) => world_folder,
+            _ => continue,
+        };
+
+        if is_world_data(components.as_path()) {
+            return Some((save_folder.join(world_folder), category));
+        }
+    }
+
+    None
+}
+
+/// The save folders configured in `DirectorySettings`, with their category.
+fn get_save_folders() -> HashMap<PathBuf, String> {
+    let mut save_folders = HashMap::new();
+
+    match get_local_directories_config(get_config_folder()) {
+        Ok(directory_settings) => {
+            for (category, vault_entries) in directory_settings.categories {
+                for path in vault_entries.paths.into_values() {
+                    save_folders.insert(path, category.clone());
+                }
+            }
+        }
+        Err(e) => error!("Watcher could not read save folders: {}", e),
+    }
+
+    save_folders
+}
+
+fn get_backup_vaults(backup_settings: &BackupSettings, world_id: &str) -> Option<Vec<String>> {
+    let profile_vaults = backup_settings
+        .profiles
+        .get(world_id)
+        .filter(|profile| profile.enabled)
+        .and_then(|profile| profile.vaults.clone());
+
+    profile_vaults
+        .or_else(|| backup_settings.default_vaults.clone())
+        .filter(|vaults| !vaults.is_empty())
+}
+
+async fn backup_quiet_world<F>(world_path: &Path, category: &str, on_event: &F)
+where
+    F: Fn(SchedulerEvent) + Send + Sync,
+{
+    let world_id = match get_vault_id(&world_path.to_path_buf()).await {
+        Ok(world_id) => world_id,
+        Err(e) => {
+            error!("Watcher could not read vault id of {:?}: {}", world_path, e);
+            return;
+        }
+    };
+
+    let backup_settings = match get_backup_config().await {
+        Ok(backup_settings) => backup_settings,
+        Err(e) => {
+            error!("Watcher could not read backup settings: {}", e);
+            return;
+        }
+    };
+
+    info!("World {} went quiet, backing it up", world_id);
+
+    let scheduled_for = Local::now().timestamp();
+
+    on_event(SchedulerEvent::BackupStarted {
+        world_id: world_id.clone(),
+        scheduled_for,
+    });
+
+    let result = create_backup_from_id(
+        &world_id,
+        Some(category),
+        None,
+        get_backup_vaults(&backup_settings, &world_id),
+        false,
+        None,
+        &Progress::default(),
+    )
+    .await;
+
+    if let Err(e) = &result {
+        error!("Watcher backup for {} failed: {}", world_id, e);
+    }
+
+    on_event(SchedulerEvent::BackupFinished {
+        world_id,
+        scheduled_for,
+        error: result.err().map(|e| e.to_string()),
+    });
+}
+
+/// Backs up worlds in the configured save folders once their save data has
+/// stopped changing for `WatcherSettings::quiet_period`, which usually means
+/// the player has left the world. Runs for as long as the returned future is
+/// polled, picking up changes to the settings and save folders as it goes.
+pub async fn run_watcher<F>(on_event: F)
+where
+    F: Fn(SchedulerEvent) + Send + Sync,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel::<notify::Result<Event>>();
+
+    let mut watcher: Option<RecommendedWatcher> = None;
+    let mut watched: HashSet<PathBuf> = HashSet::new();
+    let mut save_folders: HashMap<PathBuf, String> = HashMap::new();
+    let mut pending: HashMap<PathBuf, PendingWorld> = HashMap::new();
+    let mut quiet_period = Duration::from_secs(0);
+
+    let mut reload = tokio::time::interval(RELOAD_INTERVAL);
+    let mut check = tokio::time::interval(CHECK_INTERVAL);
+
+    info!("Starting world watcher");
+
+    loop {
+        tokio::select! {
+            _ = reload.tick() => {
+                let watcher_settings = match get_backup_config().await {
+                    Ok(backup_settings) => backup_settings.watcher,
+                    Err(e) => {
+                        error!("Watcher could not read backup settings: {}", e);
+                        continue;
+                    }
+                };
+
+                quiet_period = Duration::from_secs(watcher_settings.quiet_period);
+
+                if !watcher_settings.enabled {
+                    if watcher.take().is_some() {
+                        info!("World watcher turned off");
+                    }
+                    watched.clear();
+                    pending.clear();
+                    continue;
+                }
+
+                if watcher.is_none() {
+                    let sender = sender.clone();
+                    watcher = match notify::recommended_watcher(move |event| {
+                        let _ = sender.send(event);
+                    }) {
+                        Ok(watcher) => Some(watcher),
+                        Err(e) => {
+                            error!("Could not start world watcher: {:?}", e);
+                            continue;
+                        }
+                    };
+                }
+
+                save_folders = get_save_folders();
+
+                if let Some(watcher) = watcher.as_mut() {
+                    for path in watched.clone() {
+                        if !save_folders.contains_key(&path) {
+                            let _ = watcher.unwatch(&path);
+                            watched.remove(&path);
+                        }
+                    }
+
+                    for path in save_folders.keys() {
+                        if watched.contains(path) || !path.is_dir() {
+                            continue;
+                        }
+
+                        match watcher.watch(path, RecursiveMode::Recursive) {
+                            Ok(_) => {
+                                info!("Watching {:?} for world changes", path);
+                                watched.insert(path.clone());
+                            }
+                            Err(e) => error!("Could not watch {:?}: {:?}", path, e),
+                        }
+                    }
+                }
+            }
+            Some(event) = receiver.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!("World watcher error: {:?}", e);
+                        continue;
+                    }
+                };
+
+                if watcher.is_none() || matches!(event.kind, EventKind::Access(_)) {
+                    continue;
+                }
+
+                for path in &event.paths {
+                    if let Some((world_path, category)) = find_changed_world(path, &save_folders) {
+                        pending.insert(
+                            world_path,
+                            PendingWorld {
+                                category: category.clone(),
+                                last_change: Instant::now(),
+                            },
+                        );
+                    }
+                }
+            }
+            _ = check.tick() => {
+                // A world can be quiet while the game still has it open, for
+                // example when paused. It is backed up once it is closed.
+                let quiet_worlds: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, world)| world.last_change.elapsed() >= quiet_period)
+                    .filter(|(world_path, _)| !is_world_in_use(world_path))
+                    .map(|(world_path, _)| world_path.clone())
+                    .collect();
+
+                for world_path in quiet_worlds {
+                    if let Some(world) = pending.remove(&world_path) {
+                        backup_quiet_world(&world_path, &world.category, &on_event).await;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,16 +1,26 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tokio::fs;
 
 use crate::{
-    handlers::{backup::get_backup_meta_from_path, config::backup::get_backup_config},
+    handlers::{
+        catalog::get_vault_catalog,
+        config::backup::get_backup_config,
+        vault::{
+            find_newest_snapshot, local_snapshot_path, read_snapshot_metadata, snapshot_file_name,
+            vault_backend, VaultBackend, SNAPSHOT_SUFFIX,
+        },
+    },
     types::{
-        backup::{BackupMetadata, SnapshotInfo},
+        backup::{BackupMetadata, BackupSettings, SnapshotInfo},
+        error::Error,
         world::WorldData,
     },
 };
 
-async fn get_backups_from_path(directory_path: &str) -> Result<Vec<fs::DirEntry>, std::io::Error> {
+pub(crate) async fn get_backups_from_path(
+    directory_path: &Path,
+) -> Result<Vec<fs::DirEntry>, std::io::Error> {
     let mut entries = fs::read_dir(directory_path).await?;
     let mut files: Vec<fs::DirEntry> = Vec::new();
 
@@ -21,13 +31,13 @@ async fn get_backups_from_path(directory_path: &str) -> Result<Vec<fs::DirEntry>
     Ok(files)
 }
 
-fn find_newest_backup(files: &[fs::DirEntry]) -> Option<PathBuf> {
+pub(crate) fn find_newest_backup(files: &[fs::DirEntry]) -> Option<PathBuf> {
     let mut newest_file: Option<PathBuf> = None;
     let mut newest_time = i64::MIN;
 
     for file in files {
         if let Some(file_name) = file.file_name().to_str() {
-            let file_name = file_name.replace(".chunkvault-snapshot", "");
+            let file_name = file_name.replace(SNAPSHOT_SUFFIX, "");
 
             if let Ok(time) = file_name.parse::<i64>() {
                 if time > newest_time {
@@ -41,101 +51,57 @@ fn find_newest_backup(files: &[fs::DirEntry]) -> Option<PathBuf> {
     newest_file
 }
 
-pub async fn grab_local_backup_list(vault: &str) -> Result<Vec<WorldData>, String> {
-    let backup_settings = get_backup_config().await?;
-
-    let local_backups_path = if let Some(vault_path) = backup_settings.vaults.get(vault) {
-        vault_path
-    } else {
-        return Err(format!("Vault {} does not exist", vault));
-    };
-
-    let mut backup_entries = fs::read_dir(local_backups_path)
-        .await
-        .map_err(|e| format!("Failed to read backups directory: {}", e))?;
-
-    let mut backups: Vec<WorldData> = Vec::new();
-
-    while let Some(entry) = backup_entries
-        .next_entry()
-        .await
-        .map_err(|e| format!("Failed to read backups directory: {}", e))?
-    {
-        let path = entry.path();
-
-        if path.is_dir() {
-            let all_backups = get_backups_from_path(path.to_str().unwrap())
-                .await
-                .map_err(|e| format!("Failed to read backups directory: {}", e))?;
-
-            let newest_backup = find_newest_backup(&all_backups);
-
-            if let Some(newest_backup) = newest_backup {
-                let metadata = get_backup_meta_from_path(newest_backup).await;
-                if metadata.is_ok() {
-                    let world_data = metadata.unwrap();
-                    backups.push(world_data.entry);
-                } else {
-                    continue;
-                }
-            }
-        }
+/// Opens the vault picked in the frontend, which has no default.
+fn selected_vault_backend(
+    backup_settings: &BackupSettings,
+    selected_vault: Option<&str>,
+) -> Result<Box<dyn VaultBackend>, Error> {
+    match selected_vault {
+        Some(selected_vault) => vault_backend(backup_settings, selected_vault),
+        None => Err(Error::VaultNotFound("No vault selected".to_string())),
     }
+}
 
-    Ok(backups)
+pub async fn grab_local_backup_list(vault: &str) -> Result<Vec<WorldData>, Error> {
+    let catalog = get_vault_catalog(vault).await?;
+
+    Ok(catalog
+        .worlds
+        .into_values()
+        .filter_map(|world| world.entry)
+        .collect())
 }
 
 pub async fn grab_world_backups(
     world_id: &str,
     selected_vault: Option<&str>,
-) -> Result<Vec<SnapshotInfo>, String> {
+) -> Result<Vec<SnapshotInfo>, Error> {
     let backup_settings = get_backup_config().await?;
 
-    let world_path = if let Some(selected_vault) = selected_vault {
-        if let Some(vault_path) = backup_settings.vaults.get(selected_vault) {
-            vault_path.join(world_id)
-        } else {
-            return Err(format!("Vault {} does not exist", selected_vault));
-        }
-    } else {
-        return Err("No vault selected".to_string());
+    let vault = selected_vault_backend(&backup_settings, selected_vault)?;
+
+    let catalog = get_vault_catalog(selected_vault.unwrap_or_default()).await?;
+
+    let snapshots = match catalog.worlds.get(world_id) {
+        Some(world) => &world.snapshots,
+        None => return Ok(Vec::new()),
     };
 
-    let files = get_backups_from_path(world_path.to_str().unwrap())
-        .await
-        .map_err(|e| format!("Failed to read backups directory: {}", e))?;
-
-    let mut backups = Vec::new();
-
-    for entry in files {
-        if entry
-            .file_name()
-            .to_str()
-            .unwrap()
-            .contains(".chunkvault-snapshot")
-        {
-            let path = entry.path();
-
-            let created = path
-                .file_name()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .replace(".chunkvault-snapshot", "");
-            let created = created.parse::<i64>().unwrap();
-
-            let metadata = fs::metadata(&path).await.unwrap();
-            let size = metadata.len();
-
-            let data = SnapshotInfo {
-                created,
-                size,
-                path,
+    let backups = snapshots
+        .iter()
+        .map(|snapshot| {
+            let path = match local_snapshot_path(vault.as_ref(), world_id, &snapshot.snapshot_id) {
+                Some(path) => path,
+                None => Path::new(world_id).join(snapshot_file_name(&snapshot.snapshot_id)),
             };
 
-            backups.push(data);
-        }
-    }
+            SnapshotInfo {
+                created: snapshot.created,
+                size: snapshot.size,
+                path,
+            }
+        })
+        .collect();
 
     Ok(backups)
 }
@@ -143,26 +109,18 @@ pub async fn grab_world_backups(
 pub async fn get_world_metadata_from_id(
     world_id: &str,
     selected_vault: Option<&str>,
-) -> Result<BackupMetadata, String> {
+) -> Result<BackupMetadata, Error> {
     let backup_settings = get_backup_config().await?;
 
-    let world_path = if let Some(selected_vault) = selected_vault {
-        if let Some(vault_path) = backup_settings.vaults.get(selected_vault) {
-            vault_path.join(world_id).to_owned()
-        } else {
-            return Err(format!("Vault {} does not exist", selected_vault));
-        }
-    } else {
-        return Err("No vault selected".to_string());
-    };
+    let vault = selected_vault_backend(&backup_settings, selected_vault)?;
 
-    let files = get_backups_from_path(world_path.to_str().unwrap())
-        .await
-        .map_err(|e| format!("Failed to read backups directory: {}", e))?;
+    let snapshot_ids = vault.list_snapshots(world_id).await?;
 
-    match find_newest_backup(&files) {
-        Some(newest_backup) => get_backup_meta_from_path(newest_backup).await,
-        None => Err("No backups found".to_string()),
+    match find_newest_snapshot(&snapshot_ids) {
+        Some(newest_backup) => {
+            read_snapshot_metadata(vault.as_ref(), world_id, newest_backup).await
+        }
+        None => Err(Error::SnapshotNotFound("No backups found".to_string())),
     }
 }
 
@@ -170,24 +128,10 @@ pub async fn grab_backup_metadata(
     world_id: &str,
     selected_vault: Option<&str>,
     backup_id: &str,
-) -> Result<BackupMetadata, String> {
+) -> Result<BackupMetadata, Error> {
     let backup_settings = get_backup_config().await?;
 
-    let world_path = if let Some(selected_vault) = selected_vault {
-        if let Some(vault_path) = backup_settings.vaults.get(selected_vault) {
-            vault_path.join(world_id).to_owned()
-        } else {
-            return Err(format!("Vault {} does not exist", selected_vault));
-        }
-    } else {
-        return Err("No vault selected".to_string());
-    };
-
-    let backup_path = world_path.join(format!("{}.chunkvault-snapshot", backup_id));
+    let vault = selected_vault_backend(&backup_settings, selected_vault)?;
 
-    if backup_path.exists() {
-        get_backup_meta_from_path(backup_path).await
-    } else {
-        Err("Backup does not exist".to_string())
-    }
+    read_snapshot_metadata(vault.as_ref(), world_id, backup_id).await
 }
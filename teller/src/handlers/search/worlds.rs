@@ -12,13 +12,16 @@ use crate::{
         },
         world::{get_vault_id, parse_world_entry_data, process_world_data, GameType},
     },
-    types::world::{WorldData, WorldLevelData},
+    types::{
+        error::Error,
+        world::{WorldData, WorldLevelData},
+    },
 };
 
 pub async fn fetch_worlds_from_instance(
     selected_category: &str,
     instance: &str,
-) -> Result<Vec<WorldData>, String> {
+) -> Result<Vec<WorldData>, Error> {
     let mut worlds_list: Vec<WorldData> = Vec::new();
 
     let config_dir = get_config_folder();
@@ -27,7 +30,9 @@ pub async fn fetch_worlds_from_instance(
         Ok(config) => config,
         Err(e) => {
             error!("Could not get local directories config: {:?}", e);
-            return Err("Could not get local directories config".to_string());
+            return Err(Error::Config(
+                "Could not get local directories config".to_string(),
+            ));
         }
     };
     let local_saves_path = if selected_category == "default" {
@@ -35,7 +40,9 @@ pub async fn fetch_worlds_from_instance(
             Some(path) => path,
             None => {
                 error!("Could not find Minecraft save location");
-                return Err("Could not find Minecraft save location".to_string());
+                return Err(Error::WorldNotFound(
+                    "Could not find Minecraft save location".to_string(),
+                ));
             }
         }
     } else {
@@ -47,12 +54,12 @@ pub async fn fetch_worlds_from_instance(
                         "Could not find instance {} in category {}",
                         instance, selected_category
                     );
-                    return Err("Could not find instance".to_string());
+                    return Err(Error::WorldNotFound("Could not find instance".to_string()));
                 }
             },
             None => {
                 error!("Could not find category {}", selected_category);
-                return Err("Could not find category".to_string());
+                return Err(Error::WorldNotFound("Could not find category".to_string()));
             }
         }
     };
@@ -65,7 +72,9 @@ pub async fn fetch_worlds_from_instance(
             local_saves_path
         );
 
-        return Err("Could not find Minecraft save location".to_string());
+        return Err(Error::WorldNotFound(
+            "Could not find Minecraft save location".to_string(),
+        ));
     }
 
     let entries = match local_saves_path.read_dir() {
@@ -76,7 +85,9 @@ pub async fn fetch_worlds_from_instance(
                 local_saves_path, e
             );
 
-            return Err("Could not read Minecraft save location".to_string());
+            return Err(Error::World(
+                "Could not read Minecraft save location".to_string(),
+            ));
         }
     };
 
@@ -101,7 +112,7 @@ pub async fn get_world_path_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<PathBuf, String> {
+) -> Result<PathBuf, Error> {
     let config_dir = get_config_folder();
 
     info!("Searching for world: {}", world_id);
@@ -159,14 +170,14 @@ pub async fn get_world_path_by_id(
         }
     }
 
-    Err("Could not find world".to_string())
+    Err(Error::WorldNotFound("Could not find world".to_string()))
 }
 
 pub async fn get_world_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<WorldLevelData, String> {
+) -> Result<WorldLevelData, Error> {
     match get_world_path_by_id(world_id, category, instance).await {
         Ok(path) => {
             let game_type = is_minecraft_world(&path.clone());
@@ -174,13 +185,13 @@ pub async fn get_world_by_id(
                 Ok(data) => Ok(data),
                 Err(e) => {
                     error!("Could not process world data: {:?}", e);
-                    Err("Could not process world data".to_string())
+                    Err(e)
                 }
             }
         }
         Err(e) => {
             error!("Could not find world: {:?}", e);
-            Err("Could not find world".to_string())
+            Err(e)
         }
     }
 }
@@ -242,13 +253,13 @@ pub async fn recursive_world_search(
     depth: usize,
     max_depth: usize,
     save_folders: &mut Vec<PathBuf>,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     if depth > max_depth {
         return Ok(());
     }
 
     if !path.exists() {
-        return Err(format!("Path {:?} does not exist", path));
+        return Err(Error::World(format!("Path {:?} does not exist", path)));
     }
 
     if path.ends_with("node_modules") || path.extension().map_or(false, |ext| ext == "zip") {
@@ -1,90 +1,203 @@
-use std::{path::PathBuf, str::FromStr};
+use std::path::{Path, PathBuf};
 
-use log::info;
+use log::{error, info};
 
 use crate::handlers::{
-    backup::get_backup_meta_from_path, search::directories::get_directory_by_name,
-    world::new_vault_id,
+    backup::get_backup_meta_from_path, progress::Progress,
+    search::directories::get_directory_by_name, world::new_vault_id,
 };
+use crate::types::error::Error;
 
 use super::{
-    backup::extract_world_backup, config::backup::get_backup_config,
+    backup::extract_world_backup,
     search::worlds::get_world_path_by_id,
+    vault::{fetch_snapshot, open_vault},
 };
 
+/// Worlds being replaced are restored next to the world under this suffix,
+/// and only take its place once they were extracted completely.
+const RESTORE_SUFFIX: &str = ".restore-partial";
+/// Where the replaced world is moved while the restored one takes its place.
+const REPLACED_SUFFIX: &str = ".restore-old";
+
+/// A failed or cancelled restore leaves a half extracted world behind, which
+/// is removed so the game does not pick it up.
+fn discard_failed_restore(world_path: &Path, error: Error) -> Error {
+    info!("Restore did not finish, removing {:?}", world_path);
+    let _ = std::fs::remove_dir_all(world_path);
+
+    error
+}
+
+fn sibling_path(world_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = world_path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Moves a completely restored world into the place of `world_path`. The
+/// world it replaces is only deleted once the restored one is in place, and
+/// moved back if that fails.
+async fn replace_world(restored_path: &Path, world_path: &Path) -> Result<(), Error> {
+    let replaced_path = sibling_path(world_path, REPLACED_SUFFIX);
+
+    if replaced_path.exists() {
+        tokio::fs::remove_dir_all(&replaced_path).await?;
+    }
+
+    let replacing = world_path.exists();
+
+    if replacing {
+        tokio::fs::rename(world_path, &replaced_path)
+            .await
+            .map_err(|e| Error::io(format!("Failed to move {:?} aside", world_path), e))?;
+    }
+
+    if let Err(e) = tokio::fs::rename(restored_path, world_path).await {
+        if replacing {
+            if let Err(e) = tokio::fs::rename(&replaced_path, world_path).await {
+                error!(
+                    "Failed to move {:?} back to {:?}: {}",
+                    replaced_path, world_path, e
+                );
+            }
+        }
+
+        return Err(Error::io(
+            format!("Failed to move restored world into {:?}", world_path),
+            e,
+        ));
+    }
+
+    if replacing {
+        if let Err(e) = tokio::fs::remove_dir_all(&replaced_path).await {
+            error!("Failed to remove replaced world {:?}: {}", replaced_path, e);
+        }
+    }
+
+    Ok(())
+}
+
 pub async fn snapshot_to_world(
     snapshot_id: &str,
     selected_vault: Option<&str>,
     world_id: &str,
     replace: bool,
     instances: Vec<String>,
-) -> Result<(), String> {
-    let backup_settings = get_backup_config().await?;
-
-    let world_path = if let Some(selected_vault) = selected_vault {
-        if let Some(vault_path) = backup_settings.vaults.get(selected_vault) {
-            vault_path.join(world_id).to_owned()
-        } else {
-            return Err(format!("Vault {} does not exist", selected_vault));
-        }
-    } else {
-        return Err("No vault selected".to_string());
+    progress: &Progress,
+) -> Result<(), Error> {
+    let vault = match selected_vault {
+        Some(selected_vault) => open_vault(Some(selected_vault)).await?,
+        None => return Err(Error::VaultNotFound("No vault selected".to_string())),
     };
 
-    let backup_path = world_path.join(format!("{}.chunkvault-snapshot", snapshot_id));
-
-    info!("Restoring backup from {:?}", backup_path);
-
-    if backup_path.exists() {
-        for instance in instances {
-            let mut world_path = match get_world_path_by_id(world_id, None, Some(&instance)).await {
-                Ok(path) => path.to_owned(),
-                Err(_) => {
-                    let instance_path = get_directory_by_name(&instance, None).unwrap();
-
-                    let metadata = get_backup_meta_from_path(backup_path.clone()).await?;
-
-                    instance_path.join(&metadata.entry.name)
-                }
-            };
-
-            if replace {
-                if world_path.exists() {
-                    tokio::fs::remove_dir_all(&world_path)
-                        .await
-                        .map_err(|e| e.to_string())?;
-                }
-
-                tokio::fs::create_dir_all(&world_path)
-                    .await
-                    .map_err(|e| e.to_string())?;
-
-                extract_world_backup(backup_path.clone(), world_path).await?;
-            } else {
-                let mut copy_counter = 1;
-                let original_world_path = world_path.clone();
-
-                while world_path.exists() {
-                    let new_world_path = format!(
-                        "{}-copy({})",
-                        original_world_path.to_str().unwrap(),
-                        copy_counter
-                    );
-                    copy_counter += 1;
-                    world_path = PathBuf::from_str(&new_world_path).unwrap();
-                }
-
-                tokio::fs::create_dir_all(&world_path)
-                    .await
-                    .map_err(|e| e.to_string())?;
-                extract_world_backup(backup_path.clone(), world_path.clone().to_owned()).await?;
-
-                new_vault_id(&world_path).await?;
+    info!("Restoring backup {} of {}", snapshot_id, world_id);
+
+    let snapshot = fetch_snapshot(vault.as_ref(), world_id, snapshot_id).await?;
+    let backup_path = snapshot.path.clone();
+
+    for instance in instances {
+        let mut world_path = match get_world_path_by_id(world_id, None, Some(&instance)).await {
+            Ok(path) => path.to_owned(),
+            Err(_) => {
+                let instance_path = match get_directory_by_name(&instance, None) {
+                    Some(instance_path) => instance_path,
+                    None => {
+                        return Err(Error::Config(format!(
+                            "Instance {} does not exist",
+                            instance
+                        )))
+                    }
+                };
+
+                let metadata = get_backup_meta_from_path(backup_path.clone()).await?;
+
+                instance_path.join(&metadata.entry.name)
+            }
+        };
+
+        if replace {
+            let restore_path = sibling_path(&world_path, RESTORE_SUFFIX);
+
+            if restore_path.exists() {
+                tokio::fs::remove_dir_all(&restore_path).await?;
             }
+
+            tokio::fs::create_dir_all(&restore_path).await?;
+
+            extract_world_backup(
+                vault.as_ref(),
+                world_id,
+                backup_path.clone(),
+                restore_path.clone(),
+                progress,
+            )
+            .await
+            .map_err(|e| discard_failed_restore(&restore_path, e))?;
+
+            replace_world(&restore_path, &world_path)
+                .await
+                .map_err(|e| discard_failed_restore(&restore_path, e))?;
+        } else {
+            let mut copy_counter = 1;
+            let original_world_path = world_path.clone();
+
+            while world_path.exists() {
+                let mut new_world_path = original_world_path.clone().into_os_string();
+                new_world_path.push(format!("-copy({})", copy_counter));
+                copy_counter += 1;
+                world_path = new_world_path.into();
+            }
+
+            tokio::fs::create_dir_all(&world_path).await?;
+            extract_world_backup(
+                vault.as_ref(),
+                world_id,
+                backup_path.clone(),
+                world_path.clone(),
+                progress,
+            )
+            .await
+            .map_err(|e| discard_failed_restore(&world_path, e))?;
+
+            new_vault_id(&world_path).await?;
         }
-    } else {
-        return Err("Backup does not exist".to_string());
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replaces_worlds_only_once_restored() {
+        let dir = std::env::temp_dir().join(format!("teller-test-{}", uuid::Uuid::new_v4()));
+        let world_path = dir.join("world");
+        let restore_path = sibling_path(&world_path, RESTORE_SUFFIX);
+
+        tokio::fs::create_dir_all(&world_path).await.unwrap();
+        tokio::fs::write(world_path.join("level.dat"), "old")
+            .await
+            .unwrap();
+        tokio::fs::create_dir_all(&restore_path).await.unwrap();
+        tokio::fs::write(restore_path.join("level.dat"), "new")
+            .await
+            .unwrap();
+
+        replace_world(&restore_path, &world_path).await.unwrap();
+
+        let level = tokio::fs::read_to_string(world_path.join("level.dat")).await;
+        let mut left = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            left.push(entry.file_name().to_string_lossy().to_string());
+        }
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+
+        assert_eq!(level.unwrap(), "new");
+        assert_eq!(left, vec!["world"]);
+    }
+}
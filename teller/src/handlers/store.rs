@@ -0,0 +1,680 @@
+use std::collections::{HashMap, HashSet};
+use std::io::SeekFrom;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use async_recursion::async_recursion;
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::RwLock;
+
+use crate::handlers::search::backups::{find_newest_backup, get_backups_from_path};
+use crate::handlers::search::worlds::is_minecraft_world;
+use crate::types::backup::{ManifestEntry, RegionChunk, SnapshotManifest, VerifyReport};
+use crate::types::error::Error;
+
+use super::archive::{open_snapshot, read_named_entry, METADATA_FILE};
+use super::atomic::{commit_file, discard_partial, partial_path, write_atomic};
+use super::progress::Progress;
+use super::region::{
+    is_region_file, is_valid_location, parse_region_header, write_region_header, CHUNKS_PER_REGION,
+    HEADER_SIZE, SECTOR_SIZE,
+};
+use super::vault::SNAPSHOT_SUFFIX;
+use super::world::GameType;
+
+pub const OBJECTS_DIR: &str = ".objects";
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+pub(crate) const BUFFER_SIZE: usize = 64 * 1024;
+
+/// One lock per object store. Snapshots being stored hold it shared from
+/// the first object they reuse until their manifest is committed, garbage
+/// collection holds it exclusively, so it never removes an object a snapshot
+/// that is not on disk yet points at.
+static STORE_LOCKS: OnceLock<Mutex<HashMap<PathBuf, Arc<RwLock<()>>>>> = OnceLock::new();
+
+fn store_lock(vault_path: &Path) -> Arc<RwLock<()>> {
+    let vault_path = vault_path
+        .canonicalize()
+        .unwrap_or_else(|_| vault_path.to_path_buf());
+
+    let mut locks = STORE_LOCKS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    locks.entry(vault_path).or_default().clone()
+}
+
+fn object_path(objects_dir: &Path, hash: &str) -> PathBuf {
+    objects_dir.join(&hash[..2]).join(hash)
+}
+
+pub(crate) async fn hash_file(path: &Path) -> Result<(String, u64), std::io::Error> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((hex::encode(hasher.finalize()), size))
+}
+
+/// Adds a file to the object store, returning its hash and size. The file is
+/// hashed first so unchanged contents are never rewritten to the vault; if it
+/// changes while being copied it is stored under the hash of what was copied.
+async fn store_object(objects_dir: &Path, path: &Path) -> Result<(String, u64), std::io::Error> {
+    let (hash, size) = hash_file(path).await?;
+
+    if object_path(objects_dir, &hash).exists() {
+        return Ok((hash, size));
+    }
+
+    let temp_path = partial_path(&objects_dir.join(uuid::Uuid::new_v4().to_string()));
+
+    let mut source = File::open(path).await?;
+    let mut target = File::create(&temp_path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut size = 0;
+
+    loop {
+        let read = source.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        target.write_all(&buffer[..read]).await?;
+        size += read as u64;
+    }
+
+    target.flush().await?;
+    drop(target);
+
+    let hash = hex::encode(hasher.finalize());
+    let final_path = object_path(objects_dir, &hash);
+
+    if final_path.exists() {
+        fs::remove_file(&temp_path).await?;
+    } else {
+        if let Some(parent) = final_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        commit_file(&temp_path, &final_path).await?;
+    }
+
+    Ok((hash, size))
+}
+
+async fn store_bytes(objects_dir: &Path, data: &[u8]) -> Result<String, std::io::Error> {
+    let hash = hex::encode(Sha256::digest(data));
+    let final_path = object_path(objects_dir, &hash);
+
+    if final_path.exists() {
+        return Ok(hash);
+    }
+
+    if let Some(parent) = final_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    write_atomic(&final_path, data).await?;
+
+    Ok(hash)
+}
+
+/// Stores every chunk of an Anvil region file as its own object. Chunks whose
+/// header timestamp and sector count match the previous snapshot are reused
+/// without being read. Returns `None` if the file is not a valid region, in
+/// which case it should be stored whole.
+async fn store_region(
+    objects_dir: &Path,
+    path: &Path,
+    previous: Option<&Vec<RegionChunk>>,
+) -> Result<Option<(Vec<RegionChunk>, u64)>, std::io::Error> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+
+    if size < HEADER_SIZE as u64 {
+        return Ok(None);
+    }
+
+    let mut header = [0; HEADER_SIZE];
+    file.read_exact(&mut header).await?;
+
+    let locations = parse_region_header(&header);
+
+    if !locations
+        .iter()
+        .all(|location| is_valid_location(location, size))
+    {
+        return Ok(None);
+    }
+
+    let previous: HashMap<usize, &RegionChunk> = previous
+        .map(|chunks| chunks.iter().map(|chunk| (chunk.index, chunk)).collect())
+        .unwrap_or_default();
+
+    let mut chunks = Vec::with_capacity(locations.len());
+
+    for location in locations {
+        if let Some(previous_chunk) = previous.get(&location.index) {
+            if location.timestamp != 0
+                && previous_chunk.timestamp == location.timestamp
+                && previous_chunk.sectors == location.sectors
+                && object_path(objects_dir, &previous_chunk.hash).exists()
+            {
+                chunks.push(RegionChunk {
+                    index: location.index,
+                    offset: location.offset,
+                    sectors: location.sectors,
+                    timestamp: location.timestamp,
+                    hash: previous_chunk.hash.clone(),
+                });
+                continue;
+            }
+        }
+
+        file.seek(SeekFrom::Start(location.offset as u64 * SECTOR_SIZE))
+            .await?;
+
+        let mut length = [0; 4];
+        file.read_exact(&mut length).await?;
+        let length = u32::from_be_bytes(length) as u64;
+
+        if length == 0 || length + 4 > location.sectors as u64 * SECTOR_SIZE {
+            return Ok(None);
+        }
+
+        let mut payload = vec![0; length as usize + 4];
+        payload[..4].copy_from_slice(&(length as u32).to_be_bytes());
+        file.read_exact(&mut payload[4..]).await?;
+
+        let hash = store_bytes(objects_dir, &payload).await?;
+
+        chunks.push(RegionChunk {
+            index: location.index,
+            offset: location.offset,
+            sectors: location.sectors,
+            timestamp: location.timestamp,
+            hash,
+        });
+    }
+
+    Ok(Some((chunks, size)))
+}
+
+async fn rebuild_region(
+    objects_dir: &Path,
+    chunks: &[RegionChunk],
+    size: u64,
+    path: &Path,
+) -> Result<(), std::io::Error> {
+    if chunks.iter().any(|chunk| chunk.index >= CHUNKS_PER_REGION) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "chunk index outside of region",
+        ));
+    }
+
+    let mut file = File::create(path).await?;
+
+    file.write_all(&write_region_header(chunks)).await?;
+
+    let mut end = HEADER_SIZE as u64;
+
+    for chunk in chunks {
+        let payload = fs::read(object_path(objects_dir, &chunk.hash)).await?;
+        let start = chunk.offset as u64 * SECTOR_SIZE;
+
+        file.seek(SeekFrom::Start(start)).await?;
+        file.write_all(&payload).await?;
+
+        end = end.max(start + chunk.sectors as u64 * SECTOR_SIZE);
+    }
+
+    file.set_len(end.max(size)).await?;
+    file.flush().await?;
+
+    Ok(())
+}
+
+#[async_recursion]
+async fn store_directory(
+    objects_dir: &Path,
+    directory: &Path,
+    prefix: &Path,
+    previous_regions: &HashMap<String, Vec<RegionChunk>>,
+    split_regions: bool,
+    manifest: &mut SnapshotManifest,
+) -> Result<(), std::io::Error> {
+    let mut entries = fs::read_dir(directory).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+
+        if path.is_file() {
+            let name = match path.strip_prefix(prefix) {
+                Ok(name) => name,
+                Err(_) => continue,
+            };
+
+            let name = name.to_string_lossy().replace('\\', "/");
+
+            if split_regions && is_region_file(&path) {
+                if let Some((chunks, size)) =
+                    store_region(objects_dir, &path, previous_regions.get(&name)).await?
+                {
+                    manifest.files.push(ManifestEntry {
+                        path: name,
+                        hash: String::new(),
+                        size,
+                        region: Some(chunks),
+                    });
+                    continue;
+                }
+            }
+
+            let (hash, size) = store_object(objects_dir, &path).await?;
+
+            manifest.files.push(ManifestEntry {
+                path: name,
+                hash,
+                size,
+                region: None,
+            });
+        } else if path.is_dir() {
+            store_directory(
+                objects_dir,
+                &path,
+                prefix,
+                previous_regions,
+                split_regions,
+                manifest,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the region chunks of the newest snapshot of a world, so that
+/// chunks which have not been saved since can be reused.
+async fn get_previous_regions(backups_path: &Path) -> HashMap<String, Vec<RegionChunk>> {
+    let newest_backup = match get_backups_from_path(backups_path).await {
+        Ok(files) => find_newest_backup(&files),
+        Err(_) => None,
+    };
+
+    let manifest = match newest_backup {
+        Some(newest_backup) => match read_snapshot_manifest(&newest_backup).await {
+            Ok(Some(manifest)) => manifest,
+            _ => return HashMap::new(),
+        },
+        None => return HashMap::new(),
+    };
+
+    manifest
+        .files
+        .into_iter()
+        .filter_map(|file| file.region.map(|chunks| (file.path, chunks)))
+        .collect()
+}
+
+async fn write_store_snapshot(path: &Path, metadata: &str, manifest: &str) -> Result<(), Error> {
+    let file = File::create(path)
+        .await
+        .map_err(|e| Error::io(format!("Failed to create snapshot {:?}", path), e))?;
+
+    let mut zip = ZipFileWriter::with_tokio(file);
+
+    let meta_builder = ZipEntryBuilder::new(METADATA_FILE.into(), Compression::Stored);
+    zip.write_entry_whole(meta_builder, metadata.as_bytes())
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!("Failed to write snapshot metadata: {:?}", e))
+        })?;
+
+    let manifest_builder = ZipEntryBuilder::new(MANIFEST_FILE.into(), Compression::Deflate);
+    zip.write_entry_whole(manifest_builder, manifest.as_bytes())
+        .await
+        .map_err(|e| {
+            Error::SnapshotFormat(format!("Failed to write snapshot manifest: {:?}", e))
+        })?;
+
+    zip.close().await.map_err(|e| {
+        Error::SnapshotFormat(format!("Failed to finish snapshot {:?}: {:?}", path, e))
+    })?;
+
+    Ok(())
+}
+
+pub async fn store_world_snapshot(
+    world_path: &Path,
+    vault_path: &Path,
+    world_id: &str,
+    snapshot_name: &str,
+    metadata: &str,
+) -> Result<PathBuf, Error> {
+    let objects_dir = vault_path.join(OBJECTS_DIR);
+
+    fs::create_dir_all(&objects_dir).await.map_err(|e| {
+        Error::io(
+            format!("Failed to create object store {:?}", objects_dir),
+            e,
+        )
+    })?;
+
+    info!(
+        "Storing world {} in object store {:?}",
+        world_id, objects_dir
+    );
+
+    let lock = store_lock(vault_path);
+    let _guard = lock.read().await;
+
+    let split_regions = is_minecraft_world(&world_path.to_path_buf()) == GameType::Java;
+
+    let previous_regions = match split_regions {
+        true => get_previous_regions(&vault_path.join(world_id)).await,
+        false => HashMap::new(),
+    };
+
+    let mut manifest = SnapshotManifest::default();
+
+    store_directory(
+        &objects_dir,
+        world_path,
+        world_path,
+        &previous_regions,
+        split_regions,
+        &mut manifest,
+    )
+    .await
+    .map_err(|e| Error::io("Failed to store world files", e))?;
+
+    let manifest = serde_json::to_string(&manifest).map_err(|e| {
+        Error::SnapshotFormat(format!("Failed to serialize snapshot manifest: {:?}", e))
+    })?;
+
+    let backup_location = vault_path.join(world_id);
+
+    fs::create_dir_all(&backup_location).await.map_err(|e| {
+        Error::io(
+            format!("Failed to create vault folder {:?}", backup_location),
+            e,
+        )
+    })?;
+
+    let backup_path = backup_location.join(snapshot_name);
+
+    let partial = partial_path(&backup_path);
+
+    let written = match write_store_snapshot(&partial, metadata, &manifest).await {
+        Ok(_) => commit_file(&partial, &backup_path)
+            .await
+            .map_err(|e| Error::io(format!("Failed to finish snapshot {:?}", backup_path), e)),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = written {
+        discard_partial(&partial).await;
+        return Err(e);
+    }
+
+    Ok(backup_path)
+}
+
+/// Returns the manifest of a deduplicated snapshot, or `None` if the snapshot
+/// is a self-contained archive.
+pub async fn read_snapshot_manifest(backup_path: &Path) -> Result<Option<SnapshotManifest>, Error> {
+    let mut zip = open_snapshot(backup_path).await?;
+
+    let manifest = match read_named_entry(&mut zip, MANIFEST_FILE, backup_path).await? {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+
+    let manifest = serde_json::from_str(&manifest).map_err(|e| {
+        Error::SnapshotFormat(format!(
+            "Failed to parse manifest in backup {}: {:?}",
+            backup_path.display(),
+            e
+        ))
+    })?;
+
+    Ok(Some(manifest))
+}
+
+pub(crate) fn is_safe_relative_path(path: &Path) -> bool {
+    path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+pub async fn extract_store_snapshot(
+    manifest: &SnapshotManifest,
+    vault_path: &Path,
+    extract_path: &Path,
+    progress: &Progress,
+) -> Result<(), Error> {
+    let objects_dir = vault_path.join(OBJECTS_DIR);
+
+    for file in &manifest.files {
+        progress.check_cancelled()?;
+        progress.advance(file.size);
+
+        let relative_path = Path::new(&file.path);
+
+        if !is_safe_relative_path(relative_path) {
+            return Err(Error::SnapshotFormat(format!(
+                "Refusing to extract unsafe path {}",
+                file.path
+            )));
+        }
+
+        let path = extract_path.join(relative_path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        if let Some(chunks) = &file.region {
+            rebuild_region(&objects_dir, chunks, file.size, &path)
+                .await
+                .map_err(|e| Error::io(format!("Failed to rebuild region {}", file.path), e))?;
+            continue;
+        }
+
+        let object = object_path(&objects_dir, &file.hash);
+
+        let mut source = File::open(&object).await.map_err(|e| {
+            Error::Vault(format!(
+                "Failed to open object {} for {}: {:?}",
+                file.hash, file.path, e
+            ))
+        })?;
+        let mut target = File::create(&path).await?;
+
+        tokio::io::copy(&mut source, &mut target).await?;
+    }
+
+    Ok(())
+}
+
+async fn referenced_objects(vault_path: &Path) -> Result<HashSet<String>, Error> {
+    let mut referenced = HashSet::new();
+
+    let mut world_dirs = fs::read_dir(vault_path)
+        .await
+        .map_err(|e| Error::io(format!("Failed to read vault {:?}", vault_path), e))?;
+
+    while let Some(world_dir) = world_dirs.next_entry().await? {
+        let world_path = world_dir.path();
+
+        if !world_path.is_dir() || world_dir.file_name() == OBJECTS_DIR {
+            continue;
+        }
+
+        let mut snapshots = fs::read_dir(&world_path)
+            .await
+            .map_err(|e| Error::io(format!("Failed to read {:?}", world_path), e))?;
+
+        while let Some(snapshot) = snapshots.next_entry().await? {
+            let snapshot_path = snapshot.path();
+
+            if !snapshot_path.to_string_lossy().ends_with(SNAPSHOT_SUFFIX) {
+                continue;
+            }
+
+            if let Some(manifest) = read_snapshot_manifest(&snapshot_path).await? {
+                for file in manifest.files {
+                    match file.region {
+                        Some(chunks) => {
+                            referenced.extend(chunks.into_iter().map(|chunk| chunk.hash))
+                        }
+                        None => {
+                            referenced.insert(file.hash);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(referenced)
+}
+
+/// Lists the objects in a vault's object store that no snapshot references,
+/// as `(hash, path)` pairs.
+pub async fn find_unreferenced_objects(vault_path: &Path) -> Result<Vec<(String, PathBuf)>, Error> {
+    let objects_dir = vault_path.join(OBJECTS_DIR);
+
+    if !objects_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let referenced = referenced_objects(vault_path).await?;
+
+    let mut unreferenced = Vec::new();
+
+    let mut prefixes = fs::read_dir(&objects_dir)
+        .await
+        .map_err(|e| Error::io(format!("Failed to read object store {:?}", objects_dir), e))?;
+
+    while let Some(prefix) = prefixes.next_entry().await? {
+        if !prefix.path().is_dir() {
+            continue;
+        }
+
+        let mut objects = fs::read_dir(prefix.path()).await?;
+
+        while let Some(object) = objects.next_entry().await? {
+            let hash = object.file_name().to_string_lossy().to_string();
+
+            if !referenced.contains(&hash) {
+                unreferenced.push((hash, object.path()));
+            }
+        }
+    }
+
+    Ok(unreferenced)
+}
+
+/// Removes objects from a vault's object store that are no longer referenced
+/// by any snapshot. Nothing is removed if any snapshot cannot be read, since
+/// its references would be unknown. Waits for snapshots that are still being
+/// stored in the vault.
+pub async fn collect_garbage(vault_path: &Path) -> Result<usize, Error> {
+    let lock = store_lock(vault_path);
+    let _guard = lock.write().await;
+
+    let unreferenced = match find_unreferenced_objects(vault_path).await {
+        Ok(unreferenced) => unreferenced,
+        Err(e) => {
+            error!("Skipping garbage collection of {:?}: {}", vault_path, e);
+            return Err(e);
+        }
+    };
+
+    let mut removed = 0;
+
+    for (hash, path) in unreferenced {
+        match fs::remove_file(path).await {
+            Ok(_) => removed += 1,
+            Err(e) => warn!("Failed to remove unreferenced object {}: {:?}", hash, e),
+        }
+    }
+
+    info!(
+        "Removed {} unreferenced objects from {:?}",
+        removed,
+        vault_path.join(OBJECTS_DIR)
+    );
+
+    Ok(removed)
+}
+
+enum ObjectState {
+    Intact(u64),
+    Missing,
+    Corrupted,
+}
+
+async fn check_object(objects_dir: &Path, hash: &str) -> ObjectState {
+    let path = object_path(objects_dir, hash);
+
+    if !path.exists() {
+        return ObjectState::Missing;
+    }
+
+    match hash_file(&path).await {
+        Ok((actual, size)) if actual == hash => ObjectState::Intact(size),
+        _ => ObjectState::Corrupted,
+    }
+}
+
+/// Re-hashes every object a deduplicated snapshot points at.
+pub async fn verify_store_snapshot(
+    manifest: &SnapshotManifest,
+    vault_path: &Path,
+    report: &mut VerifyReport,
+) {
+    let objects_dir = vault_path.join(OBJECTS_DIR);
+
+    for file in &manifest.files {
+        report.checked += 1;
+
+        match &file.region {
+            Some(chunks) => {
+                for chunk in chunks {
+                    match check_object(&objects_dir, &chunk.hash).await {
+                        ObjectState::Intact(_) => {}
+                        ObjectState::Missing => report
+                            .missing
+                            .push(format!("{} (chunk {})", file.path, chunk.index)),
+                        ObjectState::Corrupted => report
+                            .corrupted
+                            .push(format!("{} (chunk {})", file.path, chunk.index)),
+                    }
+                }
+            }
+            None => match check_object(&objects_dir, &file.hash).await {
+                ObjectState::Intact(size) if size == file.size => {}
+                ObjectState::Missing => report.missing.push(file.path.clone()),
+                _ => report.corrupted.push(file.path.clone()),
+            },
+        }
+    }
+}
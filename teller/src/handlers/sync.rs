@@ -0,0 +1,359 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use log::{error, info};
+
+use crate::handlers::backup::get_backup_meta_from_path;
+use crate::handlers::catalog::{forget_snapshots, record_snapshot};
+use crate::handlers::config::backup::get_backup_config;
+use crate::handlers::vault::{fetch_snapshot, vault_backend, VaultBackend};
+use crate::types::backup::{
+    BackupMetadata, BackupSettings, SyncFailure, SyncReport, SyncedSnapshot, VaultStorage,
+};
+use crate::types::error::Error;
+use crate::types::progress::ProgressStage;
+
+use super::progress::Progress;
+use super::store::hash_file;
+use super::transfer::put_snapshot_with_retries;
+
+/// Snapshots are copied as they are stored, so both vaults have to keep full
+/// snapshots, and encrypted ones have to share the key.
+fn check_compatible(
+    backup_settings: &BackupSettings,
+    source: &str,
+    destination: &str,
+) -> Result<(), Error> {
+    if source == destination {
+        return Err(Error::Config(format!(
+            "Cannot sync vault {} with itself",
+            source
+        )));
+    }
+
+    for vault_id in [source, destination] {
+        if backup_settings.vault_storage(vault_id) == VaultStorage::Deduplicated {
+            return Err(Error::Config(format!(
+                "Vault {} uses deduplicated storage, which cannot be synced",
+                vault_id
+            )));
+        }
+    }
+
+    let same_key = match (
+        backup_settings.vault_encryption(source),
+        backup_settings.vault_encryption(destination),
+    ) {
+        (None, None) => true,
+        (Some(source), Some(destination)) => {
+            source.salt == destination.salt && source.key_check == destination.key_check
+        }
+        _ => false,
+    };
+
+    match same_key {
+        true => Ok(()),
+        false => Err(Error::Config(format!(
+            "Vaults {} and {} are not encrypted with the same key",
+            source, destination
+        ))),
+    }
+}
+
+/// Oldest first, so incremental snapshots arrive after their parents.
+fn sort_snapshot_ids(snapshot_ids: &mut [String]) {
+    snapshot_ids.sort_by_key(|id| (id.parse::<i64>().unwrap_or(i64::MAX), id.clone()));
+}
+
+/// Copies a snapshot and checks that the destination holds the same bytes.
+/// A copy that does not match is removed again. Incremental snapshots whose
+/// parent is in `failed` are not copied, since they could not be restored
+/// from the destination. Returns the size of the snapshot and its metadata,
+/// when it can be read without the vault's key.
+async fn copy_snapshot(
+    source: &dyn VaultBackend,
+    destination: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+    failed: &HashSet<String>,
+) -> Result<(u64, Option<BackupMetadata>), Error> {
+    let snapshot = fetch_snapshot(source, world_id, snapshot_id).await?;
+
+    let metadata = get_backup_meta_from_path(snapshot.path.clone()).await.ok();
+
+    if let Some(parent) = metadata
+        .as_ref()
+        .and_then(|metadata| metadata.parent.as_ref())
+    {
+        if failed.contains(parent) {
+            return Err(Error::Vault(format!(
+                "Parent backup {} of {} could not be synced",
+                parent, snapshot_id
+            )));
+        }
+    }
+
+    let (hash, size) = hash_file(&snapshot.path).await.map_err(|e| {
+        Error::io(
+            format!("Failed to read backup {}", snapshot.path.display()),
+            e,
+        )
+    })?;
+
+    put_snapshot_with_retries(destination, world_id, snapshot_id, &snapshot.path).await?;
+
+    let copied_size = destination.stat_snapshot(world_id, snapshot_id).await?.size;
+
+    let mismatch = match copied_size == size {
+        true => {
+            let copy = fetch_snapshot(destination, world_id, snapshot_id).await?;
+            let (copied_hash, _) = hash_file(&copy.path).await.map_err(|e| {
+                Error::io(format!("Failed to read backup {}", copy.path.display()), e)
+            })?;
+
+            (copied_hash != hash).then(|| "its hash does not match".to_string())
+        }
+        false => Some(format!("it has {} of {} bytes", copied_size, size)),
+    };
+
+    if let Some(mismatch) = mismatch {
+        if let Err(e) = destination.delete_snapshot(world_id, snapshot_id).await {
+            error!("Failed to remove bad copy of {}: {}", snapshot_id, e);
+        }
+
+        return Err(Error::Vault(format!(
+            "Copy of backup {} is corrupted, {}",
+            snapshot_id, mismatch
+        )));
+    }
+
+    Ok((size, metadata))
+}
+
+/// Removes the snapshots of a world the source no longer has, newest first
+/// so incremental snapshots go before their parents.
+async fn mirror_deletions(
+    destination_id: &str,
+    destination: &dyn VaultBackend,
+    world_id: &str,
+    source_ids: &HashSet<String>,
+    destination_ids: &[String],
+    report: &mut SyncReport,
+) {
+    let mut removed: Vec<&String> = destination_ids
+        .iter()
+        .filter(|id| !source_ids.contains(*id))
+        .collect();
+    removed.sort_by_key(|id| Reverse(id.parse::<i64>().unwrap_or(i64::MIN)));
+
+    for snapshot_id in removed {
+        let size = match destination.stat_snapshot(world_id, snapshot_id).await {
+            Ok(stat) => stat.size,
+            Err(_) => 0,
+        };
+
+        match destination.delete_snapshot(world_id, snapshot_id).await {
+            Ok(_) => {
+                forget_snapshots(destination_id, [(world_id, snapshot_id.as_str())]).await;
+
+                report.deleted.push(SyncedSnapshot {
+                    world_id: world_id.to_string(),
+                    snapshot_id: snapshot_id.clone(),
+                    size,
+                });
+            }
+            Err(e) => report.failed.push(SyncFailure {
+                world_id: world_id.to_string(),
+                snapshot_id: Some(snapshot_id.clone()),
+                error: e.to_string(),
+            }),
+        }
+    }
+}
+
+/// Copies the snapshots `destination` is missing from `source`, for every
+/// world or only the worlds in `world_filter`. With `mirror`, snapshots the
+/// source does not have are removed from the destination as well.
+///
+/// Every copy is checked against the source's size and SHA-256. A world or
+/// snapshot that fails is reported and the sync moves on to the next one,
+/// skipping the incremental snapshots that build on it.
+pub async fn sync_vaults(
+    source: &str,
+    destination: &str,
+    world_filter: Option<&[String]>,
+    mirror: bool,
+    progress: &Progress,
+) -> Result<SyncReport, Error> {
+    let backup_settings = get_backup_config().await?;
+
+    check_compatible(&backup_settings, source, destination)?;
+
+    let source_vault = vault_backend(&backup_settings, source)?;
+    let destination_vault = vault_backend(&backup_settings, destination)?;
+
+    info!("Syncing vault {} to {}", source, destination);
+
+    let source_worlds = source_vault.list_worlds().await?;
+    let destination_worlds = destination_vault.list_worlds().await?;
+
+    let mut world_ids = source_worlds.clone();
+
+    if mirror {
+        for world_id in &destination_worlds {
+            if !source_worlds.contains(world_id) {
+                world_ids.push(world_id.clone());
+            }
+        }
+    }
+
+    if let Some(world_filter) = world_filter {
+        world_ids.retain(|world_id| world_filter.contains(world_id));
+    }
+
+    let mut report = SyncReport::default();
+    let mut pending = Vec::new();
+    // Snapshots that did not reach the destination, by world id.
+    let mut failed: HashMap<String, HashSet<String>> = HashMap::new();
+
+    for world_id in world_ids {
+        let listed = async {
+            // Worlds only the destination has are there to be mirrored away.
+            let source_ids = match source_worlds.contains(&world_id) {
+                true => source_vault.list_snapshots(&world_id).await?,
+                false => Vec::new(),
+            };
+
+            let destination_ids = match destination_worlds.contains(&world_id) {
+                true => destination_vault.list_snapshots(&world_id).await?,
+                false => Vec::new(),
+            };
+
+            Ok::<_, Error>((source_ids, destination_ids))
+        }
+        .await;
+
+        // A world that cannot be listed is left alone, mirroring it could
+        // remove snapshots the source still has.
+        let (mut source_ids, destination_ids) = match listed {
+            Ok(snapshot_ids) => snapshot_ids,
+            Err(e) => {
+                error!("Failed to list backups of {}: {}", world_id, e);
+                report.failed.push(SyncFailure {
+                    world_id,
+                    snapshot_id: None,
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        sort_snapshot_ids(&mut source_ids);
+
+        for snapshot_id in source_ids.iter() {
+            if destination_ids.contains(snapshot_id) {
+                report.unchanged += 1;
+                continue;
+            }
+
+            match source_vault.stat_snapshot(&world_id, snapshot_id).await {
+                Ok(stat) => pending.push((world_id.clone(), snapshot_id.clone(), stat.size)),
+                Err(e) => {
+                    error!(
+                        "Failed to read backup {} of {}: {}",
+                        snapshot_id, world_id, e
+                    );
+                    failed
+                        .entry(world_id.clone())
+                        .or_default()
+                        .insert(snapshot_id.clone());
+                    report.failed.push(SyncFailure {
+                        world_id: world_id.clone(),
+                        snapshot_id: Some(snapshot_id.clone()),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        if mirror {
+            let source_ids: HashSet<String> = source_ids.into_iter().collect();
+
+            mirror_deletions(
+                destination,
+                destination_vault.as_ref(),
+                &world_id,
+                &source_ids,
+                &destination_ids,
+                &mut report,
+            )
+            .await;
+        }
+    }
+
+    progress.start(
+        ProgressStage::Copying,
+        pending.iter().map(|(_, _, size)| size).sum(),
+    );
+
+    for (world_id, snapshot_id, size) in pending {
+        if progress.is_cancelled() {
+            report.cancelled = true;
+            break;
+        }
+
+        let failed_ids = failed.entry(world_id.clone()).or_default();
+
+        match copy_snapshot(
+            source_vault.as_ref(),
+            destination_vault.as_ref(),
+            &world_id,
+            &snapshot_id,
+            failed_ids,
+        )
+        .await
+        {
+            Ok((size, metadata)) => {
+                record_snapshot(
+                    destination,
+                    destination_vault.as_ref(),
+                    VaultStorage::Full,
+                    &world_id,
+                    &snapshot_id,
+                    metadata,
+                )
+                .await;
+
+                report.copied.push(SyncedSnapshot {
+                    world_id,
+                    snapshot_id,
+                    size,
+                });
+            }
+            Err(e) => {
+                error!(
+                    "Failed to copy backup {} of {} to vault {}: {}",
+                    snapshot_id, world_id, destination, e
+                );
+                failed_ids.insert(snapshot_id.clone());
+                report.failed.push(SyncFailure {
+                    world_id,
+                    snapshot_id: Some(snapshot_id),
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        progress.advance(size);
+    }
+
+    info!(
+        "Synced vault {} to {}: {} copied, {} deleted, {} failed",
+        source,
+        destination,
+        report.copied.len(),
+        report.deleted.len(),
+        report.failed.len()
+    );
+
+    Ok(report)
+}
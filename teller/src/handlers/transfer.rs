@@ -0,0 +1,311 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use futures_util::StreamExt;
+use log::error;
+use reqwest::Body;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use crate::types::backup::{BandwidthSettings, UploadState};
+use crate::types::error::Error;
+
+use super::atomic::write_atomic;
+use super::lock::is_minecraft_running;
+use super::vault::VaultBackend;
+
+/// Folder in the config folder interrupted uploads are recorded in.
+const UPLOADS_DIR: &str = "uploads";
+
+/// Attempts an upload gets before it counts as failed. Attempts after the
+/// first resume where the last one stopped on S3, SFTP and WebDAV vaults,
+/// and start over on ChunkVault remote vaults, which take a snapshot in one
+/// request.
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Wait before the second attempt, doubled for every one after.
+const UPLOAD_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// How long the throttle trusts its last look at whether Minecraft runs.
+const PLAYING_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+struct ThrottleState {
+    settings: BandwidthSettings,
+    /// Set by a background thread, so uploads never wait for the check.
+    playing: Arc<AtomicBool>,
+    checked_at: Option<Instant>,
+    /// When the bytes handed out so far have been sent at the current rate.
+    next_free: Instant,
+}
+
+impl ThrottleState {
+    fn limit(&mut self) -> Option<u64> {
+        if self.settings.upload_limit_while_playing.is_some() {
+            let stale = match self.checked_at {
+                Some(checked_at) => checked_at.elapsed() >= PLAYING_CHECK_INTERVAL,
+                None => true,
+            };
+
+            if stale {
+                self.checked_at = Some(Instant::now());
+
+                let playing = self.playing.clone();
+                std::thread::spawn(move || {
+                    playing.store(is_minecraft_running(), Ordering::Relaxed);
+                });
+            }
+        }
+
+        match self.playing.load(Ordering::Relaxed) {
+            true => self
+                .settings
+                .upload_limit_while_playing
+                .or(self.settings.upload_limit),
+            false => self.settings.upload_limit,
+        }
+        .filter(|limit| *limit > 0)
+    }
+}
+
+/// Limits the rate of uploads to the caps in `BandwidthSettings`. Clones share
+/// their budget, so parallel uploads split the cap. The default is unlimited.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    state: Option<Arc<Mutex<ThrottleState>>>,
+}
+
+impl Throttle {
+    pub fn new(settings: &BandwidthSettings) -> Self {
+        if settings.upload_limit.is_none() && settings.upload_limit_while_playing.is_none() {
+            return Self::default();
+        }
+
+        Self {
+            state: Some(Arc::new(Mutex::new(ThrottleState {
+                settings: settings.clone(),
+                playing: Arc::new(AtomicBool::new(false)),
+                checked_at: None,
+                next_free: Instant::now(),
+            }))),
+        }
+    }
+
+    /// Reserves `bytes` of the budget, returning how long to wait before
+    /// sending them.
+    fn reserve(&self, bytes: u64) -> Duration {
+        let mut state = match self.state.as_ref().and_then(|state| state.lock().ok()) {
+            Some(state) => state,
+            None => return Duration::ZERO,
+        };
+
+        let limit = match state.limit() {
+            Some(limit) => limit,
+            None => return Duration::ZERO,
+        };
+
+        let now = Instant::now();
+        let start = state.next_free.max(now);
+        state.next_free = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+
+        start - now
+    }
+
+    pub async fn consume(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like `consume`, for uploads running on a blocking thread.
+    pub fn consume_blocking(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// The throttle every upload shares, with the settings it was built from.
+static SHARED_THROTTLE: OnceLock<Mutex<Option<(BandwidthSettings, Throttle)>>> = OnceLock::new();
+
+/// Returns the one throttle all uploads of the process share, so the caps
+/// hold across vaults and jobs. It is only rebuilt when the settings change.
+pub fn shared_throttle(settings: &BandwidthSettings) -> Throttle {
+    let mut shared = SHARED_THROTTLE
+        .get_or_init(|| Mutex::new(None))
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    match shared.as_ref() {
+        Some((shared_settings, throttle)) if shared_settings == settings => throttle.clone(),
+        _ => {
+            let throttle = Throttle::new(settings);
+            *shared = Some((settings.clone(), throttle.clone()));
+            throttle
+        }
+    }
+}
+
+/// A request body streaming `file` no faster than the throttle allows.
+pub(crate) fn throttled_body(file: File, throttle: Throttle) -> Body {
+    let stream = ReaderStream::new(file).then(move |chunk| {
+        let throttle = throttle.clone();
+
+        async move {
+            if let Ok(chunk) = &chunk {
+                throttle.consume(chunk.len() as u64).await;
+            }
+
+            chunk
+        }
+    });
+
+    Body::wrap_stream(stream)
+}
+
+/// Stores a snapshot in a vault, trying again when the connection failed.
+pub(crate) async fn put_snapshot_with_retries(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+    source: &Path,
+) -> Result<(), Error> {
+    let mut attempt = 1;
+
+    loop {
+        match vault.put_snapshot(world_id, snapshot_id, source).await {
+            Err(Error::Network(e)) if attempt < UPLOAD_ATTEMPTS => {
+                let delay = UPLOAD_RETRY_DELAY * 2u32.pow(attempt - 1);
+
+                error!(
+                    "Upload of backup {} failed, retrying in {}s: {}",
+                    snapshot_id,
+                    delay.as_secs(),
+                    e
+                );
+
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn uploads_dir() -> PathBuf {
+    super::config::get_config_folder().join(UPLOADS_DIR)
+}
+
+/// Tests record their uploads in the temp folder, never in the config folder
+/// of whoever runs them.
+#[cfg(test)]
+fn uploads_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("teller-test-{}", UPLOADS_DIR))
+}
+
+fn upload_state_path(destination: &str) -> PathBuf {
+    let name = hex::encode(Sha256::digest(destination.as_bytes()));
+
+    uploads_dir().join(format!("{}.json", name))
+}
+
+async fn source_identity(source: &Path) -> Result<(u64, Option<i64>), Error> {
+    let metadata = tokio::fs::metadata(source)
+        .await
+        .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?;
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs() as i64);
+
+    Ok((metadata.len(), modified))
+}
+
+/// Starts recording an upload of `source` to `destination`, which names the
+/// target in a way that is unique across vaults, like `s3://bucket/key`.
+pub(crate) async fn new_upload_state(
+    destination: &str,
+    source: &Path,
+) -> Result<UploadState, Error> {
+    let (source_size, source_modified) = source_identity(source).await?;
+
+    Ok(UploadState {
+        destination: destination.to_string(),
+        source_size,
+        source_modified,
+        upload_id: None,
+        parts: Vec::new(),
+    })
+}
+
+/// The recorded state of an interrupted upload of `source`, if there is one
+/// and the file has not changed since.
+pub(crate) async fn load_upload_state(destination: &str, source: &Path) -> Option<UploadState> {
+    let state = tokio::fs::read_to_string(upload_state_path(destination))
+        .await
+        .ok()?;
+    let state: UploadState = serde_json::from_str(&state).ok()?;
+
+    let (source_size, source_modified) = source_identity(source).await.ok()?;
+
+    if state.destination != destination
+        || state.source_size != source_size
+        || state.source_modified != source_modified
+    {
+        clear_upload_state(destination).await;
+        return None;
+    }
+
+    Some(state)
+}
+
+pub(crate) async fn save_upload_state(state: &UploadState) {
+    let state_path = upload_state_path(&state.destination);
+
+    let data = match serde_json::to_vec(state) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Could not serialize upload state: {:?}", e);
+            return;
+        }
+    };
+
+    let saved = match state_path.parent() {
+        Some(uploads_dir) => match tokio::fs::create_dir_all(uploads_dir).await {
+            Ok(_) => write_atomic(&state_path, &data).await,
+            Err(e) => Err(e),
+        },
+        None => Ok(()),
+    };
+
+    if let Err(e) = saved {
+        error!(
+            "Could not write upload state at {}: {}",
+            state_path.display(),
+            e
+        );
+    }
+}
+
+pub(crate) async fn clear_upload_state(destination: &str) {
+    let state_path = upload_state_path(destination);
+
+    if let Err(e) = tokio::fs::remove_file(&state_path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!(
+                "Could not remove upload state at {}: {}",
+                state_path.display(),
+                e
+            );
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use async_trait::async_trait;
+use tokio::fs::{self, File};
+
+use crate::handlers::atomic::copy_atomic;
+use crate::handlers::store::OBJECTS_DIR;
+use crate::types::backup::SnapshotStat;
+use crate::types::error::Error;
+
+use super::{snapshot_file_name, SnapshotReader, VaultBackend, SNAPSHOT_SUFFIX};
+
+/// A vault in a folder on this machine.
+pub struct LocalVault {
+    path: PathBuf,
+}
+
+impl LocalVault {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn snapshot_path(&self, world_id: &str, snapshot_id: &str) -> PathBuf {
+        self.path
+            .join(world_id)
+            .join(snapshot_file_name(snapshot_id))
+    }
+
+    fn snapshot_error(snapshot_id: &str, path: &Path, e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::NotFound => {
+                Error::SnapshotNotFound(format!("Backup {} does not exist", snapshot_id))
+            }
+            _ => Error::io(format!("Failed to access backup {}", path.display()), e),
+        }
+    }
+}
+
+#[async_trait]
+impl VaultBackend for LocalVault {
+    async fn list_worlds(&self) -> Result<Vec<String>, Error> {
+        let read_error = |e| Error::io(format!("Failed to read vault {}", self.path.display()), e);
+
+        let mut entries = fs::read_dir(&self.path).await.map_err(read_error)?;
+        let mut worlds = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await.map_err(read_error)? {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if name == OBJECTS_DIR || name == "temp" || !entry.path().is_dir() {
+                continue;
+            }
+
+            worlds.push(name);
+        }
+
+        Ok(worlds)
+    }
+
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error> {
+        let world_path = self.path.join(world_id);
+        let read_error = |e| Error::io(format!("Failed to read backups of {}", world_id), e);
+
+        let mut entries = fs::read_dir(&world_path).await.map_err(read_error)?;
+        let mut snapshots = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await.map_err(read_error)? {
+            if let Some(snapshot_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(SNAPSHOT_SUFFIX))
+            {
+                snapshots.push(snapshot_id.to_string());
+            }
+        }
+
+        Ok(snapshots)
+    }
+
+    async fn stat_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotStat, Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+
+        let metadata = fs::metadata(&path)
+            .await
+            .map_err(|e| Self::snapshot_error(snapshot_id, &path, e))?;
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_secs() as i64);
+
+        Ok(SnapshotStat {
+            size: metadata.len(),
+            modified,
+        })
+    }
+
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error> {
+        let world_path = self.path.join(world_id);
+
+        fs::create_dir_all(&world_path).await.map_err(|e| {
+            Error::io(
+                format!("Failed to create vault folder {}", world_path.display()),
+                e,
+            )
+        })?;
+
+        let destination = self.snapshot_path(world_id, snapshot_id);
+
+        copy_atomic(source, &destination).await.map_err(|e| {
+            Error::io(
+                format!("Failed to copy backup to {}", destination.display()),
+                e,
+            )
+        })
+    }
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+
+        let file = File::open(&path)
+            .await
+            .map_err(|e| Self::snapshot_error(snapshot_id, &path, e))?;
+
+        Ok(Box::new(file))
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+
+        fs::remove_file(&path).await.map_err(|e| {
+            Error::io(
+                format!("Failed to remove backup file {}", path.display()),
+                e,
+            )
+        })
+    }
+
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error> {
+        let world_path = self.path.join(world_id);
+
+        fs::remove_dir_all(&world_path).await.map_err(|e| {
+            Error::io(
+                format!("Failed to remove backup folder {}", world_path.display()),
+                e,
+            )
+        })
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
@@ -0,0 +1,400 @@
+pub mod local;
+pub mod remote;
+pub mod s3;
+pub mod sftp;
+pub mod webdav;
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use log::error;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::types::backup::{BackupMetadata, BackupSettings, SnapshotStat};
+use crate::types::error::Error;
+
+use super::archive::{
+    find_central_directory, find_stored_entry, local_header_length, LOCAL_HEADER_SIZE,
+    METADATA_FILE, ZIP_TAIL_SIZE,
+};
+use super::atomic::{commit_file, discard_partial, partial_path};
+use super::backup::{
+    get_backup_meta_from_path, get_default_vault, get_temp_dir, parse_backup_metadata,
+};
+use super::config::backup::get_backup_config;
+use super::transfer::shared_throttle;
+
+pub use local::LocalVault;
+pub use remote::RemoteVault;
+pub use s3::S3Vault;
+pub use sftp::SftpVault;
+pub use webdav::WebdavVault;
+
+pub const SNAPSHOT_SUFFIX: &str = ".chunkvault-snapshot";
+
+pub type SnapshotReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Storage behind a vault. Snapshots are addressed by world id and snapshot
+/// id, and laid out as `<world_id>/<snapshot_id>.chunkvault-snapshot`
+/// wherever the backend keeps them.
+#[async_trait]
+pub trait VaultBackend: Send + Sync {
+    /// Ids of the worlds that have snapshots in the vault.
+    async fn list_worlds(&self) -> Result<Vec<String>, Error>;
+
+    /// Ids of the snapshots of a world, in no particular order.
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error>;
+
+    /// Fails with [`Error::SnapshotNotFound`] when the snapshot does not exist.
+    async fn stat_snapshot(&self, world_id: &str, snapshot_id: &str)
+        -> Result<SnapshotStat, Error>;
+
+    /// Stores the file at `source` as a snapshot. The snapshot only becomes
+    /// visible once it has been written completely.
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error>;
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error>;
+
+    /// Reads part of a snapshot. The default skips ahead in the snapshot's
+    /// stream, backends that can fetch a range directly override it.
+    async fn read_snapshot_range(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        let mut reader = self.get_snapshot(world_id, snapshot_id).await?;
+        let mut data = Vec::new();
+
+        let read = async {
+            tokio::io::copy(&mut (&mut reader).take(range.start), &mut tokio::io::sink()).await?;
+            reader
+                .take(range.end.saturating_sub(range.start))
+                .read_to_end(&mut data)
+                .await
+        }
+        .await;
+
+        match read {
+            Ok(_) => Ok(data),
+            Err(e) => Err(Error::io(
+                format!("Failed to read backup {}", snapshot_id),
+                e,
+            )),
+        }
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error>;
+
+    /// Removes every snapshot of a world.
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error>;
+
+    /// The folder the vault lives in, for backends that keep it on disk.
+    /// Deduplicated storage and incremental snapshots need one, since they
+    /// read objects and parents next to the snapshot.
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+pub fn snapshot_file_name(snapshot_id: &str) -> String {
+    format!("{}{}", snapshot_id, SNAPSHOT_SUFFIX)
+}
+
+/// Path of a snapshot inside a vault that lives on disk.
+pub(crate) fn local_snapshot_path(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+) -> Option<PathBuf> {
+    vault
+        .local_path()
+        .map(|path| path.join(world_id).join(snapshot_file_name(snapshot_id)))
+}
+
+pub fn vault_backend(
+    backup_settings: &BackupSettings,
+    vault: &str,
+) -> Result<Box<dyn VaultBackend>, Error> {
+    if let Some(vault_path) = backup_settings.vaults.get(vault) {
+        return Ok(Box::new(LocalVault::new(vault_path)));
+    }
+
+    let throttle = shared_throttle(&backup_settings.bandwidth);
+
+    if let Some(s3) = backup_settings.s3_vaults.get(vault) {
+        return Ok(Box::new(S3Vault::new(s3).with_throttle(throttle)));
+    }
+
+    if let Some(sftp) = backup_settings.sftp_vaults.get(vault) {
+        return Ok(Box::new(SftpVault::new(sftp).with_throttle(throttle)));
+    }
+
+    if let Some(remote) = backup_settings.remote_vaults.get(vault) {
+        return Ok(Box::new(RemoteVault::new(remote)?.with_throttle(throttle)));
+    }
+
+    match backup_settings.webdav_vaults.get(vault) {
+        Some(webdav) => Ok(Box::new(WebdavVault::new(webdav)?.with_throttle(throttle))),
+        None => Err(Error::VaultNotFound(format!(
+            "Vault {} does not exist.",
+            vault
+        ))),
+    }
+}
+
+/// Opens a vault by id, or the default vault when none is given.
+pub async fn open_vault(vault: Option<&str>) -> Result<Box<dyn VaultBackend>, Error> {
+    match vault {
+        Some(vault) => vault_backend(&get_backup_config().await?, vault),
+        None => Ok(Box::new(LocalVault::new(get_default_vault().await))),
+    }
+}
+
+/// A snapshot readable from disk. Snapshots of vaults that are not on disk
+/// are downloaded into the temp folder and removed again on drop.
+pub(crate) struct LocalSnapshot {
+    pub path: PathBuf,
+    temporary: bool,
+}
+
+impl Drop for LocalSnapshot {
+    fn drop(&mut self) {
+        if self.temporary {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                error!("Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+pub(crate) async fn fetch_snapshot(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+) -> Result<LocalSnapshot, Error> {
+    if let Some(path) = local_snapshot_path(vault, world_id, snapshot_id) {
+        if !path.exists() {
+            return Err(Error::SnapshotNotFound(format!(
+                "Backup {} does not exist",
+                snapshot_id
+            )));
+        }
+
+        return Ok(LocalSnapshot {
+            path,
+            temporary: false,
+        });
+    }
+
+    let mut reader = vault.get_snapshot(world_id, snapshot_id).await?;
+
+    let path = get_temp_dir().await.join(format!(
+        "{}-{}",
+        uuid::Uuid::new_v4(),
+        snapshot_file_name(snapshot_id)
+    ));
+    let partial = partial_path(&path);
+
+    let fetched = async {
+        let mut file = File::create(&partial).await?;
+        tokio::io::copy(&mut reader, &mut file).await?;
+        drop(file);
+
+        commit_file(&partial, &path).await
+    }
+    .await;
+
+    if let Err(e) = fetched {
+        discard_partial(&partial).await;
+        return Err(Error::io(
+            format!("Failed to download backup {}", snapshot_id),
+            e,
+        ));
+    }
+
+    Ok(LocalSnapshot {
+        path,
+        temporary: true,
+    })
+}
+
+/// Reads the metadata entry of a snapshot in a vault that is not on disk
+/// from its central directory, without downloading the rest of it. Returns
+/// `None` when the entry is encrypted or compressed.
+async fn read_stored_metadata(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+) -> Result<Option<String>, Error> {
+    let size = vault.stat_snapshot(world_id, snapshot_id).await?.size;
+    let tail_start = size.saturating_sub(ZIP_TAIL_SIZE);
+
+    let tail = vault
+        .read_snapshot_range(world_id, snapshot_id, tail_start..size)
+        .await?;
+
+    let (offset, length) = match find_central_directory(&tail) {
+        Some(central_directory) => central_directory,
+        None => return Ok(None),
+    };
+
+    let central_directory = match offset.checked_sub(tail_start) {
+        Some(start) => match tail.get(start as usize..(start + length) as usize) {
+            Some(central_directory) => central_directory.to_vec(),
+            None => return Ok(None),
+        },
+        None => {
+            vault
+                .read_snapshot_range(world_id, snapshot_id, offset..offset + length)
+                .await?
+        }
+    };
+
+    let (header_offset, length) = match find_stored_entry(&central_directory, METADATA_FILE) {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    let header = vault
+        .read_snapshot_range(
+            world_id,
+            snapshot_id,
+            header_offset..header_offset + LOCAL_HEADER_SIZE,
+        )
+        .await?;
+
+    let data_start = match local_header_length(&header) {
+        Some(header_length) => header_offset + header_length,
+        None => return Ok(None),
+    };
+
+    let metadata = vault
+        .read_snapshot_range(world_id, snapshot_id, data_start..data_start + length)
+        .await?;
+
+    Ok(String::from_utf8(metadata).ok())
+}
+
+/// Reads the metadata of a snapshot, only downloading the whole snapshot
+/// when its metadata cannot be read on its own.
+pub async fn read_snapshot_metadata(
+    vault: &dyn VaultBackend,
+    world_id: &str,
+    snapshot_id: &str,
+) -> Result<BackupMetadata, Error> {
+    if let Some(path) = local_snapshot_path(vault, world_id, snapshot_id) {
+        return get_backup_meta_from_path(path).await;
+    }
+
+    if let Some(metadata) = read_stored_metadata(vault, world_id, snapshot_id).await? {
+        let name = Path::new(world_id).join(snapshot_file_name(snapshot_id));
+        return parse_backup_metadata(&metadata, &name);
+    }
+
+    let snapshot = fetch_snapshot(vault, world_id, snapshot_id).await?;
+    get_backup_meta_from_path(snapshot.path.clone()).await
+}
+
+/// The newest of a world's snapshots, going by the timestamp in their ids.
+pub(crate) fn find_newest_snapshot(snapshot_ids: &[String]) -> Option<&str> {
+    snapshot_ids
+        .iter()
+        .filter_map(|id| id.parse::<i64>().ok().map(|time| (time, id)))
+        .max_by_key(|(time, _)| *time)
+        .map(|(_, id)| id.as_str())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Stores a snapshot of `size` bytes in a fresh world of `vault`, reads
+    /// it back every way a vault can be read, and removes it again.
+    pub(crate) async fn round_trip(vault: &dyn VaultBackend, size: usize) {
+        let world_id = format!("teller-test-{}", uuid::Uuid::new_v4());
+        let snapshot_id = "1700000000";
+
+        let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        let source = std::env::temp_dir().join(format!("{}.snapshot", world_id));
+        tokio::fs::write(&source, &data).await.unwrap();
+
+        vault
+            .put_snapshot(&world_id, snapshot_id, &source)
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&source).await.unwrap();
+
+        assert!(vault.list_worlds().await.unwrap().contains(&world_id));
+        assert_eq!(
+            vault.list_snapshots(&world_id).await.unwrap(),
+            vec![snapshot_id.to_string()]
+        );
+        assert_eq!(
+            vault
+                .stat_snapshot(&world_id, snapshot_id)
+                .await
+                .unwrap()
+                .size,
+            size as u64
+        );
+
+        let start = size as u64 / 3;
+        let end = start + 1000.min(size as u64 - start);
+        assert_eq!(
+            vault
+                .read_snapshot_range(&world_id, snapshot_id, start..end)
+                .await
+                .unwrap(),
+            &data[start as usize..end as usize]
+        );
+
+        let mut read = Vec::new();
+        vault
+            .get_snapshot(&world_id, snapshot_id)
+            .await
+            .unwrap()
+            .read_to_end(&mut read)
+            .await
+            .unwrap();
+        assert!(read == data);
+
+        vault.delete_snapshot(&world_id, snapshot_id).await.unwrap();
+        assert!(matches!(
+            vault.stat_snapshot(&world_id, snapshot_id).await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+
+        vault.delete_world(&world_id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_vault_round_trip() {
+        let vault_path =
+            std::env::temp_dir().join(format!("teller-vault-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&vault_path).await.unwrap();
+
+        round_trip(&LocalVault::new(&vault_path), 64 * 1024).await;
+
+        tokio::fs::remove_dir_all(&vault_path).await.unwrap();
+    }
+
+    #[test]
+    fn finds_newest_snapshot() {
+        let snapshot_ids = ["1700000000", "notes", "1800000000", "900000000"].map(String::from);
+
+        assert_eq!(find_newest_snapshot(&snapshot_ids), Some("1800000000"));
+        assert_eq!(find_newest_snapshot(&[]), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::io;
+use std::path::Path;
+
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use tokio_util::io::StreamReader;
+
+use crate::handlers::remote::RemoteVaultClient;
+use crate::handlers::transfer::Throttle;
+use crate::types::backup::{RemoteBackup, SnapshotStat};
+use crate::types::error::Error;
+use crate::types::world::WorldVersion;
+
+use super::{SnapshotReader, VaultBackend, SNAPSHOT_SUFFIX};
+
+/// A vault hosted by the ChunkVault backend, where every snapshot of a world
+/// is one of its versions.
+///
+/// The backend keeps the name a snapshot was uploaded with in the version's
+/// `backup_path`, which is where its snapshot id is taken from. Versions
+/// uploaded some other way are named after the time they were created.
+pub struct RemoteVault {
+    client: RemoteVaultClient,
+}
+
+impl RemoteVault {
+    pub fn new(remote: &RemoteBackup) -> Result<Self, Error> {
+        Ok(Self {
+            client: RemoteVaultClient::new(remote)?,
+        })
+    }
+
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.client = self.client.with_throttle(throttle);
+        self
+    }
+
+    fn snapshot_id(version: &WorldVersion) -> Option<String> {
+        let file_name = version.backup_path.rsplit('/').next()?;
+
+        match file_name.strip_suffix(SNAPSHOT_SUFFIX) {
+            Some(snapshot_id) => Some(snapshot_id.to_string()),
+            None => version
+                .created_at
+                .map(|created| created.and_utc().timestamp().to_string()),
+        }
+    }
+
+    async fn find_version(&self, world_id: &str, snapshot_id: &str) -> Result<WorldVersion, Error> {
+        self.client
+            .list_versions(world_id)
+            .await?
+            .into_iter()
+            .find(|version| Self::snapshot_id(version).as_deref() == Some(snapshot_id))
+            .ok_or_else(|| {
+                Error::SnapshotNotFound(format!("Backup {} does not exist", snapshot_id))
+            })
+    }
+}
+
+#[async_trait]
+impl VaultBackend for RemoteVault {
+    async fn list_worlds(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .client
+            .list_worlds()
+            .await?
+            .into_iter()
+            .map(|world| world.id.to_string())
+            .collect())
+    }
+
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .client
+            .list_versions(world_id)
+            .await?
+            .iter()
+            .filter_map(Self::snapshot_id)
+            .collect())
+    }
+
+    async fn stat_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotStat, Error> {
+        let version = self.find_version(world_id, snapshot_id).await?;
+
+        Ok(SnapshotStat {
+            size: version.size.max(0) as u64,
+            modified: version
+                .created_at
+                .map(|created| created.and_utc().timestamp()),
+        })
+    }
+
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error> {
+        self.client
+            .upload_snapshot(world_id, snapshot_id, source)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error> {
+        let version = self.find_version(world_id, snapshot_id).await?;
+
+        let stream = self
+            .client
+            .request_version(world_id, version.version)
+            .await?
+            .bytes_stream()
+            .map_err(io::Error::other);
+
+        Ok(Box::new(StreamReader::new(Box::pin(stream))))
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error> {
+        let version = self.find_version(world_id, snapshot_id).await?;
+
+        self.client.delete_version(world_id, version.version).await
+    }
+
+    /// The backend has no way to remove a world, so all its versions are
+    /// removed instead.
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error> {
+        for version in self.client.list_versions(world_id).await? {
+            self.client
+                .delete_version(world_id, version.version)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::handlers::mock_http::{serve, version_json, MockServer, Reply};
+
+    use super::*;
+
+    async fn vault_with_versions() -> (RemoteVault, MockServer) {
+        let server = serve(
+            |request| match (request.method.as_str(), request.path.as_str()) {
+                ("GET", "/worlds/world/versions") => Reply::new(200).json(&json!([
+                    version_json(1, "world/1700000000.chunkvault-snapshot"),
+                    version_json(2, "uploads/version-2.zip"),
+                ])),
+                ("DELETE", "/worlds/world/versions/1") => Reply::new(204),
+                _ => Reply::new(404),
+            },
+        )
+        .await;
+
+        let vault = RemoteVault::new(&RemoteBackup {
+            remote_url: server.url.clone(),
+            api_key: "secret".to_string(),
+        })
+        .unwrap();
+
+        (vault, server)
+    }
+
+    #[tokio::test]
+    async fn names_versions_after_their_snapshots() {
+        let (vault, _server) = vault_with_versions().await;
+
+        let mut snapshot_ids = vault.list_snapshots("world").await.unwrap();
+        snapshot_ids.sort();
+
+        // The second version was not uploaded by teller, 2023-10-01T12:00:00
+        // is its creation time.
+        assert_eq!(snapshot_ids, vec!["1696161600", "1700000000"]);
+
+        let stat = vault.stat_snapshot("world", "1700000000").await.unwrap();
+        assert_eq!(stat.size, 2048);
+    }
+
+    #[tokio::test]
+    async fn addresses_snapshots_by_version() {
+        let (vault, server) = vault_with_versions().await;
+
+        vault.delete_snapshot("world", "1700000000").await.unwrap();
+
+        assert!(matches!(
+            vault.delete_snapshot("world", "1").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+        assert!(matches!(
+            vault.stat_snapshot("other", "1700000000").await,
+            Err(Error::SnapshotNotFound(_))
+        ));
+
+        let deleted: Vec<_> = server
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "DELETE")
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(deleted, vec!["/worlds/world/versions/1"]);
+    }
+}
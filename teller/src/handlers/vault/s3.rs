@@ -0,0 +1,700 @@
+use std::ops::Range;
+use std::path::Path;
+
+use async_trait::async_trait;
+use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use log::{error, info};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::handlers::transfer::{
+    clear_upload_state, load_upload_state, new_upload_state, save_upload_state, Throttle,
+};
+use crate::types::backup::{S3Backup, SnapshotStat, UploadState, UploadedPart};
+use crate::types::error::Error;
+
+use super::{snapshot_file_name, SnapshotReader, VaultBackend, SNAPSHOT_SUFFIX};
+
+/// Snapshots larger than this are uploaded in parts of this size. S3 needs
+/// every part but the last to be at least 5 MiB.
+const PART_SIZE: usize = 16 * 1024 * 1024;
+
+fn s3_error(context: String, e: impl std::error::Error) -> Error {
+    Error::Network(format!("{}: {}", context, DisplayErrorContext(e)))
+}
+
+/// A vault in an S3 bucket, keeping snapshots under
+/// `<prefix>/<world_id>/<snapshot_id>.chunkvault-snapshot`.
+pub struct S3Vault {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    throttle: Throttle,
+}
+
+impl S3Vault {
+    pub fn new(settings: &S3Backup) -> Self {
+        let credentials = Credentials::new(
+            &settings.access_key_id,
+            &settings.secret_access_key,
+            None,
+            None,
+            "teller",
+        );
+
+        let mut config = aws_sdk_s3::Config::builder()
+            .region(Region::new(settings.region.clone()))
+            .credentials_provider(credentials)
+            .force_path_style(settings.path_style);
+
+        if let Some(endpoint) = &settings.endpoint {
+            config = config.endpoint_url(endpoint);
+        }
+
+        Self::with_client(Client::from_conf(config.build()), settings)
+    }
+
+    /// Uses an already configured client.
+    pub fn with_client(client: Client, settings: &S3Backup) -> Self {
+        Self {
+            client,
+            bucket: settings.bucket.clone(),
+            prefix: settings.prefix.trim_matches('/').to_string(),
+            throttle: Throttle::default(),
+        }
+    }
+
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    fn key(&self, path: &str) -> String {
+        match self.prefix.is_empty() {
+            true => path.to_string(),
+            false => format!("{}/{}", self.prefix, path),
+        }
+    }
+
+    fn world_key(&self, world_id: &str) -> String {
+        self.key(&format!("{}/", world_id))
+    }
+
+    fn snapshot_key(&self, world_id: &str, snapshot_id: &str) -> String {
+        self.key(&format!("{}/{}", world_id, snapshot_file_name(snapshot_id)))
+    }
+
+    /// Lists the keys directly under `prefix`, and the folders below it when
+    /// `folders` is set.
+    async fn list_keys(&self, prefix: &str, folders: bool) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let mut request = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token);
+
+            if folders {
+                request = request.delimiter("/");
+            }
+
+            let output = request
+                .send()
+                .await
+                .map_err(|e| s3_error(format!("Failed to list {}", prefix), e))?;
+
+            match folders {
+                true => keys.extend(
+                    output
+                        .common_prefixes()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|folder| folder.prefix().map(str::to_string)),
+                ),
+                false => keys.extend(
+                    output
+                        .contents()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|object| object.key().map(str::to_string)),
+                ),
+            }
+
+            match output.next_continuation_token() {
+                Some(token) if output.is_truncated() => {
+                    continuation_token = Some(token.to_string());
+                }
+                _ => break,
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        state: &mut UploadState,
+        source: &Path,
+    ) -> Result<(), Error> {
+        let upload_id = state.upload_id.clone().unwrap_or_default();
+
+        let mut file = File::open(source)
+            .await
+            .map_err(|e| Error::io(format!("Failed to open {}", source.display()), e))?;
+
+        file.seek(std::io::SeekFrom::Start(
+            (state.parts.len() * PART_SIZE) as u64,
+        ))
+        .await
+        .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?;
+
+        loop {
+            let mut part = Vec::with_capacity(PART_SIZE);
+            (&mut file)
+                .take(PART_SIZE as u64)
+                .read_to_end(&mut part)
+                .await
+                .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?;
+
+            if part.is_empty() && !state.parts.is_empty() {
+                break;
+            }
+
+            let part_number = state.parts.len() as i32 + 1;
+            let last = part.len() < PART_SIZE;
+
+            self.throttle.consume(part.len() as u64).await;
+
+            let output = self
+                .client
+                .upload_part()
+                .bucket(&self.bucket)
+                .key(key)
+                .upload_id(&upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(part))
+                .send()
+                .await
+                .map_err(|e| {
+                    s3_error(
+                        format!("Failed to upload part {} of {}", part_number, key),
+                        e,
+                    )
+                })?;
+
+            state.parts.push(UploadedPart {
+                number: part_number,
+                etag: output.e_tag().map(str::to_string),
+            });
+            save_upload_state(state).await;
+
+            if last {
+                break;
+            }
+        }
+
+        let parts = state
+            .parts
+            .iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .set_e_tag(part.etag.clone())
+                    .part_number(part.number)
+                    .build()
+            })
+            .collect();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| s3_error(format!("Failed to complete upload of {}", key), e))?;
+
+        Ok(())
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) {
+        if let Err(e) = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+        {
+            error!(
+                "Failed to abort upload of {}: {}",
+                key,
+                DisplayErrorContext(e)
+            );
+        }
+    }
+
+    /// Picks up an interrupted upload of the same file, keeping the parts the
+    /// bucket still has. Returns `None` when there is nothing to resume.
+    async fn resume_upload(
+        &self,
+        destination: &str,
+        key: &str,
+        source: &Path,
+    ) -> Option<UploadState> {
+        let mut state = load_upload_state(destination, source).await?;
+        let upload_id = state.upload_id.clone()?;
+
+        let listed = match self
+            .client
+            .list_parts()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(&upload_id)
+            .send()
+            .await
+        {
+            Ok(listed) => listed,
+            Err(e) => {
+                info!(
+                    "Could not resume upload of {}, starting over: {}",
+                    key,
+                    DisplayErrorContext(e)
+                );
+                clear_upload_state(destination).await;
+                return None;
+            }
+        };
+
+        let confirmed = listed.parts().unwrap_or_default();
+
+        let resumable = state
+            .parts
+            .iter()
+            .take_while(|part| {
+                confirmed.iter().any(|confirmed| {
+                    confirmed.part_number() == part.number
+                        && confirmed.e_tag() == part.etag.as_deref()
+                })
+            })
+            .count();
+        state.parts.truncate(resumable);
+
+        info!(
+            "Resuming upload of {} after {} parts",
+            key,
+            state.parts.len()
+        );
+
+        Some(state)
+    }
+
+    /// Uploads a large snapshot in parts, recording every part the bucket
+    /// confirmed so an upload cut off by the connection continues where it
+    /// stopped on the next attempt, even after a restart. Uploads that fail
+    /// for other reasons are aborted, so their parts do not linger in the
+    /// bucket. The snapshot only appears once all parts are in.
+    async fn put_multipart(&self, key: &str, source: &Path) -> Result<(), Error> {
+        let destination = format!("s3://{}/{}", self.bucket, key);
+
+        let mut state = match self.resume_upload(&destination, key, source).await {
+            Some(state) => state,
+            None => {
+                let output = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .send()
+                    .await
+                    .map_err(|e| s3_error(format!("Failed to start upload of {}", key), e))?;
+
+                let mut state = new_upload_state(&destination, source).await?;
+                state.upload_id = match output.upload_id() {
+                    Some(upload_id) => Some(upload_id.to_string()),
+                    None => {
+                        return Err(Error::Network(format!(
+                            "No upload id was returned for {}",
+                            key
+                        )))
+                    }
+                };
+                save_upload_state(&state).await;
+
+                state
+            }
+        };
+
+        let uploaded = self.upload_parts(key, &mut state, source).await;
+
+        match &uploaded {
+            Ok(_) => clear_upload_state(&destination).await,
+            Err(Error::Network(_)) => {}
+            Err(_) => {
+                if let Some(upload_id) = &state.upload_id {
+                    self.abort_upload(key, upload_id).await;
+                }
+                clear_upload_state(&destination).await;
+            }
+        }
+
+        uploaded
+    }
+}
+
+#[async_trait]
+impl VaultBackend for S3Vault {
+    async fn list_worlds(&self) -> Result<Vec<String>, Error> {
+        let prefix = self.key("");
+
+        Ok(self
+            .list_keys(&prefix, true)
+            .await?
+            .iter()
+            .filter_map(|folder| folder.strip_prefix(&prefix))
+            .map(|folder| folder.trim_end_matches('/').to_string())
+            .filter(|world_id| !world_id.is_empty())
+            .collect())
+    }
+
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error> {
+        let prefix = self.world_key(world_id);
+
+        Ok(self
+            .list_keys(&prefix, false)
+            .await?
+            .iter()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .filter_map(|name| name.strip_suffix(SNAPSHOT_SUFFIX))
+            .filter(|snapshot_id| !snapshot_id.contains('/'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    async fn stat_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotStat, Error> {
+        let key = self.snapshot_key(world_id, snapshot_id);
+
+        let output = match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(SdkError::ServiceError(e)) if e.err().is_not_found() => {
+                return Err(Error::SnapshotNotFound(format!(
+                    "Backup {} does not exist",
+                    snapshot_id
+                )))
+            }
+            Err(e) => return Err(s3_error(format!("Failed to stat {}", key), e)),
+        };
+
+        Ok(SnapshotStat {
+            size: output.content_length().max(0) as u64,
+            modified: output.last_modified().map(|modified| modified.secs()),
+        })
+    }
+
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error> {
+        let key = self.snapshot_key(world_id, snapshot_id);
+
+        let size = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?
+            .len();
+
+        info!("Uploading {} to bucket {}", key, self.bucket);
+
+        if size > PART_SIZE as u64 {
+            return self.put_multipart(&key, source).await;
+        }
+
+        let body = ByteStream::from_path(source)
+            .await
+            .map_err(|e| Error::Vault(format!("Failed to read {}: {}", source.display(), e)))?;
+
+        self.throttle.consume(size).await;
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| s3_error(format!("Failed to upload {}", key), e))?;
+
+        Ok(())
+    }
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error> {
+        let key = self.snapshot_key(world_id, snapshot_id);
+
+        let output = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(SdkError::ServiceError(e)) if e.err().is_no_such_key() => {
+                return Err(Error::SnapshotNotFound(format!(
+                    "Backup {} does not exist",
+                    snapshot_id
+                )))
+            }
+            Err(e) => return Err(s3_error(format!("Failed to download {}", key), e)),
+        };
+
+        Ok(Box::new(Box::pin(output.body.into_async_read())))
+    }
+
+    async fn read_snapshot_range(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let key = self.snapshot_key(world_id, snapshot_id);
+
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .range(format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await
+            .map_err(|e| s3_error(format!("Failed to download {}", key), e))?;
+
+        let data = output
+            .body
+            .collect()
+            .await
+            .map_err(|e| Error::Network(format!("Failed to download {}: {}", key, e)))?;
+
+        Ok(data.into_bytes().to_vec())
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error> {
+        let key = self.snapshot_key(world_id, snapshot_id);
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| s3_error(format!("Failed to remove {}", key), e))?;
+
+        Ok(())
+    }
+
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error> {
+        for key in self.list_keys(&self.world_key(world_id), false).await? {
+            self.client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .send()
+                .await
+                .map_err(|e| s3_error(format!("Failed to remove {}", key), e))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::mock_http::{serve, Reply};
+    use crate::handlers::vault::tests::round_trip;
+
+    use super::*;
+
+    fn settings(prefix: &str, endpoint: Option<String>) -> S3Backup {
+        S3Backup {
+            bucket: "teller".to_string(),
+            prefix: prefix.to_string(),
+            region: "us-east-1".to_string(),
+            endpoint,
+            path_style: true,
+            access_key_id: "access".to_string(),
+            secret_access_key: "secret".to_string(),
+        }
+    }
+
+    fn list_result(keys: &[&str], folders: &[&str], next: Option<&str>) -> String {
+        let mut xml = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?><ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/"><Name>teller</Name>"#,
+        );
+
+        for key in keys {
+            xml.push_str(&format!(
+                "<Contents><Key>{}</Key><Size>1</Size></Contents>",
+                key
+            ));
+        }
+
+        for folder in folders {
+            xml.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                folder
+            ));
+        }
+
+        match next {
+            Some(token) => xml.push_str(&format!(
+                "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                token
+            )),
+            None => xml.push_str("<IsTruncated>false</IsTruncated>"),
+        }
+
+        xml.push_str("</ListBucketResult>");
+        xml
+    }
+
+    #[test]
+    fn maps_paths_to_keys() {
+        let vault = S3Vault::new(&settings("/backups/teller/", None));
+
+        assert_eq!(vault.key(""), "backups/teller/");
+        assert_eq!(vault.world_key("world"), "backups/teller/world/");
+        assert_eq!(
+            vault.snapshot_key("world", "1700000000"),
+            "backups/teller/world/1700000000.chunkvault-snapshot"
+        );
+
+        let vault = S3Vault::new(&settings("", None));
+
+        assert_eq!(vault.key(""), "");
+        assert_eq!(vault.world_key("world"), "world/");
+        assert_eq!(
+            vault.snapshot_key("world", "1700000000"),
+            "world/1700000000.chunkvault-snapshot"
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_snapshots_directly_in_the_world() {
+        let server = serve(|request| {
+            let body = match request.path.contains("continuation-token=page") {
+                false => list_result(
+                    &[
+                        "backups/world/1700000000.chunkvault-snapshot",
+                        "backups/world/notes.txt",
+                        "backups/world/old/1600000000.chunkvault-snapshot",
+                    ],
+                    &[],
+                    Some("page"),
+                ),
+                true => list_result(&["backups/world/1800000000.chunkvault-snapshot"], &[], None),
+            };
+
+            Reply::new(200)
+                .header("Content-Type", "application/xml")
+                .body(body)
+        })
+        .await;
+
+        let vault = S3Vault::new(&settings("backups", Some(server.url.clone())));
+
+        let mut snapshot_ids = vault.list_snapshots("world").await.unwrap();
+        snapshot_ids.sort();
+        assert_eq!(snapshot_ids, vec!["1700000000", "1800000000"]);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].path.starts_with("/teller"));
+        assert!(requests[0].path.contains("prefix=backups%2Fworld%2F"));
+        assert!(!requests[0].path.contains("delimiter"));
+    }
+
+    #[tokio::test]
+    async fn lists_worlds_from_folders() {
+        let server = serve(|_| {
+            Reply::new(200)
+                .header("Content-Type", "application/xml")
+                .body(list_result(
+                    &[],
+                    &["backups/first/", "backups/second/"],
+                    None,
+                ))
+        })
+        .await;
+
+        let vault = S3Vault::new(&settings("backups", Some(server.url.clone())));
+
+        let mut world_ids = vault.list_worlds().await.unwrap();
+        world_ids.sort();
+        assert_eq!(world_ids, vec!["first", "second"]);
+
+        assert!(server.requests()[0].path.contains("delimiter=%2F"));
+    }
+
+    /// Runs against the bucket `TELLER_TEST_S3_BUCKET` of an S3-compatible
+    /// store such as MinIO at `TELLER_TEST_S3_ENDPOINT`, logging in with
+    /// `TELLER_TEST_S3_ACCESS_KEY` and `TELLER_TEST_S3_SECRET_KEY`.
+    #[tokio::test]
+    #[ignore]
+    async fn minio_round_trip() {
+        let (Ok(endpoint), Ok(bucket), Ok(access_key_id), Ok(secret_access_key)) = (
+            std::env::var("TELLER_TEST_S3_ENDPOINT"),
+            std::env::var("TELLER_TEST_S3_BUCKET"),
+            std::env::var("TELLER_TEST_S3_ACCESS_KEY"),
+            std::env::var("TELLER_TEST_S3_SECRET_KEY"),
+        ) else {
+            return;
+        };
+
+        let vault = S3Vault::new(&S3Backup {
+            bucket,
+            prefix: "teller-test".to_string(),
+            region: "us-east-1".to_string(),
+            endpoint: Some(endpoint),
+            path_style: true,
+            access_key_id,
+            secret_access_key,
+        });
+
+        round_trip(&vault, 64 * 1024).await;
+        round_trip(&vault, PART_SIZE + 64 * 1024).await;
+    }
+}
@@ -0,0 +1,658 @@
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use log::{error, info};
+use ssh2::{
+    CheckResult, ErrorCode, KnownHostFileKind, OpenFlags, OpenType, RenameFlags, Session, Sftp,
+};
+use tokio::sync::mpsc;
+use tokio_util::io::StreamReader;
+
+use crate::handlers::atomic::PARTIAL_SUFFIX;
+use crate::handlers::store::OBJECTS_DIR;
+use crate::handlers::transfer::{
+    clear_upload_state, load_upload_state, new_upload_state, save_upload_state, Throttle,
+};
+use crate::types::backup::{SftpBackup, SnapshotStat};
+use crate::types::error::Error;
+
+use super::{snapshot_file_name, SnapshotReader, VaultBackend, SNAPSHOT_SUFFIX};
+
+const CHUNK_SIZE: usize = 256 * 1024;
+
+/// How long connecting, or any single SSH operation, may stall before it
+/// fails, so a dead connection does not hang a backup forever.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `LIBSSH2_FX_NO_SUCH_FILE`
+const NO_SUCH_FILE: ErrorCode = ErrorCode::SFTP(2);
+
+fn sftp_error(context: String, e: ssh2::Error) -> Error {
+    Error::Network(format!("{}: {}", context, e))
+}
+
+fn snapshot_error(snapshot_id: &str, path: &str, e: ssh2::Error) -> Error {
+    match e.code() {
+        NO_SUCH_FILE => Error::SnapshotNotFound(format!("Backup {} does not exist", snapshot_id)),
+        _ => sftp_error(format!("Failed to access backup {}", path), e),
+    }
+}
+
+/// Logs in with the configured key, after checking the server's host key
+/// against known_hosts so backups are never sent to an impostor.
+fn connect(settings: &SftpBackup) -> Result<Sftp, Error> {
+    let address = format!("{}:{}", settings.host, settings.port);
+
+    let connect_error =
+        |e: io::Error| Error::Network(format!("Failed to connect to {}: {}", address, e));
+
+    let mut tcp = Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "the host name did not resolve to any address",
+    ));
+
+    for socket_address in address.to_socket_addrs().map_err(connect_error)? {
+        tcp = TcpStream::connect_timeout(&socket_address, TIMEOUT);
+        if tcp.is_ok() {
+            break;
+        }
+    }
+
+    let tcp = tcp.map_err(connect_error)?;
+
+    let mut session =
+        Session::new().map_err(|e| sftp_error("Failed to start SSH session".to_string(), e))?;
+    session.set_timeout(TIMEOUT.as_millis() as u32);
+    session.set_tcp_stream(tcp);
+    session
+        .handshake()
+        .map_err(|e| sftp_error(format!("SSH handshake with {} failed", address), e))?;
+
+    let known_hosts_path = match &settings.known_hosts {
+        Some(known_hosts) => known_hosts.clone(),
+        None => match directories::BaseDirs::new() {
+            Some(dirs) => dirs.home_dir().join(".ssh").join("known_hosts"),
+            None => {
+                return Err(Error::Config(
+                    "Could not find the known_hosts file".to_string(),
+                ))
+            }
+        },
+    };
+
+    let mut known_hosts = session
+        .known_hosts()
+        .map_err(|e| sftp_error("Failed to read known hosts".to_string(), e))?;
+    known_hosts
+        .read_file(&known_hosts_path, KnownHostFileKind::OpenSSH)
+        .map_err(|e| sftp_error(format!("Failed to read {}", known_hosts_path.display()), e))?;
+
+    let host_key = match session.host_key() {
+        Some((host_key, _)) => host_key,
+        None => {
+            return Err(Error::Network(format!(
+                "{} did not send a host key",
+                address
+            )))
+        }
+    };
+
+    match known_hosts.check_port(&settings.host, settings.port, host_key) {
+        CheckResult::Match => {}
+        CheckResult::NotFound => {
+            return Err(Error::Config(format!(
+                "{} is not in {}, connect to it with ssh once to trust it",
+                address,
+                known_hosts_path.display()
+            )))
+        }
+        CheckResult::Mismatch => {
+            return Err(Error::Vault(format!(
+                "Host key of {} does not match {}",
+                address,
+                known_hosts_path.display()
+            )))
+        }
+        CheckResult::Failure => {
+            return Err(Error::Vault(format!(
+                "Could not check the host key of {}",
+                address
+            )))
+        }
+    }
+
+    session
+        .userauth_pubkey_file(&settings.user, None, &settings.key_path, None)
+        .map_err(|e| {
+            Error::Vault(format!(
+                "{} rejected the key {}: {}",
+                address,
+                settings.key_path.display(),
+                e
+            ))
+        })?;
+
+    session
+        .sftp()
+        .map_err(|e| sftp_error(format!("Failed to start SFTP on {}", address), e))
+}
+
+/// A vault in a folder on a server reachable over SSH, keeping snapshots
+/// under `<remote_dir>/<world_id>/<snapshot_id>.chunkvault-snapshot`.
+///
+/// libssh2 is blocking, so every operation runs on the blocking thread pool
+/// and shares one connection, which is opened on first use and again after
+/// it failed. An upload cut off by the connection is continued from the end
+/// of its partial file on the next attempt.
+pub struct SftpVault {
+    settings: Arc<SftpBackup>,
+    connection: Arc<Mutex<Option<Sftp>>>,
+    throttle: Throttle,
+}
+
+impl SftpVault {
+    pub fn new(settings: &SftpBackup) -> Self {
+        Self {
+            settings: Arc::new(settings.clone()),
+            connection: Arc::new(Mutex::new(None)),
+            throttle: Throttle::default(),
+        }
+    }
+
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    fn world_path(&self, world_id: &str) -> String {
+        format!(
+            "{}/{}",
+            self.settings.remote_dir.trim_end_matches('/'),
+            world_id
+        )
+    }
+
+    fn snapshot_path(&self, world_id: &str, snapshot_id: &str) -> String {
+        format!(
+            "{}/{}",
+            self.world_path(world_id),
+            snapshot_file_name(snapshot_id)
+        )
+    }
+
+    /// The returned future does not borrow the vault, so it can be spawned.
+    fn run<T, F>(&self, operation: F) -> impl Future<Output = Result<T, Error>> + Send + 'static
+    where
+        T: Send + 'static,
+        F: FnOnce(&Sftp) -> Result<T, Error> + Send + 'static,
+    {
+        let settings = self.settings.clone();
+        let connection = self.connection.clone();
+
+        let task = tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .map_err(|_| Error::Vault("SFTP connection is poisoned".to_string()))?;
+
+            let sftp = match connection.take() {
+                Some(sftp) => sftp,
+                None => connect(&settings)?,
+            };
+
+            let result = operation(&sftp);
+
+            // Anything but a missing file may have been the connection
+            // dropping, so the next operation starts a new one.
+            if !matches!(result, Err(Error::Network(_))) {
+                *connection = Some(sftp);
+            }
+
+            result
+        });
+
+        async move {
+            task.await
+                .map_err(|e| Error::Vault(format!("SFTP operation failed: {}", e)))?
+        }
+    }
+}
+
+fn read_dir(sftp: &Sftp, path: &str) -> Result<Vec<(String, bool)>, Error> {
+    let entries = sftp
+        .readdir(Path::new(path))
+        .map_err(|e| sftp_error(format!("Failed to read {}", path), e))?;
+
+    Ok(entries
+        .into_iter()
+        .filter_map(|(entry, stat)| {
+            entry
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| (name.to_string(), stat.is_dir()))
+        })
+        .collect())
+}
+
+/// The worlds in the listing of a vault's folder.
+fn world_ids(entries: Vec<(String, bool)>) -> Vec<String> {
+    entries
+        .into_iter()
+        .filter(|(name, is_dir)| {
+            *is_dir && !matches!(name.as_str(), "." | ".." | "temp" | OBJECTS_DIR)
+        })
+        .map(|(name, _)| name)
+        .collect()
+}
+
+/// The snapshots in the listing of a world's folder.
+fn snapshot_ids(entries: Vec<(String, bool)>) -> Vec<String> {
+    entries
+        .into_iter()
+        .filter(|(_, is_dir)| !is_dir)
+        .filter_map(|(name, _)| name.strip_suffix(SNAPSHOT_SUFFIX).map(str::to_string))
+        .collect()
+}
+
+fn create_dir_all(sftp: &Sftp, path: &str) -> Result<(), Error> {
+    let absolute = path.starts_with('/');
+    let mut current = String::new();
+
+    // Built by hand, as the server's separator is not necessarily ours.
+    for part in path.split('/').filter(|part| !part.is_empty()) {
+        if absolute || !current.is_empty() {
+            current.push('/');
+        }
+        current.push_str(part);
+
+        if sftp.stat(Path::new(&current)).is_err() {
+            sftp.mkdir(Path::new(&current), 0o755)
+                .map_err(|e| sftp_error(format!("Failed to create {}", current), e))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the source to the partial file, continuing after what an earlier
+/// attempt already wrote when `resume` is set.
+fn upload_partial(
+    sftp: &Sftp,
+    source: &Path,
+    partial: &str,
+    resume: bool,
+    throttle: &Throttle,
+) -> Result<(), Error> {
+    let read_error = |e| Error::io(format!("Failed to read {}", source.display()), e);
+
+    let mut file = std::fs::File::open(source).map_err(read_error)?;
+    let source_size = file.metadata().map_err(read_error)?.len();
+
+    let offset = match resume {
+        true => sftp
+            .stat(Path::new(partial))
+            .ok()
+            .and_then(|stat| stat.size)
+            .filter(|size| *size <= source_size)
+            .unwrap_or_default(),
+        false => 0,
+    };
+
+    let mut remote = match offset {
+        0 => sftp.create(Path::new(partial)),
+        _ => sftp.open_mode(Path::new(partial), OpenFlags::WRITE, 0o644, OpenType::File),
+    }
+    .map_err(|e| sftp_error(format!("Failed to create {}", partial), e))?;
+
+    if offset > 0 {
+        info!("Resuming upload of {} at {} bytes", partial, offset);
+
+        file.seek(SeekFrom::Start(offset)).map_err(read_error)?;
+        remote
+            .seek(SeekFrom::Start(offset))
+            .map_err(|e| Error::Network(format!("Failed to resume {}: {}", partial, e)))?;
+    }
+
+    let mut buffer = vec![0; CHUNK_SIZE];
+
+    loop {
+        let read = file.read(&mut buffer).map_err(read_error)?;
+        if read == 0 {
+            break;
+        }
+
+        throttle.consume_blocking(read as u64);
+
+        remote
+            .write_all(&buffer[..read])
+            .map_err(|e| Error::Network(format!("Failed to upload {}: {}", partial, e)))?;
+    }
+
+    Ok(())
+}
+
+/// Uploads to a partial file first and renames it over the snapshot, so an
+/// interrupted upload never leaves a truncated snapshot behind. The partial
+/// file is kept when the connection failed, for the next attempt to resume.
+fn upload(
+    sftp: &Sftp,
+    source: &Path,
+    destination: &str,
+    resume: bool,
+    throttle: &Throttle,
+) -> Result<(), Error> {
+    let partial = format!("{}{}", destination, PARTIAL_SUFFIX);
+
+    let uploaded = upload_partial(sftp, source, &partial, resume, throttle).and_then(|_| {
+        sftp.rename(
+            Path::new(&partial),
+            Path::new(destination),
+            Some(RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE),
+        )
+        .map_err(|e| sftp_error(format!("Failed to move {} into place", partial), e))
+    });
+
+    if let Err(e) = &uploaded {
+        if !matches!(e, Error::Network(_)) {
+            if let Err(e) = sftp.unlink(Path::new(&partial)) {
+                error!("Failed to remove partial upload {}: {}", partial, e);
+            }
+        }
+    }
+
+    uploaded
+}
+
+#[async_trait]
+impl VaultBackend for SftpVault {
+    async fn list_worlds(&self) -> Result<Vec<String>, Error> {
+        let remote_dir = self.settings.remote_dir.clone();
+
+        let entries = self.run(move |sftp| read_dir(sftp, &remote_dir)).await?;
+
+        Ok(world_ids(entries))
+    }
+
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error> {
+        let world_path = self.world_path(world_id);
+
+        let entries = self.run(move |sftp| read_dir(sftp, &world_path)).await?;
+
+        Ok(snapshot_ids(entries))
+    }
+
+    async fn stat_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotStat, Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+        let snapshot_id = snapshot_id.to_string();
+
+        let stat = self
+            .run(move |sftp| {
+                sftp.stat(Path::new(&path))
+                    .map_err(|e| snapshot_error(&snapshot_id, &path, e))
+            })
+            .await?;
+
+        Ok(SnapshotStat {
+            size: stat.size.unwrap_or_default(),
+            modified: stat.mtime.map(|mtime| mtime as i64),
+        })
+    }
+
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error> {
+        let world_path = self.world_path(world_id);
+        let destination = self.snapshot_path(world_id, snapshot_id);
+        let upload_id = format!(
+            "sftp://{}@{}:{}/{}",
+            self.settings.user, self.settings.host, self.settings.port, destination
+        );
+
+        let resume = load_upload_state(&upload_id, source).await.is_some();
+
+        if !resume {
+            save_upload_state(&new_upload_state(&upload_id, source).await?).await;
+        }
+
+        info!("Uploading {} to {}", destination, self.settings.host);
+
+        let source = source.to_path_buf();
+        let throttle = self.throttle.clone();
+
+        let uploaded = self
+            .run(move |sftp| {
+                create_dir_all(sftp, &world_path)?;
+                upload(sftp, &source, &destination, resume, &throttle)
+            })
+            .await;
+
+        if !matches!(uploaded, Err(Error::Network(_))) {
+            clear_upload_state(&upload_id).await;
+        }
+
+        uploaded
+    }
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error> {
+        // Surfaces a missing snapshot as an error instead of an empty stream.
+        self.stat_snapshot(world_id, snapshot_id).await?;
+
+        let path = self.snapshot_path(world_id, snapshot_id);
+        let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(4);
+
+        let download = self.run(move |sftp| {
+            let mut file = match sftp.open(Path::new(&path)) {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(io::Error::other(e)));
+                    return Ok(());
+                }
+            };
+
+            let mut buffer = vec![0; CHUNK_SIZE];
+
+            loop {
+                let chunk = match file.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => Ok(Bytes::copy_from_slice(&buffer[..read])),
+                    Err(e) => Err(e),
+                };
+
+                let failed = chunk.is_err();
+
+                // The reader was dropped, nobody wants the rest.
+                if sender.blocking_send(chunk).is_err() || failed {
+                    return Ok(());
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            if let Err(e) = download.await {
+                error!("Failed to download backup: {}", e);
+            }
+        });
+
+        let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|chunk| (chunk, receiver))
+        });
+
+        Ok(Box::new(StreamReader::new(Box::pin(stream))))
+    }
+
+    async fn read_snapshot_range(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+        let snapshot_id = snapshot_id.to_string();
+
+        self.run(move |sftp| {
+            let mut file = sftp
+                .open(Path::new(&path))
+                .map_err(|e| snapshot_error(&snapshot_id, &path, e))?;
+
+            let mut data = Vec::new();
+
+            file.seek(SeekFrom::Start(range.start))
+                .and_then(|_| {
+                    file.take(range.end.saturating_sub(range.start))
+                        .read_to_end(&mut data)
+                })
+                .map_err(|e| Error::Network(format!("Failed to read {}: {}", path, e)))?;
+
+            Ok(data)
+        })
+        .await
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error> {
+        let path = self.snapshot_path(world_id, snapshot_id);
+
+        self.run(move |sftp| {
+            sftp.unlink(Path::new(&path))
+                .map_err(|e| sftp_error(format!("Failed to remove backup file {}", path), e))
+        })
+        .await
+    }
+
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error> {
+        let world_path = self.world_path(world_id);
+
+        self.run(move |sftp| {
+            for (name, is_dir) in read_dir(sftp, &world_path)? {
+                if is_dir {
+                    continue;
+                }
+
+                let path = format!("{}/{}", world_path, name);
+                sftp.unlink(Path::new(&path))
+                    .map_err(|e| sftp_error(format!("Failed to remove {}", path), e))?;
+            }
+
+            sftp.rmdir(Path::new(&world_path))
+                .map_err(|e| sftp_error(format!("Failed to remove {}", world_path), e))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::vault::tests::round_trip;
+
+    use super::*;
+
+    fn settings(remote_dir: &str) -> SftpBackup {
+        SftpBackup {
+            host: "localhost".to_string(),
+            port: 22,
+            user: "teller".to_string(),
+            key_path: "id_ed25519".into(),
+            remote_dir: remote_dir.to_string(),
+            known_hosts: None,
+        }
+    }
+
+    fn entries(entries: &[(&str, bool)]) -> Vec<(String, bool)> {
+        entries
+            .iter()
+            .map(|(name, is_dir)| (name.to_string(), *is_dir))
+            .collect()
+    }
+
+    #[test]
+    fn maps_snapshots_to_paths() {
+        let vault = SftpVault::new(&settings("/srv/backups/"));
+
+        assert_eq!(vault.world_path("world"), "/srv/backups/world");
+        assert_eq!(
+            vault.snapshot_path("world", "1700000000"),
+            "/srv/backups/world/1700000000.chunkvault-snapshot"
+        );
+
+        let vault = SftpVault::new(&settings("backups"));
+
+        assert_eq!(vault.world_path("world"), "backups/world");
+    }
+
+    #[test]
+    fn lists_world_folders() {
+        let mut world_ids = world_ids(entries(&[
+            (".", true),
+            ("..", true),
+            ("temp", true),
+            (OBJECTS_DIR, true),
+            ("first", true),
+            ("second", true),
+            ("notes.txt", false),
+        ]));
+        world_ids.sort();
+
+        assert_eq!(world_ids, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn lists_snapshot_files() {
+        let snapshot_ids = snapshot_ids(entries(&[
+            ("1700000000.chunkvault-snapshot", false),
+            ("1800000000.chunkvault-snapshot.partial", false),
+            ("1900000000.chunkvault-snapshot", true),
+            ("notes.txt", false),
+        ]));
+
+        assert_eq!(snapshot_ids, vec!["1700000000"]);
+    }
+
+    /// Runs against an SSH server given by `TELLER_TEST_SFTP_HOST`,
+    /// `TELLER_TEST_SFTP_PORT`, `TELLER_TEST_SFTP_USER` and
+    /// `TELLER_TEST_SFTP_KEY`, in the folder `TELLER_TEST_SFTP_DIR`. The host
+    /// key is checked against `TELLER_TEST_SFTP_KNOWN_HOSTS` when it is set.
+    #[tokio::test]
+    #[ignore]
+    async fn sshd_round_trip() {
+        let (Ok(host), Ok(user), Ok(key_path), Ok(remote_dir)) = (
+            std::env::var("TELLER_TEST_SFTP_HOST"),
+            std::env::var("TELLER_TEST_SFTP_USER"),
+            std::env::var("TELLER_TEST_SFTP_KEY"),
+            std::env::var("TELLER_TEST_SFTP_DIR"),
+        ) else {
+            return;
+        };
+
+        let vault = SftpVault::new(&SftpBackup {
+            host,
+            port: std::env::var("TELLER_TEST_SFTP_PORT")
+                .ok()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(22),
+            user,
+            key_path: key_path.into(),
+            remote_dir,
+            known_hosts: std::env::var("TELLER_TEST_SFTP_KNOWN_HOSTS")
+                .ok()
+                .map(Into::into),
+        });
+
+        round_trip(&vault, CHUNK_SIZE * 3 + 1024).await;
+    }
+}
@@ -0,0 +1,1358 @@
+use std::io::{self, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use async_trait::async_trait;
+use futures_util::TryStreamExt;
+use log::{error, info};
+use percent_encoding::percent_decode_str;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::StreamReader;
+use url::Url;
+
+use crate::handlers::atomic::PARTIAL_SUFFIX;
+use crate::handlers::store::OBJECTS_DIR;
+use crate::handlers::transfer::{
+    clear_upload_state, load_upload_state, new_upload_state, save_upload_state, throttled_body,
+    Throttle,
+};
+use crate::types::backup::{SnapshotStat, UploadState, UploadedPart, WebdavBackup};
+use crate::types::error::Error;
+
+use super::{snapshot_file_name, SnapshotReader, VaultBackend, SNAPSHOT_SUFFIX};
+
+/// Snapshots larger than this are uploaded in chunks of this size. Nextcloud
+/// needs every chunk but the last to be at least 5 MiB.
+const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Name of the file in a Nextcloud upload folder that stands for the
+/// assembled chunks.
+const ASSEMBLED_FILE: &str = ".file";
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getlastmodified/>
+  </d:prop>
+</d:propfind>"#;
+
+/// One `<response>` of a PROPFIND.
+#[derive(Default)]
+struct DavEntry {
+    href: String,
+    collection: bool,
+    size: Option<u64>,
+    modified: Option<i64>,
+}
+
+/// Reads the entries of a PROPFIND multistatus. Elements are matched by
+/// their local name, as servers disagree on the prefix of the `DAV:`
+/// namespace.
+fn parse_multistatus(xml: &str) -> Result<Vec<DavEntry>, Error> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut entries = Vec::new();
+    let mut entry: Option<DavEntry> = None;
+    let mut element = Vec::new();
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| Error::Vault(format!("Invalid WebDAV listing: {}", e)))?;
+
+        match event {
+            Event::Start(start) => {
+                element = start.local_name().as_ref().to_vec();
+
+                match element.as_slice() {
+                    b"response" => entry = Some(DavEntry::default()),
+                    b"collection" => {
+                        if let Some(entry) = entry.as_mut() {
+                            entry.collection = true;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Empty(empty) if empty.local_name().as_ref() == b"collection" => {
+                if let Some(entry) = entry.as_mut() {
+                    entry.collection = true;
+                }
+            }
+            Event::Text(text) => {
+                let (Some(entry), Ok(text)) = (entry.as_mut(), text.unescape()) else {
+                    continue;
+                };
+
+                match element.as_slice() {
+                    b"href" => entry.href = text.trim().to_string(),
+                    b"getcontentlength" => entry.size = text.trim().parse().ok(),
+                    b"getlastmodified" => {
+                        entry.modified = chrono::DateTime::parse_from_rfc2822(text.trim())
+                            .ok()
+                            .map(|modified| modified.timestamp())
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(end) => {
+                if end.local_name().as_ref() == b"response" {
+                    if let Some(entry) = entry.take() {
+                        entries.push(entry);
+                    }
+                }
+
+                element.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+fn join_url(base_url: &Url, segments: &[&str], collection: bool) -> Url {
+    let mut url = base_url.clone();
+
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.pop_if_empty().extend(segments);
+
+        // Collections are addressed with a trailing slash, which some
+        // servers redirect to otherwise.
+        if collection {
+            path.push("");
+        }
+    }
+
+    url
+}
+
+/// The folder Nextcloud takes chunked uploads in, for vaults addressed
+/// through its `remote.php/dav/files/<user>` endpoint.
+fn nextcloud_uploads_url(base_url: &Url) -> Option<Url> {
+    let segments: Vec<String> = base_url
+        .path_segments()?
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().to_string())
+        .collect();
+
+    let files = segments
+        .windows(3)
+        .position(|window| window == ["remote.php", "dav", "files"])?;
+    let user = segments.get(files + 3).filter(|user| !user.is_empty())?;
+
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .ok()?
+        .clear()
+        .extend(&segments[..files])
+        .extend(["remote.php", "dav", "uploads", user])
+        .push("");
+
+    Some(url)
+}
+
+/// A vault in a folder on a WebDAV server, keeping snapshots under
+/// `<url>/<world_id>/<snapshot_id>.chunkvault-snapshot`.
+///
+/// Snapshots only appear once they were uploaded completely, so a dropped
+/// connection never leaves a truncated snapshot. Snapshots larger than a
+/// chunk are uploaded a chunk at a time, and every chunk the server
+/// confirmed is recorded so an interrupted upload continues where it
+/// stopped, even after a restart. Nextcloud vaults use its chunked upload,
+/// other servers get ranged PUTs to a partial file, or the whole snapshot at
+/// once if they do not take those.
+pub struct WebdavVault {
+    client: Client,
+    base_url: Url,
+    uploads_url: Option<Url>,
+    username: Option<String>,
+    password: Option<String>,
+    throttle: Throttle,
+    chunk_size: u64,
+}
+
+impl WebdavVault {
+    pub fn new(settings: &WebdavBackup) -> Result<Self, Error> {
+        Self::with_client(Client::new(), settings)
+    }
+
+    /// Uses the given HTTP client, for example one accepting the self-signed
+    /// certificate of a NAS.
+    pub fn with_client(client: Client, settings: &WebdavBackup) -> Result<Self, Error> {
+        let base_url = Url::parse(&settings.url)
+            .map_err(|e| Error::Config(format!("Invalid WebDAV url {}: {}", settings.url, e)))?;
+
+        if base_url.cannot_be_a_base() {
+            return Err(Error::Config(format!(
+                "Invalid WebDAV url {}",
+                settings.url
+            )));
+        }
+
+        Ok(Self {
+            client,
+            uploads_url: nextcloud_uploads_url(&base_url),
+            base_url,
+            username: settings.username.clone(),
+            password: settings.password.clone(),
+            throttle: Throttle::default(),
+            chunk_size: CHUNK_SIZE,
+        })
+    }
+
+    pub fn with_throttle(mut self, throttle: Throttle) -> Self {
+        self.throttle = throttle;
+        self
+    }
+
+    fn url(&self, segments: &[&str], collection: bool) -> Url {
+        join_url(&self.base_url, segments, collection)
+    }
+
+    fn snapshot_url(&self, world_id: &str, snapshot_id: &str) -> Url {
+        self.url(&[world_id, &snapshot_file_name(snapshot_id)], false)
+    }
+
+    fn request(&self, method: &str, url: Url) -> Result<RequestBuilder, Error> {
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|e| Error::Vault(format!("Invalid WebDAV method {}: {}", method, e)))?;
+
+        let request = self.client.request(method, url);
+
+        Ok(match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        })
+    }
+
+    async fn check_response(response: Response) -> Result<Response, Error> {
+        let status = response.status();
+
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let url = response.url().clone();
+        let body = response.text().await.unwrap_or_default();
+
+        Err(match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Error::Vault(format!("WebDAV server rejected the login for {}", url))
+            }
+            _ => Error::Network(format!(
+                "WebDAV server returned {} for {}: {}",
+                status, url, body
+            )),
+        })
+    }
+
+    fn snapshot_not_found(snapshot_id: &str) -> Error {
+        Error::SnapshotNotFound(format!("Backup {} does not exist", snapshot_id))
+    }
+
+    /// Lists the members of a collection, without the collection itself.
+    async fn list(&self, url: Url) -> Result<Vec<(String, DavEntry)>, Error> {
+        let response = self
+            .request("PROPFIND", url.clone())?
+            .header("Depth", "1")
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+
+        let listing = Self::check_response(response).await?.text().await?;
+        let own_path = url.path().trim_end_matches('/');
+
+        Ok(parse_multistatus(&listing)?
+            .into_iter()
+            .filter_map(|entry| {
+                let member = url.join(&entry.href).ok()?;
+                let path = member.path().trim_end_matches('/');
+
+                if path == own_path {
+                    return None;
+                }
+
+                let name = path.rsplit('/').next()?;
+                let name = percent_decode_str(name).decode_utf8_lossy().to_string();
+
+                Some((name, entry))
+            })
+            .collect())
+    }
+
+    /// Creates a collection, which is fine when it already exists.
+    async fn create_collection(&self, url: Url) -> Result<(), Error> {
+        let response = self.request("MKCOL", url)?.send().await?;
+
+        if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+            return Ok(());
+        }
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+
+    /// Looks up a single resource, `None` when it does not exist.
+    async fn stat(&self, url: Url) -> Result<Option<DavEntry>, Error> {
+        let response = self
+            .request("PROPFIND", url)?
+            .header("Depth", "0")
+            .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+            .body(PROPFIND_BODY)
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let listing = Self::check_response(response).await?.text().await?;
+
+        Ok(parse_multistatus(&listing)?.into_iter().next())
+    }
+
+    async fn remove(&self, url: Url) {
+        let removed = match self.request("DELETE", url.clone()) {
+            Ok(request) => request.send().await.map(|_| ()).map_err(Error::from),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = removed {
+            error!("Failed to remove partial upload {}: {}", url, e);
+        }
+    }
+
+    async fn move_into_place(&self, from: Url, destination: &Url) -> Result<(), Error> {
+        let response = self
+            .request("MOVE", from)?
+            .header("Destination", destination.as_str())
+            .header("Overwrite", "T")
+            .send()
+            .await?;
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+
+    async fn read_chunk(&self, source: &Path, offset: u64) -> Result<Vec<u8>, Error> {
+        let read_error = |e| Error::io(format!("Failed to read {}", source.display()), e);
+
+        let mut file = File::open(source).await.map_err(read_error)?;
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(read_error)?;
+
+        let mut chunk = Vec::with_capacity(self.chunk_size as usize);
+        file.take(self.chunk_size)
+            .read_to_end(&mut chunk)
+            .await
+            .map_err(read_error)?;
+
+        Ok(chunk)
+    }
+
+    async fn upload_partial(&self, source: &Path, partial: Url) -> Result<(), Error> {
+        let file = File::open(source)
+            .await
+            .map_err(|e| Error::io(format!("Failed to open {}", source.display()), e))?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?
+            .len();
+
+        let response = self
+            .request("PUT", partial)?
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .header(CONTENT_LENGTH, size)
+            .body(throttled_body(file, self.throttle.clone()))
+            .send()
+            .await?;
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+
+    /// Uploads the whole snapshot in one request to a partial file, and moves
+    /// it over the snapshot once it is complete.
+    async fn upload_whole(&self, source: &Path, destination: &Url) -> Result<(), Error> {
+        let partial = partial_url(destination);
+
+        let uploaded = async {
+            self.upload_partial(source, partial.clone()).await?;
+            self.move_into_place(partial.clone(), destination).await
+        }
+        .await;
+
+        if uploaded.is_err() {
+            self.remove(partial).await;
+        }
+
+        uploaded
+    }
+
+    /// Picks up the upload folder of an interrupted chunked upload, keeping
+    /// the chunks the server still has in full. Returns `None` when there is
+    /// nothing to resume.
+    async fn resume_chunked(&self, uploads_url: &Url, state: &mut UploadState) -> Option<Url> {
+        let upload_url = join_url(uploads_url, &[state.upload_id.as_deref()?], true);
+
+        let chunks = match self.list(upload_url.clone()).await {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                info!(
+                    "Could not resume upload {}, starting over: {}",
+                    upload_url, e
+                );
+                return None;
+            }
+        };
+
+        let total = state.source_size;
+        let chunk_size = self.chunk_size;
+
+        let resumable = state
+            .parts
+            .iter()
+            .take_while(|part| {
+                let offset = (part.number as u64 - 1) * chunk_size;
+                let length = chunk_size.min(total.saturating_sub(offset));
+
+                chunks.iter().any(|(name, chunk)| {
+                    *name == part.number.to_string() && chunk.size == Some(length)
+                })
+            })
+            .count();
+        state.parts.truncate(resumable);
+
+        info!(
+            "Resuming upload {} after {} chunks",
+            upload_url,
+            state.parts.len()
+        );
+
+        Some(upload_url)
+    }
+
+    /// Uploads with Nextcloud's chunked upload. The chunks are collected in
+    /// an upload folder, and moving its assembled file puts the snapshot in
+    /// place. The folder is kept when the connection failed, for the next
+    /// attempt to resume.
+    async fn upload_chunked(
+        &self,
+        source: &Path,
+        destination: &Url,
+        uploads_url: &Url,
+        state: &mut UploadState,
+    ) -> Result<(), Error> {
+        let upload_url = match self.resume_chunked(uploads_url, state).await {
+            Some(upload_url) => upload_url,
+            None => {
+                let upload_id = format!("teller-{}", uuid::Uuid::new_v4());
+                let upload_url = join_url(uploads_url, &[&upload_id], true);
+
+                let response = self
+                    .request("MKCOL", upload_url.clone())?
+                    .header("Destination", destination.as_str())
+                    .send()
+                    .await?;
+                Self::check_response(response).await?;
+
+                state.upload_id = Some(upload_id);
+                state.parts.clear();
+                save_upload_state(state).await;
+
+                upload_url
+            }
+        };
+
+        let total = state.source_size;
+
+        let uploaded = async {
+            loop {
+                let offset = state.parts.len() as u64 * self.chunk_size;
+                if offset >= total {
+                    break;
+                }
+
+                let number = state.parts.len() as i32 + 1;
+                let chunk = self.read_chunk(source, offset).await?;
+
+                self.throttle.consume(chunk.len() as u64).await;
+
+                let response = self
+                    .request("PUT", join_url(&upload_url, &[&number.to_string()], false))?
+                    .header("Destination", destination.as_str())
+                    .header("OC-Total-Length", total)
+                    .header(CONTENT_LENGTH, chunk.len())
+                    .body(chunk)
+                    .send()
+                    .await?;
+                Self::check_response(response).await?;
+
+                state.parts.push(UploadedPart { number, etag: None });
+                save_upload_state(state).await;
+            }
+
+            let response = self
+                .request("MOVE", join_url(&upload_url, &[ASSEMBLED_FILE], false))?
+                .header("Destination", destination.as_str())
+                .header("OC-Total-Length", total)
+                .header("Overwrite", "T")
+                .send()
+                .await?;
+            Self::check_response(response).await?;
+
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = &uploaded {
+            if !matches!(e, Error::Network(_)) {
+                self.remove(upload_url).await;
+            }
+        }
+
+        uploaded
+    }
+
+    /// Writes the snapshot to the partial file a chunk at a time with ranged
+    /// PUTs. A chunk counts as confirmed once the partial file has grown by
+    /// it, since servers that ignore the range replace the file instead.
+    /// Returns `false` when the server does not take ranged PUTs.
+    async fn upload_ranges(
+        &self,
+        source: &Path,
+        partial: &Url,
+        state: &mut UploadState,
+    ) -> Result<bool, Error> {
+        let total = state.source_size;
+
+        // Chunks only count while the partial file still holds them.
+        let written = match self.stat(partial.clone()).await? {
+            Some(entry) => entry.size.unwrap_or_default(),
+            None => 0,
+        };
+        let kept = (written / self.chunk_size) as usize;
+        state.parts.truncate(kept);
+
+        if !state.parts.is_empty() {
+            info!(
+                "Resuming upload of {} after {} chunks",
+                partial,
+                state.parts.len()
+            );
+        }
+
+        loop {
+            let offset = state.parts.len() as u64 * self.chunk_size;
+            if offset >= total {
+                return Ok(true);
+            }
+
+            let number = state.parts.len() as i32 + 1;
+            let chunk = self.read_chunk(source, offset).await?;
+            let end = offset + chunk.len() as u64;
+
+            self.throttle.consume(chunk.len() as u64).await;
+
+            let response = self
+                .request("PUT", partial.clone())?
+                .header(
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", offset, end - 1, total),
+                )
+                .header(CONTENT_LENGTH, chunk.len())
+                .body(chunk)
+                .send()
+                .await?;
+
+            if matches!(
+                response.status(),
+                StatusCode::BAD_REQUEST
+                    | StatusCode::METHOD_NOT_ALLOWED
+                    | StatusCode::NOT_IMPLEMENTED
+                    | StatusCode::RANGE_NOT_SATISFIABLE
+            ) {
+                return Ok(false);
+            }
+
+            Self::check_response(response).await?;
+
+            let confirmed = self
+                .stat(partial.clone())
+                .await?
+                .and_then(|entry| entry.size);
+
+            if confirmed != Some(end) {
+                return Ok(false);
+            }
+
+            state.parts.push(UploadedPart { number, etag: None });
+            save_upload_state(state).await;
+        }
+    }
+
+    async fn upload_ranged(
+        &self,
+        source: &Path,
+        destination: &Url,
+        state: &mut UploadState,
+    ) -> Result<(), Error> {
+        let partial = partial_url(destination);
+
+        let uploaded = match self.upload_ranges(source, &partial, state).await {
+            Ok(true) => self.move_into_place(partial.clone(), destination).await,
+            Ok(false) => {
+                info!(
+                    "{} does not take ranged uploads, uploading it at once",
+                    destination
+                );
+                state.parts.clear();
+                save_upload_state(state).await;
+
+                return self.upload_whole(source, destination).await;
+            }
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = &uploaded {
+            if !matches!(e, Error::Network(_)) {
+                self.remove(partial).await;
+            }
+        }
+
+        uploaded
+    }
+
+    /// Uploads a snapshot so it only appears at `destination` once it is
+    /// complete. Snapshots larger than a chunk record every chunk the server
+    /// confirmed, so an upload cut off by the connection continues where it
+    /// stopped on the next attempt. Uploads that fail for other reasons start
+    /// over.
+    async fn upload(&self, source: &Path, destination: Url) -> Result<(), Error> {
+        let size = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| Error::io(format!("Failed to read {}", source.display()), e))?
+            .len();
+
+        if size <= self.chunk_size {
+            return self.upload_whole(source, &destination).await;
+        }
+
+        let state_destination = destination.to_string();
+
+        let mut state = match load_upload_state(&state_destination, source).await {
+            Some(state) => state,
+            None => {
+                let state = new_upload_state(&state_destination, source).await?;
+                save_upload_state(&state).await;
+                state
+            }
+        };
+
+        let uploaded = match &self.uploads_url {
+            Some(uploads_url) => {
+                self.upload_chunked(source, &destination, uploads_url, &mut state)
+                    .await
+            }
+            None => self.upload_ranged(source, &destination, &mut state).await,
+        };
+
+        if !matches!(uploaded, Err(Error::Network(_))) {
+            clear_upload_state(&state_destination).await;
+        }
+
+        uploaded
+    }
+}
+
+fn partial_url(destination: &Url) -> Url {
+    let mut partial = destination.clone();
+    partial.set_path(&format!("{}{}", destination.path(), PARTIAL_SUFFIX));
+    partial
+}
+
+#[async_trait]
+impl VaultBackend for WebdavVault {
+    async fn list_worlds(&self) -> Result<Vec<String>, Error> {
+        Ok(self
+            .list(self.url(&[], true))
+            .await?
+            .into_iter()
+            .filter(|(name, entry)| entry.collection && name != "temp" && name != OBJECTS_DIR)
+            .map(|(name, _)| name)
+            .collect())
+    }
+
+    async fn list_snapshots(&self, world_id: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .list(self.url(&[world_id], true))
+            .await?
+            .into_iter()
+            .filter(|(_, entry)| !entry.collection)
+            .filter_map(|(name, _)| name.strip_suffix(SNAPSHOT_SUFFIX).map(str::to_string))
+            .collect())
+    }
+
+    async fn stat_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotStat, Error> {
+        match self.stat(self.snapshot_url(world_id, snapshot_id)).await? {
+            Some(entry) => Ok(SnapshotStat {
+                size: entry.size.unwrap_or_default(),
+                modified: entry.modified,
+            }),
+            None => Err(Self::snapshot_not_found(snapshot_id)),
+        }
+    }
+
+    async fn put_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        source: &Path,
+    ) -> Result<(), Error> {
+        self.create_collection(self.url(&[], true)).await?;
+        self.create_collection(self.url(&[world_id], true)).await?;
+
+        let destination = self.snapshot_url(world_id, snapshot_id);
+
+        info!("Uploading {}", destination);
+
+        self.upload(source, destination).await
+    }
+
+    async fn get_snapshot(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+    ) -> Result<SnapshotReader, Error> {
+        let response = self
+            .request("GET", self.snapshot_url(world_id, snapshot_id))?
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Self::snapshot_not_found(snapshot_id));
+        }
+
+        let stream = Self::check_response(response)
+            .await?
+            .bytes_stream()
+            .map_err(io::Error::other);
+
+        Ok(Box::new(StreamReader::new(Box::pin(stream))))
+    }
+
+    async fn read_snapshot_range(
+        &self,
+        world_id: &str,
+        snapshot_id: &str,
+        range: Range<u64>,
+    ) -> Result<Vec<u8>, Error> {
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let response = self
+            .request("GET", self.snapshot_url(world_id, snapshot_id))?
+            .header(RANGE, format!("bytes={}-{}", range.start, range.end - 1))
+            .send()
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(Self::snapshot_not_found(snapshot_id));
+        }
+
+        let response = Self::check_response(response).await?;
+        let length = (range.end - range.start) as usize;
+
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            let mut data = response.bytes().await?.to_vec();
+            data.truncate(length);
+            return Ok(data);
+        }
+
+        // Servers without range support answer with the whole snapshot, which
+        // is read up to the end of the range and skipped until its start.
+        let mut stream = response.bytes_stream();
+        let mut data = Vec::with_capacity(length);
+        let mut position = 0;
+
+        while position < range.end {
+            let chunk = match stream.try_next().await? {
+                Some(chunk) => chunk,
+                None => break,
+            };
+
+            let chunk_end = position + chunk.len() as u64;
+
+            if chunk_end > range.start {
+                let from = range.start.saturating_sub(position) as usize;
+                let to = (range.end.min(chunk_end) - position) as usize;
+                data.extend_from_slice(&chunk[from..to]);
+            }
+
+            position = chunk_end;
+        }
+
+        Ok(data)
+    }
+
+    async fn delete_snapshot(&self, world_id: &str, snapshot_id: &str) -> Result<(), Error> {
+        let response = self
+            .request("DELETE", self.snapshot_url(world_id, snapshot_id))?
+            .send()
+            .await?;
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+
+    async fn delete_world(&self, world_id: &str) -> Result<(), Error> {
+        let response = self
+            .request("DELETE", self.url(&[world_id], true))?
+            .send()
+            .await?;
+
+        Self::check_response(response).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::sync::{Arc, Mutex};
+
+    use crate::handlers::mock_http::{serve, MockServer, Reply, Request};
+    use crate::handlers::vault::tests::round_trip;
+
+    use super::*;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Ranges {
+        Honoured,
+        /// Ranged PUTs replace the file and ranged GETs get all of it.
+        Ignored,
+        Rejected,
+    }
+
+    type FailOnce = Box<dyn Fn(&Request) -> bool + Send>;
+
+    /// An in-memory WebDAV server, with Nextcloud's chunked upload.
+    struct Dav {
+        files: BTreeMap<String, Vec<u8>>,
+        collections: BTreeSet<String>,
+        ranges: Ranges,
+        /// Answers the first request it matches with a 503.
+        fail_once: Option<FailOnce>,
+    }
+
+    impl Dav {
+        fn is_collection(&self, path: &str) -> bool {
+            self.collections.contains(path)
+                || self
+                    .files
+                    .keys()
+                    .any(|file| file.starts_with(&format!("{}/", path)))
+        }
+
+        fn propfind(&self, path: &str, depth: &str) -> Reply {
+            let entry = |href: &str, size: Option<usize>| {
+                match size {
+                Some(size) => format!(
+                    "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:resourcetype/><d:getcontentlength>{}</d:getcontentlength></d:prop></d:propstat></d:response>",
+                    href, size
+                ),
+                None => format!(
+                    "<d:response><d:href>{}/</d:href><d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat></d:response>",
+                    href
+                ),
+            }
+            };
+
+            let mut responses = Vec::new();
+
+            if let Some(data) = self.files.get(path) {
+                responses.push(entry(path, Some(data.len())));
+            } else if self.is_collection(path) {
+                responses.push(entry(path, None));
+
+                if depth == "1" {
+                    let prefix = format!("{}/", path);
+                    let mut members = BTreeMap::new();
+
+                    for (file, data) in &self.files {
+                        if let Some(member) = file.strip_prefix(&prefix) {
+                            match member.split_once('/') {
+                                Some((folder, _)) => members.insert(folder.to_string(), None),
+                                None => members.insert(member.to_string(), Some(data.len())),
+                            };
+                        }
+                    }
+
+                    for collection in &self.collections {
+                        if let Some(member) = collection.strip_prefix(&prefix) {
+                            if !member.contains('/') {
+                                members.insert(member.to_string(), None);
+                            }
+                        }
+                    }
+
+                    for (member, size) in members {
+                        responses.push(entry(&format!("{}{}", prefix, member), size));
+                    }
+                }
+            } else {
+                return Reply::new(404);
+            }
+
+            Reply::new(207).body(format!(
+                r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:">{}</d:multistatus>"#,
+                responses.concat()
+            ))
+        }
+
+        fn put(&mut self, path: &str, request: &Request) -> Reply {
+            let range = request.header("Content-Range").and_then(|range| {
+                let (start, _) = range.strip_prefix("bytes ")?.split_once('-')?;
+                start.parse::<usize>().ok()
+            });
+
+            match (range, self.ranges) {
+                (Some(_), Ranges::Rejected) => return Reply::new(501),
+                (Some(start), Ranges::Honoured) => {
+                    let file = self.files.entry(path.to_string()).or_default();
+
+                    if file.len() < start {
+                        return Reply::new(416);
+                    }
+
+                    file.truncate(start);
+                    file.extend_from_slice(&request.body);
+                }
+                _ => {
+                    self.files.insert(path.to_string(), request.body.clone());
+                }
+            }
+
+            Reply::new(201)
+        }
+
+        fn move_to(&mut self, path: &str, request: &Request) -> Reply {
+            let destination = request.header("Destination").unwrap();
+            let destination = destination.splitn(4, '/').nth(3).unwrap();
+            let destination = format!("/{}", destination);
+
+            let data = match path.strip_suffix(&format!("/{}", ASSEMBLED_FILE)) {
+                Some(upload) => {
+                    let prefix = format!("{}/", upload);
+                    let mut chunks: Vec<(u32, Vec<u8>)> = self
+                        .files
+                        .iter()
+                        .filter_map(|(file, data)| {
+                            let number = file.strip_prefix(&prefix)?.parse().ok()?;
+                            Some((number, data.clone()))
+                        })
+                        .collect();
+                    chunks.sort_by_key(|(number, _)| *number);
+
+                    self.delete(upload);
+                    chunks.into_iter().flat_map(|(_, data)| data).collect()
+                }
+                None => match self.files.remove(path) {
+                    Some(data) => data,
+                    None => return Reply::new(404),
+                },
+            };
+
+            self.files.insert(destination, data);
+
+            Reply::new(201)
+        }
+
+        fn delete(&mut self, path: &str) -> Reply {
+            let prefix = format!("{}/", path);
+            let found = self.files.contains_key(path) || self.is_collection(path);
+
+            self.files
+                .retain(|file, _| file != path && !file.starts_with(&prefix));
+            self.collections
+                .retain(|collection| collection != path && !collection.starts_with(&prefix));
+
+            Reply::new(if found { 204 } else { 404 })
+        }
+
+        fn answer(&mut self, request: &Request) -> Reply {
+            if self.fail_once.as_ref().is_some_and(|fail| fail(request)) {
+                self.fail_once = None;
+                return Reply::new(503);
+            }
+
+            let path = request.path.trim_end_matches('/').to_string();
+
+            match request.method.as_str() {
+                "PROPFIND" => self.propfind(&path, request.header("Depth").unwrap_or("1")),
+                "MKCOL" if self.is_collection(&path) => Reply::new(405),
+                "MKCOL" => {
+                    self.collections.insert(path);
+                    Reply::new(201)
+                }
+                "PUT" => self.put(&path, request),
+                "MOVE" => self.move_to(&path, request),
+                "DELETE" => self.delete(&path),
+                "GET" => {
+                    let Some(data) = self.files.get(&path) else {
+                        return Reply::new(404);
+                    };
+
+                    let range = request.header("Range").and_then(|range| {
+                        let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+                        Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+                    });
+
+                    match range {
+                        Some((start, end)) if self.ranges == Ranges::Honoured => {
+                            Reply::new(206).body(&data[start..=end.min(data.len() - 1)])
+                        }
+                        _ => Reply::new(200).body(data.clone()),
+                    }
+                }
+                _ => Reply::new(405),
+            }
+        }
+    }
+
+    async fn dav_server(ranges: Ranges, fail_once: Option<FailOnce>) -> MockServer {
+        let dav = Arc::new(Mutex::new(Dav {
+            files: BTreeMap::new(),
+            collections: BTreeSet::new(),
+            ranges,
+            fail_once,
+        }));
+
+        serve(move |request| dav.lock().unwrap().answer(request)).await
+    }
+
+    fn vault(url: String) -> WebdavVault {
+        let mut vault = WebdavVault::new(&WebdavBackup {
+            url,
+            username: Some("alice".to_string()),
+            password: Some("secret".to_string()),
+        })
+        .unwrap();
+        vault.chunk_size = 1024;
+        vault
+    }
+
+    fn puts(server: &MockServer) -> Vec<Request> {
+        server
+            .requests()
+            .into_iter()
+            .filter(|request| request.method == "PUT")
+            .collect()
+    }
+
+    async fn snapshot_source(size: usize) -> (std::path::PathBuf, Vec<u8>) {
+        let data: Vec<u8> = (0..size).map(|i| (i % 253) as u8).collect();
+        let path = std::env::temp_dir().join(format!("teller-webdav-{}", uuid::Uuid::new_v4()));
+        tokio::fs::write(&path, &data).await.unwrap();
+        (path, data)
+    }
+
+    async fn read_snapshot(vault: &WebdavVault, world_id: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        vault
+            .get_snapshot(world_id, "1700000000")
+            .await
+            .unwrap()
+            .read_to_end(&mut data)
+            .await
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn parses_multistatus() {
+        let entries = parse_multistatus(
+            r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/teller/</d:href>
+                <d:propstat>
+                  <d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/teller/my%20world/1700000000.chunkvault-snapshot</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <d:resourcetype/>
+                    <d:getcontentlength>2048</d:getcontentlength>
+                    <d:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</d:getlastmodified>
+                  </d:prop>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#,
+        )
+        .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].collection);
+        assert_eq!(entries[0].size, None);
+        assert!(!entries[1].collection);
+        assert_eq!(
+            entries[1].href,
+            "/remote.php/dav/files/alice/teller/my%20world/1700000000.chunkvault-snapshot"
+        );
+        assert_eq!(entries[1].size, Some(2048));
+        assert_eq!(entries[1].modified, Some(1700000000));
+    }
+
+    #[test]
+    fn parses_multistatus_of_any_prefix() {
+        let entries = parse_multistatus(
+            r#"<multistatus xmlns="DAV:"><response><href>/a&amp;b</href><propstat><prop><resourcetype><collection></collection></resourcetype></prop></propstat></response><D:response xmlns:D="DAV:"><D:href>/c</D:href><D:propstat><D:prop><D:getcontentlength>7</D:getcontentlength><D:getlastmodified>not a date</D:getlastmodified></D:prop></D:propstat></D:response></multistatus>"#,
+        )
+        .unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].href, "/a&b");
+        assert!(entries[0].collection);
+        assert_eq!(entries[1].size, Some(7));
+        assert_eq!(entries[1].modified, None);
+    }
+
+    #[test]
+    fn rejects_invalid_multistatus() {
+        assert!(parse_multistatus("<d:multistatus><d:response></d:multistatus>").is_err());
+        assert!(parse_multistatus("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_nextcloud_upload_folder() {
+        let uploads = |url: &str| nextcloud_uploads_url(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            uploads("https://cloud.example.com/remote.php/dav/files/alice/teller")
+                .unwrap()
+                .as_str(),
+            "https://cloud.example.com/remote.php/dav/uploads/alice/"
+        );
+        assert_eq!(
+            uploads("https://example.com/nextcloud/remote.php/dav/files/a%20b/")
+                .unwrap()
+                .as_str(),
+            "https://example.com/nextcloud/remote.php/dav/uploads/a%20b/"
+        );
+        assert!(uploads("https://cloud.example.com/remote.php/dav/files/").is_none());
+        assert!(uploads("https://cloud.example.com/remote.php/webdav/teller").is_none());
+        assert!(uploads("https://nas.local/webdav/teller").is_none());
+    }
+
+    #[tokio::test]
+    async fn round_trip_with_ranges() {
+        let server = dav_server(Ranges::Honoured, None).await;
+
+        round_trip(&vault(format!("{}dav/vault", server.url)), 5000).await;
+    }
+
+    #[tokio::test]
+    async fn round_trip_without_ranges() {
+        let server = dav_server(Ranges::Ignored, None).await;
+
+        round_trip(&vault(format!("{}dav/vault", server.url)), 5000).await;
+    }
+
+    #[tokio::test]
+    async fn round_trip_with_nextcloud_chunking() {
+        let server = dav_server(Ranges::Rejected, None).await;
+
+        round_trip(
+            &vault(format!("{}remote.php/dav/files/alice/vault", server.url)),
+            5000,
+        )
+        .await;
+
+        assert!(puts(&server).iter().all(|request| request
+            .path
+            .starts_with("/remote.php/dav/uploads/alice/teller-")));
+    }
+
+    #[tokio::test]
+    async fn reads_ranges_of_whole_responses() {
+        let server = dav_server(Ranges::Ignored, None).await;
+        let vault = vault(format!("{}dav/vault", server.url));
+
+        let (source, data) = snapshot_source(1000).await;
+        vault
+            .upload_whole(&source, &vault.snapshot_url("world", "1700000000"))
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&source).await.unwrap();
+
+        for range in [0..10, 100..600, 990..1000, 995..1200] {
+            let read = vault
+                .read_snapshot_range("world", "1700000000", range.clone())
+                .await
+                .unwrap();
+            let end = range.end.min(1000) as usize;
+
+            assert_eq!(read, &data[range.start as usize..end]);
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_nextcloud_chunked_uploads() {
+        let server = dav_server(
+            Ranges::Rejected,
+            Some(Box::new(|request: &Request| {
+                request.method == "PUT" && request.path.ends_with("/3")
+            })),
+        )
+        .await;
+        let vault = vault(format!("{}remote.php/dav/files/alice/vault", server.url));
+
+        let (source, data) = snapshot_source(4500).await;
+
+        assert!(matches!(
+            vault.put_snapshot("world", "1700000000", &source).await,
+            Err(Error::Network(_))
+        ));
+        vault
+            .put_snapshot("world", "1700000000", &source)
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&source).await.unwrap();
+
+        let chunks: Vec<String> = puts(&server)
+            .iter()
+            .map(|request| request.path.rsplit('/').next().unwrap().to_string())
+            .collect();
+        assert_eq!(chunks, vec!["1", "2", "3", "3", "4", "5"]);
+
+        let upload_folders = server
+            .requests()
+            .iter()
+            .filter(|request| {
+                request.method == "MKCOL" && request.path.starts_with("/remote.php/dav/uploads/")
+            })
+            .count();
+        assert_eq!(upload_folders, 1);
+
+        assert!(read_snapshot(&vault, "world").await == data);
+        let leftover = vault.list(vault.uploads_url.clone().unwrap()).await;
+        assert!(leftover.unwrap_or_default().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_ranged_uploads() {
+        let server = dav_server(
+            Ranges::Honoured,
+            Some(Box::new(|request: &Request| {
+                request
+                    .header("Content-Range")
+                    .is_some_and(|range| range.starts_with("bytes 2048-"))
+            })),
+        )
+        .await;
+        let vault = vault(format!("{}dav/vault", server.url));
+
+        let (source, data) = snapshot_source(4500).await;
+
+        assert!(vault
+            .put_snapshot("world", "1700000000", &source)
+            .await
+            .is_err());
+        vault
+            .put_snapshot("world", "1700000000", &source)
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&source).await.unwrap();
+
+        let ranges: Vec<String> = puts(&server)
+            .iter()
+            .map(|request| request.header("Content-Range").unwrap().to_string())
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "bytes 0-1023/4500",
+                "bytes 1024-2047/4500",
+                "bytes 2048-3071/4500",
+                "bytes 2048-3071/4500",
+                "bytes 3072-4095/4500",
+                "bytes 4096-4499/4500",
+            ]
+        );
+
+        assert!(read_snapshot(&vault, "world").await == data);
+        assert_eq!(
+            vault.list_snapshots("world").await.unwrap(),
+            vec!["1700000000"]
+        );
+    }
+
+    #[tokio::test]
+    async fn uploads_at_once_without_ranged_puts() {
+        for ranges in [Ranges::Ignored, Ranges::Rejected] {
+            let server = dav_server(ranges, None).await;
+            let vault = vault(format!("{}dav/vault", server.url));
+
+            let (source, data) = snapshot_source(4500).await;
+
+            vault
+                .put_snapshot("world", "1700000000", &source)
+                .await
+                .unwrap();
+            tokio::fs::remove_file(&source).await.unwrap();
+
+            let last = puts(&server).pop().unwrap();
+            assert!(last.header("Content-Range").is_none());
+            assert_eq!(last.body.len(), 4500);
+
+            assert!(read_snapshot(&vault, "world").await == data);
+        }
+    }
+
+    /// Runs against the WebDAV folder `TELLER_TEST_WEBDAV_URL`, logging in
+    /// with `TELLER_TEST_WEBDAV_USER` and `TELLER_TEST_WEBDAV_PASSWORD` when
+    /// they are set.
+    #[tokio::test]
+    #[ignore]
+    async fn webdav_round_trip() {
+        let Ok(url) = std::env::var("TELLER_TEST_WEBDAV_URL") else {
+            return;
+        };
+
+        let vault = WebdavVault::new(&WebdavBackup {
+            url,
+            username: std::env::var("TELLER_TEST_WEBDAV_USER").ok(),
+            password: std::env::var("TELLER_TEST_WEBDAV_PASSWORD").ok(),
+        })
+        .unwrap();
+
+        round_trip(&vault, 64 * 1024).await;
+        round_trip(&vault, CHUNK_SIZE as usize * 2 + 64 * 1024).await;
+    }
+}
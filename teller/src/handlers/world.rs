@@ -9,9 +9,13 @@ use tokio::{fs, io::AsyncWriteExt};
 use crate::{
     handlers::{
         player::get_player_data,
+        progress::Progress,
         search::worlds::{get_world_path_by_id, is_minecraft_world},
     },
-    types::world::{GameRules, WorldData, WorldLevelData},
+    types::{
+        error::Error,
+        world::{GameRules, WorldData, WorldLevelData},
+    },
     utils::{calculate_dir_size, encode_image_to_base64},
 };
 
@@ -22,20 +26,23 @@ pub enum GameType {
     None,
 }
 
-pub async fn create_vault_file(vault_data: Value, world_path: &PathBuf) -> Result<(), String> {
+pub async fn create_vault_file(vault_data: Value, world_path: &PathBuf) -> Result<(), Error> {
     info!("Creating vault file for: {:?}", world_path);
 
     let vault_file_path = world_path.join(".chunkvault");
 
     if vault_file_path.exists() {
-        return Err("Vault file already exists".to_string());
+        return Err(Error::World("Vault file already exists".to_string()));
     }
 
     let mut vault_file = match fs::File::create(&vault_file_path).await {
         Ok(file) => file,
         Err(e) => {
             error!("Failed to create vault file: {e:?} {vault_file_path:?}");
-            return Err(format!("Failed to create vault file: {:?}", e));
+            return Err(Error::World(format!(
+                "Failed to create vault file: {:?}",
+                e
+            )));
         }
     };
 
@@ -45,48 +52,51 @@ pub async fn create_vault_file(vault_data: Value, world_path: &PathBuf) -> Resul
     {
         Ok(_) => {}
         Err(e) => {
-            return Err(format!("Failed to write vault file: {:?}", e));
+            return Err(Error::World(format!("Failed to write vault file: {:?}", e)));
         }
     }
 
     Ok(())
 }
 
-pub async fn get_vault_file(world_path: &PathBuf) -> Result<Value, String> {
+pub async fn get_vault_file(world_path: &PathBuf) -> Result<Value, Error> {
     let vault_file_path = world_path.join(".chunkvault");
 
     if !vault_file_path.exists() {
-        return Err("Vault file does not exist".to_string());
+        return Err(Error::World("Vault file does not exist".to_string()));
     }
 
     let vault_file = match fs::File::open(&vault_file_path).await {
         Ok(file) => file,
         Err(e) => {
-            return Err(format!("Failed to open vault file: {e:?}"));
+            return Err(Error::World(format!("Failed to open vault file: {e:?}")));
         }
     };
 
     let vault_data: Value = match serde_json::from_reader(vault_file.into_std().await) {
         Ok(data) => data,
         Err(e) => {
-            return Err(format!("Failed to read vault file: {:?}", e));
+            return Err(Error::World(format!("Failed to read vault file: {:?}", e)));
         }
     };
 
     Ok(vault_data)
 }
 
-pub async fn update_vault_file(vault_data: Value, world_path: &PathBuf) -> Result<(), String> {
+pub async fn update_vault_file(vault_data: Value, world_path: &PathBuf) -> Result<(), Error> {
     let vault_file_path = world_path.join(".chunkvault");
 
     if !vault_file_path.exists() {
-        return Err("Vault file does not exist".to_string());
+        return Err(Error::World("Vault file does not exist".to_string()));
     }
 
     let mut vault_file = match fs::File::create(&vault_file_path).await {
         Ok(file) => file,
         Err(e) => {
-            return Err(format!("Failed to create vault file: {:?}", e));
+            return Err(Error::World(format!(
+                "Failed to create vault file: {:?}",
+                e
+            )));
         }
     };
 
@@ -96,14 +106,14 @@ pub async fn update_vault_file(vault_data: Value, world_path: &PathBuf) -> Resul
     {
         Ok(_) => {}
         Err(e) => {
-            return Err(format!("Failed to write vault file: {:?}", e));
+            return Err(Error::World(format!("Failed to write vault file: {:?}", e)));
         }
     }
 
     Ok(())
 }
 
-pub async fn get_vault_id(path: &PathBuf) -> Result<String, String> {
+pub async fn get_vault_id(path: &PathBuf) -> Result<String, Error> {
     let vault_data = match get_vault_file(path).await {
         Ok(data) => data,
         Err(_) => {
@@ -142,7 +152,7 @@ pub async fn get_vault_id(path: &PathBuf) -> Result<String, String> {
     Ok(vault_id.to_string())
 }
 
-pub async fn new_vault_id(world_path: &PathBuf) -> Result<(), String> {
+pub async fn new_vault_id(world_path: &PathBuf) -> Result<(), Error> {
     let mut vault_info = get_vault_file(world_path).await?;
     if let Some(id_pointer) = vault_info.pointer_mut("/id") {
         *id_pointer = serde_json::Value::String(uuid::Uuid::new_v4().to_string());
@@ -154,7 +164,7 @@ pub async fn new_vault_id(world_path: &PathBuf) -> Result<(), String> {
 
 // Data parsing & processing
 
-pub fn read_dat_file(file_path: PathBuf, game_type: GameType) -> Result<NbtValue, String> {
+pub fn read_dat_file(file_path: PathBuf, game_type: GameType) -> Result<NbtValue, Error> {
     info!("Parsing {:?} dat file: {:?}", game_type, file_path);
 
     match game_type {
@@ -162,7 +172,7 @@ pub fn read_dat_file(file_path: PathBuf, game_type: GameType) -> Result<NbtValue
             let (_, dat_blob) =
                 match read_from_file(file_path, Compression::Gzip, Endian::Big, false) {
                     Ok(data) => data,
-                    Err(e) => return Err(format!("Failed to read level.dat: {e:?}")),
+                    Err(e) => return Err(Error::Nbt(format!("Failed to read level.dat: {e:?}"))),
                 };
             Ok(dat_blob)
         }
@@ -170,15 +180,15 @@ pub fn read_dat_file(file_path: PathBuf, game_type: GameType) -> Result<NbtValue
             let (_, dat_blob) =
                 match read_from_file(file_path, Compression::Uncompressed, Endian::Little, true) {
                     Ok(data) => data,
-                    Err(e) => return Err(format!("Failed to read level.dat: {e:?}")),
+                    Err(e) => return Err(Error::Nbt(format!("Failed to read level.dat: {e:?}"))),
                 };
             Ok(dat_blob)
         }
-        GameType::None => Err("Game type not specified".into()),
+        GameType::None => Err(Error::World("Game type not specified".to_string())),
     }
 }
 
-pub fn get_world_data(world_path: &PathBuf) -> Result<Value, String> {
+pub fn get_world_data(world_path: &PathBuf) -> Result<Value, Error> {
     info!("Getting world data for {:?}", world_path);
 
     let game_type = is_minecraft_world(world_path);
@@ -186,12 +196,12 @@ pub fn get_world_data(world_path: &PathBuf) -> Result<Value, String> {
     let level_dat_path = world_path.join("level.dat");
 
     if !level_dat_path.exists() {
-        return Err("level.dat does not exist".to_string());
+        return Err(Error::Nbt("level.dat does not exist".to_string()));
     }
 
     let level_data = match read_dat_file(level_dat_path, game_type) {
         Ok(data) => data,
-        Err(e) => return Err(format!("Failed to parse level.dat: {:?}", e)),
+        Err(e) => return Err(Error::Nbt(format!("Failed to parse level.dat: {}", e))),
     };
 
     let level_data = match parse_world_data(level_data, game_type) {
@@ -202,10 +212,15 @@ pub fn get_world_data(world_path: &PathBuf) -> Result<Value, String> {
     Ok(level_data)
 }
 
-pub fn parse_world_data(world_data: NbtValue, game_type: GameType) -> Result<Value, String> {
+pub fn parse_world_data(world_data: NbtValue, game_type: GameType) -> Result<Value, Error> {
     let level_value: serde_json::Value = match serde_json::to_value(world_data) {
         Ok(value) => value,
-        Err(e) => return Err(format!("Failed to parse level.dat JSON: {:?}", e)),
+        Err(e) => {
+            return Err(Error::Nbt(format!(
+                "Failed to parse level.dat JSON: {:?}",
+                e
+            )))
+        }
     };
 
     info!("Parsing world data for {:?}", game_type);
@@ -215,24 +230,24 @@ pub fn parse_world_data(world_data: NbtValue, game_type: GameType) -> Result<Val
         GameType::Java => {
             let level_data = match level_value.get("Data") {
                 Some(data) => data,
-                None => return Err("Could not find Data in level.dat".into()),
+                None => return Err(Error::Nbt("Could not find Data in level.dat".to_string())),
             };
             Ok(level_data.to_owned())
         }
-        GameType::None => Err("Game type not specified".into()),
+        GameType::None => Err(Error::World("Game type not specified".to_string())),
     }
 }
 
 pub async fn process_world_data(
     path: &PathBuf,
     game_type: GameType,
-) -> Result<WorldLevelData, String> {
+) -> Result<WorldLevelData, Error> {
     info!("Processing world data for: {:?}", path);
 
-    let level_dat = read_dat_file(path.join("level.dat"), game_type).map_err(|e| e.to_string())?;
+    let level_dat = read_dat_file(path.join("level.dat"), game_type)?;
 
     let level_value: serde_json::Value =
-        serde_json::to_value(level_dat).map_err(|e| e.to_string())?;
+        serde_json::to_value(level_dat).map_err(|e| Error::Nbt(e.to_string()))?;
 
     match game_type {
         GameType::Bedrock => {
@@ -242,7 +257,7 @@ pub async fn process_world_data(
                     .as_str()
                     .unwrap_or_default()
                     .to_string(),
-                folder: Some(path.to_str().unwrap().to_string()),
+                folder: Some(path.to_string_lossy().to_string()),
                 icon: match encode_image_to_base64(path.clone().join("world_icon.jpeg")).await {
                     Ok(data) => Some(data),
                     Err(_) => None,
@@ -272,14 +287,10 @@ pub async fn process_world_data(
 
                     chrono::NaiveDateTime::from_timestamp_opt(last_played, 0)
                 },
-                players: get_player_data(path, game_type)
-                    .await
-                    .map_err(|e| e.to_string())?,
+                players: get_player_data(path, game_type).await?,
                 size_on_disk: {
                     info!("Calculating directory size for: {:?}", path);
-                    calculate_dir_size(path.clone().to_owned())
-                        .await
-                        .map_err(|e| e.to_string())? as i64
+                    calculate_dir_size(path.clone().to_owned(), &Progress::default()).await? as i64
                 },
                 game_rules: match parse_game_rules(&level_value, game_type) {
                     Ok(rules) => Some(rules),
@@ -292,7 +303,7 @@ pub async fn process_world_data(
         GameType::Java => {
             let level_data = match level_value.get("Data") {
                 Some(data) => data,
-                None => return Err("Could not find Data in level.dat".into()),
+                None => return Err(Error::Nbt("Could not find Data in level.dat".to_string())),
             };
 
             let world_level_data = WorldLevelData {
@@ -301,7 +312,7 @@ pub async fn process_world_data(
                     .as_str()
                     .unwrap_or_default()
                     .to_string(),
-                folder: Some(path.to_str().unwrap().to_string()),
+                folder: Some(path.to_string_lossy().to_string()),
                 icon: match encode_image_to_base64(path.clone().join("icon.png")).await {
                     Ok(data) => Some(data),
                     Err(_) => None,
@@ -337,14 +348,10 @@ pub async fn process_world_data(
 
                     chrono::NaiveDateTime::from_timestamp_millis(last_played)
                 },
-                players: get_player_data(path, game_type)
-                    .await
-                    .map_err(|e| e.to_string())?,
+                players: get_player_data(path, game_type).await?,
                 size_on_disk: {
                     info!("Calculating directory size for: {:?}", path);
-                    calculate_dir_size(path.clone().to_owned())
-                        .await
-                        .map_err(|e| e.to_string())? as i64
+                    calculate_dir_size(path.clone().to_owned(), &Progress::default()).await? as i64
                 },
                 game_rules: match parse_game_rules(level_data, game_type) {
                     Ok(rules) => Some(rules),
@@ -354,14 +361,14 @@ pub async fn process_world_data(
 
             Ok(world_level_data)
         }
-        GameType::None => Err("Game type not specified".into()),
+        GameType::None => Err(Error::World("Game type not specified".to_string())),
     }
 }
 
 pub fn parse_game_rules(
     game_data: &serde_json::Value,
     game_type: GameType,
-) -> Result<GameRules, String> {
+) -> Result<GameRules, Error> {
     match game_type {
         GameType::Java => {
             let game_rules = match game_data.get("GameRules") {
@@ -480,7 +487,11 @@ pub fn parse_game_rules(
                         .unwrap_or("false")
                         == "true",
                 },
-                None => return Err("Could not find GameRules in level.dat".into()),
+                None => {
+                    return Err(Error::Nbt(
+                        "Could not find GameRules in level.dat".to_string(),
+                    ))
+                }
             };
             Ok(game_rules)
         }
@@ -602,7 +613,7 @@ pub fn parse_game_rules(
             };
             Ok(game_rules)
         }
-        GameType::None => Err("Game type not specified".into()),
+        GameType::None => Err(Error::World("Game type not specified".to_string())),
     }
 }
 
@@ -656,7 +667,7 @@ pub fn get_level_info(
     }
 }
 
-pub async fn parse_world_entry_data(path: PathBuf) -> Result<WorldData, String> {
+pub async fn parse_world_entry_data(path: PathBuf) -> Result<WorldData, Error> {
     let game_type = is_minecraft_world(&path);
 
     let level_dat_path = path.join("level.dat");
@@ -664,7 +675,10 @@ pub async fn parse_world_entry_data(path: PathBuf) -> Result<WorldData, String>
         Ok(blob) => blob,
         Err(e) => {
             error!("Could not parse level.dat at {:?}: {:?}", path, e);
-            return Err(format!("Could not parse level.dat at {:?}: {:?}", path, e));
+            return Err(Error::Nbt(format!(
+                "Could not parse level.dat at {:?}: {}",
+                path, e
+            )));
         }
     };
 
@@ -672,17 +686,23 @@ pub async fn parse_world_entry_data(path: PathBuf) -> Result<WorldData, String>
         Ok((name, time)) => (name, time),
         Err(e) => {
             error!("Could not get level name at {:?}: {:?}", path, e);
-            return Err(format!("Could not get level name at {:?}: {:?}", path, e));
+            return Err(Error::Nbt(format!(
+                "Could not get level name at {:?}: {:?}",
+                path, e
+            )));
         }
     };
 
-    let world_size = (calculate_dir_size(path.clone()).await).unwrap_or(0);
+    let world_size = (calculate_dir_size(path.clone(), &Progress::default()).await).unwrap_or(0);
 
     let vault_id = match get_vault_id(&path).await {
         Ok(id) => id,
         Err(e) => {
             error!("Could not get vault id at {:?}: {:?}", path, e);
-            return Err(format!("Could not get vault id at {:?}: {:?}", path, e));
+            return Err(Error::World(format!(
+                "Could not get vault id at {:?}: {}",
+                path, e
+            )));
         }
     };
 
@@ -715,18 +735,18 @@ pub async fn delete_world_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let world_path = get_world_path_by_id(world_id, category, instance).await?;
 
     if !world_path.exists() {
         error!("World does not exist: {:?}", world_path);
-        return Err("World does not exist".into());
+        return Err(Error::WorldNotFound("World does not exist".to_string()));
     }
 
     info!("Deleting world at {:?}", world_path);
 
     if let Err(e) = fs::remove_dir_all(world_path).await {
-        return Err(format!("Failed to delete world: {:?}", e));
+        return Err(Error::World(format!("Failed to delete world: {:?}", e)));
     }
 
     Ok(())
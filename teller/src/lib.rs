@@ -1,3 +1,5 @@
 pub mod handlers;
 pub mod types;
 pub mod utils;
+
+pub use types::error::Error;
@@ -11,6 +11,218 @@ pub struct RemoteBackup {
     pub api_key: String,
 }
 
+/// A vault in an S3 bucket or an S3-compatible object store such as MinIO.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct S3Backup {
+    pub bucket: String,
+    /// Key prefix the vault lives under, for sharing a bucket.
+    #[serde(default)]
+    pub prefix: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible store, AWS when not set.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    /// Addresses the bucket in the path instead of the host name, which most
+    /// self-hosted stores require.
+    #[serde(default)]
+    pub path_style: bool,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// Caps on the rate snapshots are uploaded to vaults that are not on disk, in
+/// bytes per second. Uploads are not limited when a cap is not set.
+#[derive(Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct BandwidthSettings {
+    #[serde(default)]
+    pub upload_limit: Option<u64>,
+    /// Used instead of `upload_limit` while Minecraft has a world open.
+    #[serde(default)]
+    pub upload_limit_while_playing: Option<u64>,
+}
+
+/// A vault in a folder on a server reachable over SSH.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct SftpBackup {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    pub user: String,
+    /// Private key used to log in.
+    pub key_path: PathBuf,
+    /// Folder on the server the vault lives in.
+    pub remote_dir: String,
+    /// File the server's host key is checked against, the user's
+    /// `~/.ssh/known_hosts` when not set.
+    #[serde(default)]
+    pub known_hosts: Option<PathBuf>,
+}
+
+/// A vault in a folder on a WebDAV server, such as a Nextcloud or NAS share.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct WebdavBackup {
+    /// Url of the folder the vault lives in.
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VaultStorage {
+    /// Every snapshot is a self-contained archive of the whole world.
+    #[default]
+    Full,
+    /// File contents are stored once in the vault's object store and
+    /// snapshots only hold a manifest pointing at them. Region files of Java
+    /// worlds are split so that unchanged chunks are shared between snapshots.
+    Deduplicated,
+}
+
+/// Encryption of snapshot payloads with XChaCha20-Poly1305, keyed from a
+/// passphrase with Argon2id. The passphrase itself is never stored.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VaultEncryption {
+    pub salt: String,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+    /// A known value sealed with the vault key, used to tell a wrong
+    /// passphrase apart from a corrupted snapshot.
+    pub key_check: String,
+    /// Keep a readable copy of the snapshot metadata so the vault can be
+    /// listed without unlocking it.
+    #[serde(default)]
+    pub plaintext_metadata: bool,
+}
+
+/// Which snapshots of a world to keep when a vault is pruned. A snapshot is
+/// kept if any rule keeps it, and the newest snapshot is always kept. The
+/// `keep_hourly` to `keep_yearly` rules keep the newest snapshot of each
+/// hour, day, week, month or year, going back that many periods from now.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    #[serde(default)]
+    pub keep_last: Option<usize>,
+    #[serde(default)]
+    pub keep_hourly: Option<u32>,
+    #[serde(default)]
+    pub keep_daily: Option<u32>,
+    #[serde(default)]
+    pub keep_weekly: Option<u32>,
+    #[serde(default)]
+    pub keep_monthly: Option<u32>,
+    #[serde(default)]
+    pub keep_yearly: Option<u32>,
+    /// Upper bound in bytes for the snapshots of each world on its own, not
+    /// for the vault as a whole. The oldest kept snapshots of the world are
+    /// removed until it fits. The size is logical: in deduplicated vaults a
+    /// snapshot counts with the full size of the world it holds, however few
+    /// objects it adds to the vault.
+    #[serde(default, alias = "max_total_size")]
+    pub max_world_size: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct VaultSettings {
+    #[serde(default)]
+    pub storage: VaultStorage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<VaultEncryption>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+    /// Retention policies for single worlds, by world id, overriding the
+    /// vault's policy.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub world_retention: HashMap<String, RetentionPolicy>,
+}
+
+/// What the scheduler does with runs that were missed while the computer was
+/// asleep or teller was not running.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpPolicy {
+    /// Run a single backup as soon as possible, however many runs were missed.
+    #[default]
+    RunOnce,
+    /// Wait for the next scheduled run.
+    Skip,
+}
+
+/// What a backup does when the game has the world open. Worlds were always
+/// backed up regardless before, so that stays the default.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum InUsePolicy {
+    /// Fail the backup.
+    Refuse,
+    /// Wait for the world to be closed, up to a limit.
+    Wait,
+    /// Back up anyway and flag the snapshot as best-effort.
+    #[default]
+    BestEffort,
+}
+
+/// A world that scheduled backups are taken of, with the same lookup
+/// arguments as `create_backup_from_id`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ScheduledWorld {
+    pub world_id: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub instance: Option<String>,
+}
+
+/// Watch mode backs up worlds in the configured save folders once their
+/// save data has stopped changing for `quiet_period` seconds.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct WatcherSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_quiet_period")]
+    pub quiet_period: u64,
+}
+
+fn default_quiet_period() -> u64 {
+    5 * 60
+}
+
+impl Default for WatcherSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            quiet_period: default_quiet_period(),
+        }
+    }
+}
+
+/// Backup settings for a single world, keyed by its `.chunkvault` id. A
+/// world with a profile is scheduled by it instead of by the global schedule,
+/// whether or not `auto_backup` is on.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct BackupProfile {
+    #[serde(default)]
+    pub enabled: bool,
+    pub schedule: String,
+    /// Vaults the world is backed up to, `default_vaults` if not set.
+    #[serde(default)]
+    pub vaults: Option<Vec<String>>,
+    /// Applied to the world in its vaults after every scheduled backup.
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub instance: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct BackupSettings {
     pub schedule: String,
@@ -18,6 +230,64 @@ pub struct BackupSettings {
     pub default_vaults: Option<Vec<String>>,
     pub vaults: HashMap<String, PathBuf>,
     pub remote_vaults: HashMap<String, RemoteBackup>,
+    #[serde(default)]
+    pub s3_vaults: HashMap<String, S3Backup>,
+    #[serde(default)]
+    pub sftp_vaults: HashMap<String, SftpBackup>,
+    #[serde(default)]
+    pub webdav_vaults: HashMap<String, WebdavBackup>,
+    #[serde(default)]
+    pub vault_settings: HashMap<String, VaultSettings>,
+    #[serde(default)]
+    pub scheduled_worlds: Vec<ScheduledWorld>,
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    #[serde(default)]
+    pub profiles: HashMap<String, BackupProfile>,
+    #[serde(default)]
+    pub watcher: WatcherSettings,
+    #[serde(default)]
+    pub in_use: InUsePolicy,
+    #[serde(default)]
+    pub bandwidth: BandwidthSettings,
+}
+
+impl BackupSettings {
+    pub fn has_vault(&self, vault_id: &str) -> bool {
+        self.vaults.contains_key(vault_id)
+            || self.s3_vaults.contains_key(vault_id)
+            || self.sftp_vaults.contains_key(vault_id)
+            || self.webdav_vaults.contains_key(vault_id)
+            || self.remote_vaults.contains_key(vault_id)
+    }
+
+    pub fn vault_storage(&self, vault_id: &str) -> VaultStorage {
+        self.vault_settings
+            .get(vault_id)
+            .map(|settings| settings.storage)
+            .unwrap_or_default()
+    }
+
+    pub fn vault_encryption(&self, vault_id: &str) -> Option<&VaultEncryption> {
+        self.vault_settings
+            .get(vault_id)
+            .and_then(|settings| settings.encryption.as_ref())
+    }
+
+    /// The most specific retention policy for a world in a vault: the vault's
+    /// policy for the world, then the world's profile, then the vault's own.
+    pub fn retention_policy(&self, vault_id: &str, world_id: &str) -> Option<&RetentionPolicy> {
+        let settings = self.vault_settings.get(vault_id);
+        let profile = self
+            .profiles
+            .get(world_id)
+            .and_then(|profile| profile.retention.as_ref());
+
+        settings
+            .and_then(|settings| settings.world_retention.get(world_id))
+            .or(profile)
+            .or(settings.and_then(|settings| settings.retention.as_ref()))
+    }
 }
 
 impl Default for BackupSettings {
@@ -28,6 +298,16 @@ impl Default for BackupSettings {
             default_vaults: Some(Vec::new()),
             vaults: HashMap::new(),
             remote_vaults: HashMap::new(),
+            s3_vaults: HashMap::new(),
+            sftp_vaults: HashMap::new(),
+            webdav_vaults: HashMap::new(),
+            vault_settings: HashMap::new(),
+            scheduled_worlds: Vec::new(),
+            catch_up: CatchUpPolicy::default(),
+            profiles: HashMap::new(),
+            watcher: WatcherSettings::default(),
+            in_use: InUsePolicy::default(),
+            bandwidth: BandwidthSettings::default(),
         }
     }
 }
@@ -36,6 +316,20 @@ impl Default for BackupSettings {
 pub struct BackupMetadata {
     pub entry: WorldData,
     pub data: WorldLevelData,
+    /// Snapshot id this snapshot was taken incrementally against, if any.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Layout version of the snapshot archive, 1 for snapshots written
+    /// before it was recorded.
+    #[serde(default = "legacy_format_version")]
+    pub format_version: u32,
+    /// Taken while the game had the world open, so region files may be torn.
+    #[serde(default)]
+    pub best_effort: bool,
+}
+
+fn legacy_format_version() -> u32 {
+    1
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -44,3 +338,192 @@ pub struct SnapshotInfo {
     pub size: u64,
     pub path: PathBuf,
 }
+
+/// Size and modification time of a snapshot as stored by a vault backend.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SnapshotStat {
+    pub size: u64,
+    pub modified: Option<i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RegionChunk {
+    pub index: usize,
+    pub offset: u32,
+    pub sectors: u8,
+    pub timestamp: u32,
+    pub hash: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+    /// Set for Anvil region files stored chunk by chunk, in which case `hash`
+    /// is empty and the file is rebuilt from its chunks on restore.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<Vec<RegionChunk>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SnapshotManifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+impl SnapshotManifest {
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct FileIndexEntry {
+    pub path: String,
+    pub size: u64,
+    pub modified: i64,
+    /// SHA-256 of the file contents, empty for snapshots taken before
+    /// integrity manifests were recorded.
+    #[serde(default)]
+    pub hash: String,
+}
+
+/// Every file in the world at the time a snapshot was taken, including files
+/// an incremental snapshot did not store because they were unchanged.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct FileIndex {
+    pub files: Vec<FileIndexEntry>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub world_id: String,
+    pub snapshot_id: String,
+    pub checked: usize,
+    pub missing: Vec<String>,
+    pub corrupted: Vec<String>,
+    pub extra: Vec<String>,
+    /// The snapshot has no integrity manifest, so only the zip checksums of
+    /// its entries could be checked.
+    pub checksums_only: bool,
+}
+
+impl VerifyReport {
+    pub fn is_healthy(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty() && self.extra.is_empty()
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct VaultVerifyReport {
+    pub snapshots: Vec<VerifyReport>,
+    /// Files in the vault that are not snapshots, and objects in the object
+    /// store that no snapshot references.
+    pub extra: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PrunedSnapshot {
+    pub world_id: String,
+    pub snapshot_id: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SyncedSnapshot {
+    pub world_id: String,
+    pub snapshot_id: String,
+    pub size: u64,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct SyncFailure {
+    pub world_id: String,
+    /// Not set when the snapshots of the world could not be listed.
+    pub snapshot_id: Option<String>,
+    pub error: String,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SyncReport {
+    /// Snapshots copied to the destination, whose size and hash were checked
+    /// after the copy.
+    pub copied: Vec<SyncedSnapshot>,
+    /// Snapshots removed from the destination because the source does not
+    /// have them, when deletions are mirrored.
+    pub deleted: Vec<SyncedSnapshot>,
+    /// Snapshots the destination already had.
+    pub unchanged: usize,
+    pub failed: Vec<SyncFailure>,
+    pub cancelled: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct UploadedPart {
+    pub number: i32,
+    pub etag: Option<String>,
+}
+
+/// An upload that was interrupted, kept so the next attempt to upload the
+/// same file can continue after the last part the destination confirmed.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct UploadState {
+    pub destination: String,
+    pub source_size: u64,
+    pub source_modified: Option<i64>,
+    /// Id the destination gave the upload, such as an S3 multipart upload id.
+    #[serde(default)]
+    pub upload_id: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<UploadedPart>,
+}
+
+/// A snapshot as recorded in a vault's catalog.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct CatalogSnapshot {
+    pub snapshot_id: String,
+    pub created: i64,
+    /// Size of the snapshot, or of the world it holds for deduplicated
+    /// storage.
+    pub size: u64,
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub best_effort: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct CatalogWorld {
+    /// Entry of the newest snapshot whose metadata could be read.
+    pub entry: Option<WorldData>,
+    pub snapshots: Vec<CatalogSnapshot>,
+}
+
+/// Index of what a vault holds, so listing backups does not have to scan
+/// the vault. Kept up to date as snapshots are stored and removed, and
+/// rebuilt from the vault when it has drifted.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct VaultCatalog {
+    pub vault_id: String,
+    /// When the catalog was last rebuilt from the vault.
+    pub indexed: i64,
+    pub worlds: HashMap<String, CatalogWorld>,
+}
+
+/// What startup recovery removed from the vaults.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct RecoveryReport {
+    /// Temp and partial files left behind by writes that never finished.
+    pub temp_files: Vec<PathBuf>,
+    /// Snapshots that were cut off before they were completely written.
+    pub snapshots: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct PruneReport {
+    pub dry_run: bool,
+    /// Snapshots that were deleted, or would be on a dry run.
+    pub deleted: Vec<PrunedSnapshot>,
+    pub kept: usize,
+    pub freed: u64,
+}
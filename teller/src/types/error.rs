@@ -1,7 +1,93 @@
+use std::fmt::Display;
+
+use async_zip::error::ZipError;
+use serde::ser::SerializeStruct;
+
+/// Errors returned by the teller handlers. The message is meant for people,
+/// while `code` is stable and meant for callers to match on.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    /// Settings could not be read, written or are invalid.
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    VaultNotFound(String),
+    /// The vault is encrypted and has not been unlocked.
+    #[error("{0}")]
+    VaultLocked(String),
+    #[error("{0}")]
+    Vault(String),
+    #[error("{0}")]
+    SnapshotNotFound(String),
+    /// A snapshot archive is corrupt, incomplete or of an unsupported version.
+    #[error("{0}")]
+    SnapshotFormat(String),
+    #[error("{0}")]
+    Encryption(String),
+    /// A `level.dat` or player file could not be parsed.
+    #[error("{0}")]
+    Nbt(String),
+    #[error("{0}")]
+    WorldNotFound(String),
+    /// The game has the world open.
+    #[error("{0}")]
+    WorldInUse(String),
+    #[error("{0}")]
+    World(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Network(String),
+    /// No job in the job queue has the id.
+    #[error("{0}")]
+    JobNotFound(String),
+    #[error("Operation was cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::VaultNotFound(_) => "vault_not_found",
+            Error::VaultLocked(_) => "vault_locked",
+            Error::Vault(_) => "vault",
+            Error::SnapshotNotFound(_) => "snapshot_not_found",
+            Error::SnapshotFormat(_) => "snapshot_format",
+            Error::Encryption(_) => "encryption",
+            Error::Nbt(_) => "nbt",
+            Error::WorldNotFound(_) => "world_not_found",
+            Error::WorldInUse(_) => "world_in_use",
+            Error::World(_) => "world",
+            Error::Io(_) => "io",
+            Error::Network(_) => "network",
+            Error::JobNotFound(_) => "job_not_found",
+            Error::Cancelled => "cancelled",
+        }
+    }
+
+    /// An IO error with what was being done when it happened.
+    pub(crate) fn io(context: impl Display, error: std::io::Error) -> Self {
+        Error::Io(std::io::Error::new(
+            error.kind(),
+            format!("{}: {}", context, error),
+        ))
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(error: ZipError) -> Self {
+        match error {
+            ZipError::UpstreamReadError(error) => Error::Io(error),
+            error => Error::SnapshotFormat(error.to_string()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        Error::Network(error.to_string())
+    }
 }
 
 impl serde::Serialize for Error {
@@ -9,6 +95,9 @@ impl serde::Serialize for Error {
     where
         S: serde::ser::Serializer,
     {
-        serializer.serialize_str(self.to_string().as_ref())
+        let mut error = serializer.serialize_struct("Error", 2)?;
+        error.serialize_field("code", self.code())?;
+        error.serialize_field("message", &self.to_string())?;
+        error.end()
     }
 }
@@ -2,4 +2,7 @@ pub mod backup;
 pub mod config;
 pub mod error;
 pub mod player;
+pub mod progress;
+pub mod queue;
+pub mod scheduler;
 pub mod world;
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressStage {
+    /// Adding up the size of a folder.
+    Measuring,
+    /// Packing world files into a snapshot.
+    Archiving,
+    /// Copying a finished snapshot into vaults.
+    Copying,
+    /// Restoring world files from a snapshot.
+    Extracting,
+}
+
+/// Progress of a long-running operation. `done` and `total` are in bytes, and
+/// `total` is 0 while it is not known yet.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct ProgressEvent {
+    pub stage: ProgressStage,
+    pub done: u64,
+    pub total: u64,
+}
@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QueuedTask {
+    /// Uploads a snapshot staged in the queue folder to a vault that is not
+    /// on disk. The staged file is already in the form the vault stores.
+    Upload {
+        world_id: String,
+        snapshot_id: String,
+        vault_id: String,
+        source: PathBuf,
+    },
+    /// Brings one vault up to date with another, see `sync_vaults`.
+    Replicate {
+        source: String,
+        destination: String,
+        #[serde(default)]
+        worlds: Option<Vec<String>>,
+        #[serde(default)]
+        mirror: bool,
+    },
+    /// Applies the retention policies of a vault, to one world or all of them.
+    Prune {
+        vault_id: String,
+        #[serde(default)]
+        world_id: Option<String>,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Active,
+    /// Ran out of attempts, and waits for the user to retry or cancel it.
+    Failed,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct QueuedJob {
+    pub id: String,
+    pub task: QueuedTask,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub created: i64,
+    /// Unix time the job is due to run, later after every failed attempt.
+    pub next_attempt: i64,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct JobQueue {
+    pub jobs: Vec<QueuedJob>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum QueueEvent {
+    JobStarted {
+        job_id: String,
+    },
+    JobFinished {
+        job_id: String,
+        error: Option<String>,
+    },
+}
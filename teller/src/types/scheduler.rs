@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub struct SchedulerState {
+    /// Unix time of the last run of each schedule that was handled, whether
+    /// it ran or was skipped. The global schedule is stored under `global`
+    /// and profiles under their world id.
+    #[serde(default)]
+    pub last_runs: HashMap<String, i64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SchedulerEvent {
+    BackupStarted {
+        world_id: String,
+        scheduled_for: i64,
+    },
+    BackupFinished {
+        world_id: String,
+        scheduled_for: i64,
+        error: Option<String>,
+    },
+}
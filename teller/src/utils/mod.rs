@@ -4,6 +4,10 @@ use async_recursion::async_recursion;
 use base64::{engine::general_purpose, Engine as _};
 use tokio::{fs, io::AsyncReadExt};
 
+use crate::handlers::progress::Progress;
+use crate::types::error::Error;
+use crate::types::progress::ProgressStage;
+
 pub async fn encode_image_to_base64(path: PathBuf) -> Result<String, std::io::Error> {
     let mut file = fs::File::open(path).await?;
     let mut buf = Vec::new();
@@ -12,19 +16,30 @@ pub async fn encode_image_to_base64(path: PathBuf) -> Result<String, std::io::Er
     Ok(format!("data:image/png;base64,{}", res_base64))
 }
 
+/// Adds up the size of every file in a folder, reporting the running total as
+/// the `Measuring` stage of `progress`.
+pub async fn calculate_dir_size(path: PathBuf, progress: &Progress) -> Result<u64, Error> {
+    progress.start(ProgressStage::Measuring, 0);
+
+    add_dir_size(path, progress).await
+}
+
 #[async_recursion]
-pub async fn calculate_dir_size(path: PathBuf) -> Result<u64, std::io::Error> {
+async fn add_dir_size(path: PathBuf, progress: &Progress) -> Result<u64, Error> {
     let mut size = 0;
 
     let mut main_dir = fs::read_dir(&path).await?;
 
     while let Some(dir) = main_dir.next_entry().await? {
+        progress.check_cancelled()?;
+
         let metadata = dir.metadata().await?;
 
         if metadata.is_dir() {
-            size += calculate_dir_size(dir.path()).await?;
+            size += add_dir_size(dir.path(), progress).await?;
         } else {
             size += metadata.len();
+            progress.advance(metadata.len());
         }
     }
 
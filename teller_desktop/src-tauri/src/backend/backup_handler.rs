@@ -1,13 +1,22 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
 use tauri::{
     plugin::{Builder, TauriPlugin},
-    Manager, Wry,
+    AppHandle, Manager, Wry,
 };
+use teller::handlers::progress::Progress;
 use teller::types::{
-    backup::{BackupMetadata, SnapshotInfo},
+    backup::{
+        BackupMetadata, InUsePolicy, PruneReport, SnapshotInfo, SyncReport, VaultCatalog,
+        VaultVerifyReport, VerifyReport,
+    },
+    queue::{QueuedJob, QueuedTask},
     world::WorldData,
 };
+use teller::Error;
 
-use crate::types::events::ToastEvent;
+use crate::types::events::{JobProgressEvent, ToastEvent};
 
 pub fn init() -> TauriPlugin<Wry> {
     Builder::new("backup_handler")
@@ -19,11 +28,76 @@ pub fn init() -> TauriPlugin<Wry> {
             grab_backup_metadata,
             delete_backup_from_id,
             delete_world_backups,
-            restore_snapshot_to_world
+            restore_snapshot_to_world,
+            verify_backup,
+            verify_vault,
+            prune_vault,
+            reindex_vault,
+            sync_vaults,
+            queue_vault_sync,
+            list_queued_jobs,
+            retry_queued_job,
+            cancel_queued_job,
+            cancel_job
         ])
         .build()
 }
 
+/// Jobs the frontend started with a job id, so it can cancel them.
+static RUNNING_JOBS: OnceLock<Mutex<HashMap<String, Progress>>> = OnceLock::new();
+
+fn running_jobs() -> &'static Mutex<HashMap<String, Progress>> {
+    RUNNING_JOBS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Registers a job and forwards its progress as `job_progress` events. Jobs
+/// started without an id report nowhere and cannot be cancelled.
+fn start_job(app: &AppHandle, job_id: &Option<String>) -> Progress {
+    let job_id = match job_id {
+        Some(job_id) => job_id.clone(),
+        None => return Progress::default(),
+    };
+
+    let app = app.clone();
+    let event_job_id = job_id.clone();
+
+    let progress = Progress::new(move |progress| {
+        let _ = app.emit_all(
+            "job_progress",
+            JobProgressEvent {
+                job_id: event_job_id.clone(),
+                progress,
+            },
+        );
+    });
+
+    if let Ok(mut jobs) = running_jobs().lock() {
+        jobs.insert(job_id, progress.clone());
+    }
+
+    progress
+}
+
+fn finish_job(job_id: &Option<String>) {
+    if let (Some(job_id), Ok(mut jobs)) = (job_id, running_jobs().lock()) {
+        jobs.remove(job_id);
+    }
+}
+
+#[tauri::command]
+fn cancel_job(job_id: &str) -> bool {
+    match running_jobs().lock() {
+        Ok(jobs) => match jobs.get(job_id) {
+            Some(progress) => {
+                progress.cancel();
+                true
+            }
+            None => false,
+        },
+        Err(_) => false,
+    }
+}
+
 #[tauri::command]
 async fn create_backup_from_id(
     app: tauri::AppHandle,
@@ -31,30 +105,50 @@ async fn create_backup_from_id(
     category: Option<String>,
     instance: Option<String>,
     vaults: Option<Vec<String>>,
-) -> String {
+    incremental: Option<bool>,
+    in_use: Option<InUsePolicy>,
+    job_id: Option<String>,
+) -> Result<String, Error> {
     let world_id_clone = world_id.clone();
 
+    let progress = start_job(&app, &job_id);
+
     let result = teller::handlers::backup::create_backup_from_id(
         &world_id,
         category.as_deref(),
         instance.as_deref(),
         vaults,
+        incremental.unwrap_or(false),
+        in_use,
+        &progress,
     )
     .await;
 
+    finish_job(&job_id);
+
     match result {
-        Ok(path) => {
+        Ok(message) => {
             let _ = app.emit_all("world_backup_list_updated", &world_id_clone);
 
             let _ = app.emit_all("backup_list_updated", ());
 
+            // Names the vaults the backup did not reach, if there were any.
+            let _ = app.emit_all(
+                "toast",
+                ToastEvent {
+                    message: message.clone(),
+                },
+            );
+            Ok(message)
+        }
+        Err(e) if progress.is_cancelled() => {
             let _ = app.emit_all(
                 "toast",
                 ToastEvent {
-                    message: "Backup created successfully".to_string(),
+                    message: "Backup cancelled".to_string(),
                 },
             );
-            path
+            Err(e)
         }
         Err(e) => {
             let _ = app.emit_all(
@@ -63,13 +157,13 @@ async fn create_backup_from_id(
                     message: format!("Error creating backup: {}", e),
                 },
             );
-            e
+            Err(e)
         }
     }
 }
 
 #[tauri::command]
-async fn grab_local_backup_list(vault: &str) -> Result<Vec<WorldData>, String> {
+async fn grab_local_backup_list(vault: &str) -> Result<Vec<WorldData>, Error> {
     teller::handlers::search::backups::grab_local_backup_list(vault).await
 }
 
@@ -77,7 +171,7 @@ async fn grab_local_backup_list(vault: &str) -> Result<Vec<WorldData>, String> {
 async fn grab_world_metadata(
     world_id: &str,
     selected_vault: Option<&str>,
-) -> Result<BackupMetadata, String> {
+) -> Result<BackupMetadata, Error> {
     teller::handlers::search::backups::get_world_metadata_from_id(world_id, selected_vault).await
 }
 
@@ -85,7 +179,7 @@ async fn grab_world_metadata(
 async fn grab_world_backups(
     world_id: &str,
     selected_vault: Option<&str>,
-) -> Result<Vec<SnapshotInfo>, String> {
+) -> Result<Vec<SnapshotInfo>, Error> {
     teller::handlers::search::backups::grab_world_backups(world_id, selected_vault).await
 }
 
@@ -94,7 +188,7 @@ async fn grab_backup_metadata(
     world_id: &str,
     selected_vault: Option<&str>,
     backup_id: &str,
-) -> Result<BackupMetadata, String> {
+) -> Result<BackupMetadata, Error> {
     teller::handlers::search::backups::grab_backup_metadata(world_id, selected_vault, backup_id)
         .await
 }
@@ -104,15 +198,29 @@ async fn delete_backup_from_id(
     world_id: &str,
     selected_vault: Option<&str>,
     backup_id: &str,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     teller::handlers::backup::delete_backup(world_id, selected_vault, backup_id).await
 }
 
 #[tauri::command]
-async fn delete_world_backups(world_id: &str, selected_vault: Option<&str>) -> Result<(), String> {
+async fn delete_world_backups(world_id: &str, selected_vault: Option<&str>) -> Result<(), Error> {
     teller::handlers::backup::delete_world_backups(world_id, selected_vault).await
 }
 
+#[tauri::command]
+async fn verify_backup(
+    world_id: &str,
+    selected_vault: Option<&str>,
+    backup_id: &str,
+) -> Result<VerifyReport, Error> {
+    teller::handlers::backup::verify_backup(world_id, selected_vault, backup_id).await
+}
+
+#[tauri::command]
+async fn verify_vault(vault: &str) -> Result<VaultVerifyReport, Error> {
+    teller::handlers::backup::verify_vault(vault).await
+}
+
 #[tauri::command]
 async fn restore_snapshot_to_world(
     app: tauri::AppHandle,
@@ -121,16 +229,23 @@ async fn restore_snapshot_to_world(
     world_id: &str,
     replace: bool,
     instances: Vec<String>,
-) -> Result<(), String> {
-    match teller::handlers::snapshot::snapshot_to_world(
+    job_id: Option<String>,
+) -> Result<(), Error> {
+    let progress = start_job(&app, &job_id);
+
+    let result = teller::handlers::snapshot::snapshot_to_world(
         snapshot_id,
         selected_vault,
         world_id,
         replace,
         instances,
+        &progress,
     )
-    .await
-    {
+    .await;
+
+    finish_job(&job_id);
+
+    match result {
         Ok(_) => {
             let _ = app.emit_all(
                 "toast",
@@ -141,6 +256,15 @@ async fn restore_snapshot_to_world(
 
             Ok(())
         }
+        Err(e) if progress.is_cancelled() => {
+            let _ = app.emit_all(
+                "toast",
+                ToastEvent {
+                    message: "Restore cancelled".to_string(),
+                },
+            );
+            Err(e)
+        }
         Err(e) => {
             let _ = app.emit_all(
                 "error",
@@ -152,3 +276,104 @@ async fn restore_snapshot_to_world(
         }
     }
 }
+
+#[tauri::command]
+async fn prune_vault(
+    app: tauri::AppHandle,
+    vault: &str,
+    dry_run: Option<bool>,
+) -> Result<PruneReport, Error> {
+    let report = teller::handlers::retention::prune_vault(vault, dry_run.unwrap_or(false)).await?;
+
+    if !report.dry_run && !report.deleted.is_empty() {
+        let _ = app.emit_all("backup_list_updated", ());
+    }
+
+    Ok(report)
+}
+
+/// Rebuilds the catalog of a vault from its contents.
+#[tauri::command]
+async fn reindex_vault(app: tauri::AppHandle, vault: &str) -> Result<VaultCatalog, Error> {
+    let catalog = teller::handlers::catalog::reindex_vault(vault).await?;
+
+    let _ = app.emit_all("backup_list_updated", ());
+
+    Ok(catalog)
+}
+
+#[tauri::command]
+async fn sync_vaults(
+    app: tauri::AppHandle,
+    source: &str,
+    destination: &str,
+    worlds: Option<Vec<String>>,
+    mirror: Option<bool>,
+    job_id: Option<String>,
+) -> Result<SyncReport, Error> {
+    let progress = start_job(&app, &job_id);
+
+    let result = teller::handlers::sync::sync_vaults(
+        source,
+        destination,
+        worlds.as_deref(),
+        mirror.unwrap_or(false),
+        &progress,
+    )
+    .await;
+
+    finish_job(&job_id);
+
+    let report = result?;
+
+    if !report.copied.is_empty() || !report.deleted.is_empty() {
+        let _ = app.emit_all("backup_list_updated", ());
+    }
+
+    Ok(report)
+}
+
+/// Queues a sync, which the job queue runs in the background and retries.
+#[tauri::command]
+async fn queue_vault_sync(
+    app: tauri::AppHandle,
+    source: String,
+    destination: String,
+    worlds: Option<Vec<String>>,
+    mirror: Option<bool>,
+) -> Result<QueuedJob, Error> {
+    let job = teller::handlers::queue::enqueue(QueuedTask::Replicate {
+        source,
+        destination,
+        worlds,
+        mirror: mirror.unwrap_or(false),
+    })
+    .await?;
+
+    let _ = app.emit_all("job_queue_updated", ());
+
+    Ok(job)
+}
+
+#[tauri::command]
+async fn list_queued_jobs() -> Vec<QueuedJob> {
+    teller::handlers::queue::list_jobs().await
+}
+
+#[tauri::command]
+async fn retry_queued_job(app: tauri::AppHandle, job_id: &str) -> Result<(), Error> {
+    teller::handlers::queue::retry_job(job_id).await?;
+
+    let _ = app.emit_all("job_queue_updated", ());
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn cancel_queued_job(app: tauri::AppHandle, job_id: &str) -> Result<(), Error> {
+    teller::handlers::queue::cancel_job(job_id).await?;
+
+    let _ = app.emit_all("job_queue_updated", ());
+
+    Ok(())
+}
@@ -10,6 +10,7 @@ use teller::{
         fetch_worlds_from_instance, get_world_path_by_id, recursive_world_search,
     },
     types::world::WorldData,
+    Error,
 };
 
 pub fn init() -> TauriPlugin<Wry> {
@@ -24,7 +25,7 @@ pub fn init() -> TauriPlugin<Wry> {
 }
 
 #[tauri::command]
-async fn check_path_for_save_folders(path: PathBuf) -> Result<Vec<PathBuf>, String> {
+async fn check_path_for_save_folders(path: PathBuf) -> Result<Vec<PathBuf>, Error> {
     info!("Checking path for saves folder: {}", path.to_string_lossy());
 
     let mut save_folders = Vec::new();
@@ -39,7 +40,7 @@ async fn check_path_for_save_folders(path: PathBuf) -> Result<Vec<PathBuf>, Stri
 }
 
 #[tauri::command]
-async fn grab_local_worlds_list(category: &str, instance: &str) -> Result<Vec<WorldData>, String> {
+async fn grab_local_worlds_list(category: &str, instance: &str) -> Result<Vec<WorldData>, Error> {
     fetch_worlds_from_instance(category, instance).await
 }
 
@@ -49,21 +50,21 @@ async fn open_world_in_explorer(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     let path = get_world_path_by_id(world_id, category, instance).await?;
 
     if path.is_dir() {
-        match tauri::api::shell::open(&handle.shell_scope(), path.to_string_lossy(), None)
-            .map_err(|e| e.to_string())
-        {
+        match tauri::api::shell::open(&handle.shell_scope(), path.to_string_lossy(), None) {
             Ok(_) => Ok(()),
             Err(e) => {
                 error!("Could not open path: {}", e);
-                Err(e.to_string())
+                Err(Error::World(format!("Could not open path: {}", e)))
             }
         }
     } else {
-        Err("Path is not a valid directory".to_string())
+        Err(Error::WorldNotFound(
+            "Path is not a valid directory".to_string(),
+        ))
     }
 }
 
@@ -1,3 +1,4 @@
 pub mod backup_handler;
 pub mod folder_handler;
+pub mod scheduler;
 pub mod world_handler;
@@ -0,0 +1,73 @@
+use tauri::{AppHandle, Manager};
+use teller::types::queue::QueueEvent;
+use teller::types::scheduler::SchedulerEvent;
+
+use crate::types::events::ToastEvent;
+
+fn emit_scheduler_event(app: &AppHandle, event: SchedulerEvent) {
+    let name = match &event {
+        SchedulerEvent::BackupStarted { .. } => "scheduled_backup_started",
+        SchedulerEvent::BackupFinished { world_id, .. } => {
+            let _ = app.emit_all("world_backup_list_updated", world_id);
+            let _ = app.emit_all("backup_list_updated", ());
+            "scheduled_backup_finished"
+        }
+    };
+
+    let _ = app.emit_all(name, &event);
+}
+
+fn emit_queue_event(app: &AppHandle, event: QueueEvent) {
+    if let QueueEvent::JobFinished { error: None, .. } = &event {
+        let _ = app.emit_all("backup_list_updated", ());
+    }
+
+    let _ = app.emit_all("job_queue_updated", &event);
+}
+
+/// Cleans up after backups a crash interrupted, so the scheduler never picks
+/// a half written snapshot as the parent of a new one.
+async fn recover_vaults(app: &AppHandle) {
+    let report = teller::handlers::recovery::recover_vaults().await;
+
+    if !report.snapshots.is_empty() {
+        let _ = app.emit_all("backup_list_updated", ());
+        let _ = app.emit_all(
+            "toast",
+            ToastEvent {
+                message: format!(
+                    "Removed {} incomplete backups left by an interrupted backup",
+                    report.snapshots.len()
+                ),
+            },
+        );
+    }
+}
+
+/// Recovers the vaults, then starts the backup scheduler, the job queue and
+/// the world watcher in the background and forwards their events to the
+/// frontend.
+pub fn start(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        recover_vaults(&app).await;
+
+        let scheduler_app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            teller::handlers::scheduler::run_scheduler(move |event| {
+                emit_scheduler_event(&scheduler_app, event)
+            })
+            .await;
+        });
+
+        let queue_app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            teller::handlers::queue::run_queue(move |event| emit_queue_event(&queue_app, event))
+                .await;
+        });
+
+        teller::handlers::scheduler::watcher::run_watcher(move |event| {
+            emit_scheduler_event(&app, event)
+        })
+        .await;
+    });
+}
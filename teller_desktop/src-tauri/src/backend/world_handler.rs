@@ -7,6 +7,7 @@ use serde_json::Value;
 use teller::{
     handlers::player::grab_player_from_uuid,
     types::{player::PlayerData, world::WorldLevelData},
+    Error,
 };
 
 use tauri::{
@@ -32,7 +33,7 @@ pub async fn get_world_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<WorldLevelData, String> {
+) -> Result<WorldLevelData, Error> {
     teller::handlers::search::worlds::get_world_by_id(world_id, category, instance).await
 }
 
@@ -41,28 +42,25 @@ async fn get_world_path_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<PathBuf, String> {
+) -> Result<PathBuf, Error> {
     teller::handlers::search::worlds::get_world_path_by_id(world_id, category, instance).await
 }
 
 #[tauri::command]
 async fn get_player_meta_from_uuids(
     player_data_list: Vec<PlayerData>,
-) -> Result<HashMap<String, Value>, String> {
+) -> Result<HashMap<String, Value>, Error> {
     teller::handlers::player::get_players_meta_from_uuids(player_data_list).await
 }
 
 #[tauri::command]
-async fn get_player_meta_from_uuid(player_uuid: String) -> Result<Value, String> {
+async fn get_player_meta_from_uuid(player_uuid: String) -> Result<Value, Error> {
     teller::handlers::player::get_player_meta_from_uuid(reqwest::Client::new(), player_uuid).await
 }
 
 #[tauri::command]
-fn get_player_from_uuid(player_uuid: String, path: &Path) -> Result<PlayerData, String> {
-    match grab_player_from_uuid(player_uuid, &path.to_path_buf()) {
-        Ok(player) => Ok(player),
-        Err(e) => Err(e.to_string()),
-    }
+fn get_player_from_uuid(player_uuid: String, path: &Path) -> Result<PlayerData, Error> {
+    grab_player_from_uuid(player_uuid, &path.to_path_buf())
 }
 
 #[tauri::command]
@@ -70,6 +68,6 @@ async fn delete_world_by_id(
     world_id: &str,
     category: Option<&str>,
     instance: Option<&str>,
-) -> Result<(), String> {
+) -> Result<(), Error> {
     teller::handlers::world::delete_world_by_id(world_id, category, instance).await
 }
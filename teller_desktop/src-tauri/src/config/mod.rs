@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use log::error;
 use teller::{
@@ -12,7 +12,11 @@ use teller::{
         },
         search::directories::get_directory_by_name,
     },
-    types::{backup::BackupSettings, config::DirectorySettings},
+    types::{
+        backup::{BackupProfile, BackupSettings},
+        config::DirectorySettings,
+    },
+    Error,
 };
 
 use tauri::{
@@ -30,13 +34,21 @@ pub fn init() -> TauriPlugin<Wry> {
             update_saves_config,
             get_minecraft_save_location,
             get_backup_settings,
-            update_backup_settings
+            update_backup_settings,
+            enable_vault_encryption,
+            unlock_vault,
+            lock_vault,
+            is_vault_unlocked,
+            get_backup_profiles,
+            get_backup_profile,
+            update_backup_profile,
+            remove_backup_profile
         ])
         .build()
 }
 
 #[tauri::command]
-fn get_save_folders(handle: tauri::AppHandle) -> Result<DirectorySettings, String> {
+fn get_save_folders(handle: tauri::AppHandle) -> Result<DirectorySettings, Error> {
     let config_dir = get_config_folder();
 
     // This simply opens the window and errors out allowing the user to configure the directories
@@ -51,8 +63,8 @@ fn get_save_folders(handle: tauri::AppHandle) -> Result<DirectorySettings, Strin
             .build()
             .unwrap();
 
-            error!("Could not get saves config: {:?}", e);
-            return Err(format!("Could not get saves config: {:?}", e));
+            error!("Could not get saves config: {}", e);
+            return Err(e);
         }
     };
 
@@ -60,13 +72,13 @@ fn get_save_folders(handle: tauri::AppHandle) -> Result<DirectorySettings, Strin
 }
 
 #[tauri::command]
-fn load_saves_folders() -> Result<DirectorySettings, String> {
+fn load_saves_folders() -> Result<DirectorySettings, Error> {
     let config_dir = get_config_folder();
 
     let saves_config = match get_local_directories_config(config_dir) {
         Ok(s) => s,
         Err(e) => {
-            return Err(format!("Could not get saves config: {:?}", e));
+            return Err(e);
         }
     };
 
@@ -79,16 +91,12 @@ fn get_folder_path(dir_name: &str, category: Option<&str>) -> Option<PathBuf> {
 }
 
 #[tauri::command]
-async fn create_saves_config(
-    settings_data: DirectorySettings,
-) -> Result<DirectorySettings, String> {
+async fn create_saves_config(settings_data: DirectorySettings) -> Result<DirectorySettings, Error> {
     create_local_directories_config(settings_data).await
 }
 
 #[tauri::command]
-async fn update_saves_config(
-    settings_data: DirectorySettings,
-) -> Result<DirectorySettings, String> {
+async fn update_saves_config(settings_data: DirectorySettings) -> Result<DirectorySettings, Error> {
     update_local_directories_config(settings_data).await
 }
 
@@ -98,11 +106,64 @@ pub fn get_minecraft_save_location() -> Option<PathBuf> {
 }
 
 #[tauri::command]
-async fn get_backup_settings() -> Result<BackupSettings, String> {
+async fn get_backup_settings() -> Result<BackupSettings, Error> {
     teller::handlers::config::backup::get_backup_config().await
 }
 
 #[tauri::command]
-async fn update_backup_settings(settings_data: BackupSettings) -> Result<BackupSettings, String> {
+async fn update_backup_settings(settings_data: BackupSettings) -> Result<BackupSettings, Error> {
     teller::handlers::config::backup::update_backup_config(settings_data).await
 }
+
+#[tauri::command]
+async fn enable_vault_encryption(
+    vault: &str,
+    passphrase: &str,
+    plaintext_metadata: bool,
+) -> Result<BackupSettings, Error> {
+    teller::handlers::encryption::enable_vault_encryption(vault, passphrase, plaintext_metadata)
+        .await
+}
+
+#[tauri::command]
+async fn unlock_vault(vault: &str, passphrase: &str) -> Result<(), Error> {
+    teller::handlers::encryption::unlock_vault(vault, passphrase).await
+}
+
+#[tauri::command]
+async fn lock_vault(vault: &str) -> Result<(), Error> {
+    teller::handlers::encryption::lock_vault(vault).await
+}
+
+#[tauri::command]
+async fn is_vault_unlocked(vault: &str) -> Result<bool, Error> {
+    teller::handlers::encryption::is_vault_unlocked(vault).await
+}
+
+#[tauri::command]
+async fn get_backup_profiles() -> Result<HashMap<String, BackupProfile>, Error> {
+    Ok(teller::handlers::config::backup::get_backup_config()
+        .await?
+        .profiles)
+}
+
+#[tauri::command]
+async fn get_backup_profile(world_id: &str) -> Result<Option<BackupProfile>, Error> {
+    Ok(teller::handlers::config::backup::get_backup_config()
+        .await?
+        .profiles
+        .remove(world_id))
+}
+
+#[tauri::command]
+async fn update_backup_profile(
+    world_id: &str,
+    profile: BackupProfile,
+) -> Result<BackupSettings, Error> {
+    teller::handlers::config::backup::update_backup_profile(world_id, profile).await
+}
+
+#[tauri::command]
+async fn remove_backup_profile(world_id: &str) -> Result<BackupSettings, Error> {
+    teller::handlers::config::backup::remove_backup_profile(world_id).await
+}
@@ -35,6 +35,10 @@ fn main() {
         .plugin(teller_desktop::backend::backup_handler::init())
         .plugin(teller_desktop::backend::folder_handler::init())
         .plugin(teller_desktop::backend::world_handler::init())
+        .setup(|app| {
+            teller_desktop::backend::scheduler::start(app.handle());
+            Ok(())
+        })
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
@@ -1,3 +1,5 @@
+use teller::types::progress::ProgressEvent;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct BackupEvent {
     pub world_id: String,
@@ -5,6 +7,12 @@ pub struct BackupEvent {
     pub vaults: Option<Vec<String>>,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JobProgressEvent {
+    pub job_id: String,
+    pub progress: ProgressEvent,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ToastEvent {
     pub message: String,
@@ -0,0 +1,40 @@
+import { writable } from 'svelte/store';
+import { invoke } from '@tauri-apps/api';
+import type { ProgressEvent } from '$lib/types/events';
+
+export interface Job {
+	id: string;
+	label: string;
+	progress: ProgressEvent | null;
+	cancelling: boolean;
+}
+
+export const jobs = writable<Record<string, Job>>({});
+
+export function startJob(label: string): string {
+	const id = crypto.randomUUID();
+	jobs.update((current) => ({ ...current, [id]: { id, label, progress: null, cancelling: false } }));
+	return id;
+}
+
+export function updateJob(id: string, progress: ProgressEvent) {
+	jobs.update((current) => {
+		if (!current[id]) return current;
+		return { ...current, [id]: { ...current[id], progress } };
+	});
+}
+
+export function finishJob(id: string) {
+	jobs.update((current) => {
+		const { [id]: _, ...rest } = current;
+		return rest;
+	});
+}
+
+export async function cancelJob(id: string) {
+	jobs.update((current) => {
+		if (!current[id]) return current;
+		return { ...current, [id]: { ...current[id], cancelling: true } };
+	});
+	await invoke('plugin:backup_handler|cancel_job', { jobId: id });
+}
@@ -10,7 +10,19 @@ export let backupSettings = writable<BackupSettings>({
 	enable_remote_backup: false,
 	default_vaults: [],
 	vaults: {},
-	remote_vaults: {}
+	remote_vaults: {},
+	s3_vaults: {},
+	sftp_vaults: {},
+	webdav_vaults: {},
+	scheduled_worlds: [],
+	catch_up: 'run_once',
+	profiles: {},
+	watcher: { enabled: false, quiet_period: 300 },
+	in_use: 'best_effort',
+	bandwidth: {
+		upload_limit: null,
+		upload_limit_while_playing: null
+	}
 });
 
 export let localVaults = writable<Vault>({});
@@ -5,6 +5,70 @@ export interface RemoteBackup {
 	api_key: string;
 }
 
+export interface S3Backup {
+	bucket: string;
+	prefix: string;
+	region: string;
+	endpoint: string | null;
+	path_style: boolean;
+	access_key_id: string;
+	secret_access_key: string;
+}
+
+export interface SftpBackup {
+	host: string;
+	port: number;
+	user: string;
+	key_path: string;
+	remote_dir: string;
+	known_hosts: string | null;
+}
+
+export interface BandwidthSettings {
+	upload_limit: number | null;
+	upload_limit_while_playing: number | null;
+}
+
+export interface WebdavBackup {
+	url: string;
+	username: string | null;
+	password: string | null;
+}
+
+export interface ScheduledWorld {
+	world_id: string;
+	category: string | null;
+	instance: string | null;
+}
+
+export type CatchUpPolicy = 'run_once' | 'skip';
+
+export interface RetentionPolicy {
+	keep_last?: number | null;
+	keep_hourly?: number | null;
+	keep_daily?: number | null;
+	keep_weekly?: number | null;
+	keep_monthly?: number | null;
+	keep_yearly?: number | null;
+	max_world_size?: number | null;
+}
+
+export type InUsePolicy = 'refuse' | 'wait' | 'best_effort';
+
+export interface WatcherSettings {
+	enabled: boolean;
+	quiet_period: number;
+}
+
+export interface BackupProfile {
+	enabled: boolean;
+	schedule: string;
+	vaults: string[] | null;
+	retention: RetentionPolicy | null;
+	category: string | null;
+	instance: string | null;
+}
+
 export interface BackupSettings {
 	schedule: string;
 	auto_backup: boolean;
@@ -12,11 +76,21 @@ export interface BackupSettings {
 	default_vaults: string[] | null;
 	vaults: Record<string, string>;
 	remote_vaults: Record<string, RemoteBackup>;
+	s3_vaults: Record<string, S3Backup>;
+	sftp_vaults: Record<string, SftpBackup>;
+	webdav_vaults: Record<string, WebdavBackup>;
+	scheduled_worlds: ScheduledWorld[];
+	catch_up: CatchUpPolicy;
+	profiles: Record<string, BackupProfile>;
+	watcher: WatcherSettings;
+	in_use: InUsePolicy;
+	bandwidth: BandwidthSettings;
 }
 
 export interface BackupMetadata {
 	entry: WorldItem;
 	data: WorldLevelData;
+	best_effort: boolean;
 }
 
 export interface SnapshotInfo {
@@ -25,6 +99,25 @@ export interface SnapshotInfo {
 	path: string;
 }
 
+export interface CatalogSnapshot {
+	snapshot_id: string;
+	created: number;
+	size: number;
+	parent: string | null;
+	best_effort: boolean;
+}
+
+export interface CatalogWorld {
+	entry: WorldItem | null;
+	snapshots: CatalogSnapshot[];
+}
+
+export interface VaultCatalog {
+	vault_id: string;
+	indexed: number;
+	worlds: Record<string, CatalogWorld>;
+}
+
 export interface Vault {
 	[key: string]: {
 		path: string;
@@ -0,0 +1,21 @@
+export type TellerErrorCode =
+	| 'config'
+	| 'vault_not_found'
+	| 'vault_locked'
+	| 'vault'
+	| 'snapshot_not_found'
+	| 'snapshot_format'
+	| 'encryption'
+	| 'nbt'
+	| 'world_not_found'
+	| 'world_in_use'
+	| 'world'
+	| 'io'
+	| 'network'
+	| 'job_not_found'
+	| 'cancelled';
+
+export interface TellerError {
+	code: TellerErrorCode;
+	message: string;
+}
@@ -1,3 +1,16 @@
 export interface ToastEvent {
 	message: string;
 }
+
+export type ProgressStage = 'measuring' | 'archiving' | 'copying' | 'extracting';
+
+export interface ProgressEvent {
+	stage: ProgressStage;
+	done: number;
+	total: number;
+}
+
+export interface JobProgressEvent {
+	job_id: string;
+	progress: ProgressEvent;
+}
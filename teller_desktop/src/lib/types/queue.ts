@@ -0,0 +1,36 @@
+export type QueuedTask =
+	| {
+			kind: 'upload';
+			world_id: string;
+			snapshot_id: string;
+			vault_id: string;
+			source: string;
+	  }
+	| {
+			kind: 'replicate';
+			source: string;
+			destination: string;
+			worlds: string[] | null;
+			mirror: boolean;
+	  }
+	| {
+			kind: 'prune';
+			vault_id: string;
+			world_id: string | null;
+	  };
+
+export type JobStatus = 'queued' | 'active' | 'failed';
+
+export interface QueuedJob {
+	id: string;
+	task: QueuedTask;
+	status: JobStatus;
+	attempts: number;
+	created: number;
+	next_attempt: number;
+	last_error: string | null;
+}
+
+export type QueueEvent =
+	| { kind: 'job_started'; job_id: string }
+	| { kind: 'job_finished'; job_id: string; error: string | null };
@@ -1,3 +1,5 @@
+import type { TellerError } from './types/errors';
+
 export function formatBytes(bytes: number): string {
 	if (bytes < 0) {
 		return '0 bytes';
@@ -41,3 +43,11 @@ export function parseDifficulty(difficulty: number) {
 			return 'Unknown';
 	}
 }
+
+export function isTellerError(error: unknown): error is TellerError {
+	return typeof error === 'object' && error !== null && 'code' in error && 'message' in error;
+}
+
+export function errorMessage(error: unknown): string {
+	return isTellerError(error) ? error.message : `${error}`;
+}